@@ -720,6 +720,15 @@ impl Debugger {
                     self.run_hooks(psx);
                 }
             }
+            CpuState::UnimplementedHardware { component, detail } => {
+                println!(
+                    "Unimplemented hardware [{}]: {} at {:08x}",
+                    component,
+                    detail,
+                    psx.cpu().registers().read(RegisterType::Pc)
+                );
+                self.set_enabled(true);
+            }
         }
     }
 }
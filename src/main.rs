@@ -2,22 +2,31 @@
 mod debugger;
 
 use std::{
-    path::PathBuf,
+    fs,
+    path::{Path, PathBuf},
     sync::Arc,
     time::{Duration, Instant},
 };
 
 use dynwave::{AudioPlayer, BufferSize};
-use trapezoid_core::{DigitalControllerKey, Psx, PsxConfig};
+use trapezoid_core::{
+    bios_region, disc_region, open_disc_image, read_m3u_playlist,
+    renderer::{SoftwareImage, SoftwareRenderer, VulkanRenderer},
+    AudioResampler, DigitalControllerKey, PbpImage, Psx, PsxConfig, Region,
+};
 
 use clap::Parser;
 use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, BlitImageInfo,
+        CommandBufferUsage,
+    },
     device::{
         physical::PhysicalDeviceType, Device, DeviceCreateInfo, DeviceExtensions, Queue,
         QueueCreateInfo, QueueFlags,
     },
-    image::{Image, ImageUsage},
-    instance::{Instance, InstanceCreateFlags, InstanceCreateInfo, InstanceExtensions},
+    image::{sampler::Filter, Image, ImageUsage},
+    instance::{Instance, InstanceCreateFlags, InstanceCreateInfo},
     swapchain::{
         self, CompositeAlpha, PresentMode, Surface, Swapchain, SwapchainCreateInfo,
         SwapchainPresentInfo,
@@ -50,7 +59,121 @@ impl Debugger {
 
     fn run(&mut self, _psx: &mut Psx) {}
 
-    fn handle_cpu_state(&mut self, _psx: &mut Psx, _cpu_state: trapezoid_core::cpu::CpuState) {}
+    fn handle_cpu_state(&mut self, _psx: &mut Psx, cpu_state: trapezoid_core::cpu::CpuState) {
+        // without the debugger, we can't pause, so just continue
+        if let trapezoid_core::cpu::CpuState::UnimplementedHardware { component, detail } =
+            cpu_state
+        {
+            eprintln!("Unimplemented hardware [{}]: {}", component, detail);
+        }
+    }
+}
+
+/// The discs of a multi-disc game
+enum DiscList {
+    Playlist(Vec<PathBuf>),
+    /// A `.pbp` archive and the number of discs in it
+    Pbp(PathBuf, usize),
+}
+
+/// Cycles through the discs of the game, if it has more than one
+struct DiscSwapper {
+    discs: DiscList,
+    current: usize,
+}
+
+impl DiscSwapper {
+    fn new(disk_file: &Path) -> Option<Self> {
+        let extension = disk_file
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let discs = match extension.as_deref() {
+            Some("m3u") => DiscList::Playlist(read_m3u_playlist(disk_file).ok()?),
+            Some("pbp") => DiscList::Pbp(
+                disk_file.to_owned(),
+                PbpImage::open_discs(disk_file).ok()?.len(),
+            ),
+            _ => return None,
+        };
+
+        Some(Self { discs, current: 0 })
+    }
+
+    fn len(&self) -> usize {
+        match &self.discs {
+            DiscList::Playlist(paths) => paths.len(),
+            DiscList::Pbp(_, count) => *count,
+        }
+    }
+
+    /// Inserts the next or previous disc
+    fn swap(&mut self, psx: &mut Psx, forward: bool) {
+        let len = self.len();
+        if len < 2 {
+            println!("There is no other disc to insert");
+            return;
+        }
+        self.current = if forward {
+            (self.current + 1) % len
+        } else {
+            (self.current + len - 1) % len
+        };
+
+        let result = match &self.discs {
+            DiscList::Playlist(paths) => psx.insert_disc(&paths[self.current]),
+            DiscList::Pbp(path, _) => PbpImage::open_discs(path).map(|discs| {
+                let disc = discs.into_iter().nth(self.current).unwrap();
+                psx.insert_disc_image(Box::new(disc));
+            }),
+        };
+        match result {
+            Ok(()) => println!("Inserted disc {}/{}", self.current + 1, len),
+            Err(e) => log::error!("Failed to insert disc: {}", e),
+        }
+    }
+}
+
+/// If `bios` is a directory, picks the BIOS matching the region of the disc in it,
+/// or the first BIOS if the region is unknown
+fn select_bios(bios: &Path, disk_file: Option<&Path>) -> PathBuf {
+    if !bios.is_dir() {
+        return bios.to_owned();
+    }
+
+    let region = disk_file
+        .and_then(|path| open_disc_image(path).ok())
+        .and_then(|mut disc| disc_region(disc.as_mut()));
+
+    let mut files: Vec<PathBuf> = fs::read_dir(bios)
+        .map(|entries| entries.filter_map(|e| e.ok().map(|e| e.path())).collect())
+        .unwrap_or_default();
+    files.sort();
+    let bioses: Vec<(PathBuf, Option<Region>)> = files
+        .into_iter()
+        .filter_map(|path| {
+            let bios_region = bios_region(&path).ok()?;
+            Some((path, bios_region))
+        })
+        .collect();
+
+    let selected = bioses
+        .iter()
+        .find(|(_, bios_region)| region.is_some() && *bios_region == region)
+        .or(bioses.first());
+    match selected {
+        Some((path, bios_region)) => {
+            println!(
+                "Using BIOS {} ({:?}) for a {:?} disc",
+                path.display(),
+                bios_region,
+                region
+            );
+            path.clone()
+        }
+        // let the emulator report the error
+        None => bios.to_owned(),
+    }
 }
 
 struct MovingAverage {
@@ -134,8 +257,49 @@ impl Fps {
     }
 }
 
+/// Dynamic rate control, keeps the audio buffer of the host at `target` samples by
+/// stretching the audio of the emulator a bit, the emulation is paced by the display.
+///
+/// `dynwave` doesn't tell us how much is buffered, so it's estimated from the samples
+/// queued and the time passed since the start of the playback.
+struct AudioSync {
+    sample_rate: f64,
+    start: Instant,
+    /// Stereo samples queued since `start`
+    queued: f64,
+    target: f64,
+}
+
+impl AudioSync {
+    /// Correction for each sample of distance from the target, relative to the target
+    const RATE_ADJUST_GAIN: f64 = 0.02;
+
+    fn new(sample_rate: u32, target: Duration) -> Self {
+        Self {
+            sample_rate: sample_rate as f64,
+            start: Instant::now(),
+            queued: 0.0,
+            target: target.as_secs_f64() * sample_rate as f64,
+        }
+    }
+
+    /// Records `samples` stereo samples queued, and returns the rate adjustment for the next ones
+    fn queue(&mut self, samples: usize) -> f64 {
+        let played = self.start.elapsed().as_secs_f64() * self.sample_rate;
+        // the buffer ran dry, the host played silence instead, or it overflowed
+        // and the samples were dropped
+        self.queued = self.queued.clamp(played, played + self.target * 2.0) + samples as f64;
+
+        let buffered = self.queued - played;
+        1.0 + (self.target - buffered) / self.target * Self::RATE_ADJUST_GAIN
+    }
+}
+
 enum DisplayType {
     Windowed {
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        command_buffer_allocator: StandardCommandBufferAllocator,
         event_loop: Option<EventLoop<()>>,
         window: Arc<Window>,
         surface: Arc<Surface>,
@@ -144,16 +308,19 @@ enum DisplayType {
         future: Option<Box<dyn GpuFuture>>,
         full_vram_display: bool,
     },
+    /// No graphics API is used at all, the video is rendered in software
+    /// and never displayed.
     Headless,
 }
 
-// Locked FPS for audio (more important than video)
-// 60 FPS result in popping sound because of emulation speed of the SPU
-const FPS: f64 = 59.5;
+// The emulation is paced by the display rate of the video standard, the
+// audio is stretched to follow it, see `AudioSync`
+const FPS: f64 = 60000.0 / 1001.0;
+const PAL_FPS: f64 = 50.0;
+/// The audio buffered by the host, half of `BufferSize::QuarterSecond`
+const AUDIO_LATENCY: Duration = Duration::from_millis(125);
 
 struct VkDisplay {
-    device: Arc<Device>,
-    queue: Arc<Queue>,
     display_type: DisplayType,
     fps: Fps,
     render_time_average: MovingAverage,
@@ -274,11 +441,15 @@ impl VkDisplay {
         };
 
         Self {
-            device: device.clone(),
-            queue,
             fps: Fps::new(FPS),
             render_time_average: MovingAverage::new(),
             display_type: DisplayType::Windowed {
+                command_buffer_allocator: StandardCommandBufferAllocator::new(
+                    device.clone(),
+                    Default::default(),
+                ),
+                device: device.clone(),
+                queue,
                 event_loop: Some(event_loop),
                 window,
                 surface,
@@ -291,68 +462,40 @@ impl VkDisplay {
     }
 
     fn headless() -> Self {
-        let vulkan_library = VulkanLibrary::new().unwrap();
-
-        let instance = Instance::new(
-            vulkan_library,
-            InstanceCreateInfo {
-                enabled_extensions: InstanceExtensions::empty(),
-                ..Default::default()
-            },
-        )
-        .unwrap();
-
-        let (physical_device, queue_family_index) = instance
-            .enumerate_physical_devices()
-            .unwrap()
-            .filter_map(|p| {
-                p.queue_family_properties()
-                    .iter()
-                    .position(|q| {
-                        q.queue_flags
-                            .contains(QueueFlags::GRAPHICS | QueueFlags::COMPUTE)
-                    })
-                    .map(|i| (p, i as u32))
-            })
-            .min_by_key(|(p, _)| match p.properties().device_type {
-                PhysicalDeviceType::DiscreteGpu => 0,
-                PhysicalDeviceType::IntegratedGpu => 1,
-                PhysicalDeviceType::VirtualGpu => 2,
-                PhysicalDeviceType::Cpu => 3,
-                PhysicalDeviceType::Other => 4,
-                _ => 5,
-            })
-            .unwrap();
-
-        println!(
-            "Using device: {} (type: {:?})",
-            physical_device.properties().device_name,
-            physical_device.properties().device_type,
-        );
-
-        let (device, mut queues) = Device::new(
-            physical_device,
-            DeviceCreateInfo {
-                queue_create_infos: vec![QueueCreateInfo {
-                    queue_family_index,
-                    ..Default::default()
-                }],
-                ..Default::default()
-            },
-        )
-        .unwrap();
-
-        let queue = queues.next().unwrap();
-
         Self {
-            device,
-            queue,
             fps: Fps::new(FPS),
             render_time_average: MovingAverage::new(),
             display_type: DisplayType::Headless,
         }
     }
 
+    /// Creates the emulator with the renderer suitable for this display
+    fn create_psx(&self, args: &PsxEmuArgs) -> Result<Psx, trapezoid_core::PsxError> {
+        let config = PsxConfig {
+            stdout_debug: args.debug,
+            fast_boot: args.fast_boot,
+            fast_disc_access: args.fast_disc,
+            audio_sample_rate: args.sample_rate,
+            audio_resampler: if args.sinc {
+                AudioResampler::Sinc
+            } else {
+                AudioResampler::Linear
+            },
+        };
+        let disk_file = args.disk_file.as_ref();
+
+        match &self.display_type {
+            DisplayType::Windowed { device, queue, .. } => {
+                let device = device.clone();
+                let queue = queue.clone();
+                Psx::new(&args.bios, disk_file, config, move || {
+                    VulkanRenderer::new(device, queue)
+                })
+            }
+            DisplayType::Headless => Psx::new(&args.bios, disk_file, config, SoftwareRenderer::new),
+        }
+    }
+
     fn window_resize(&mut self) {
         match &mut self.display_type {
             DisplayType::Windowed {
@@ -381,6 +524,8 @@ impl VkDisplay {
         let mut recreate_swapchain = false;
         match &mut self.display_type {
             DisplayType::Windowed {
+                queue,
+                command_buffer_allocator,
                 swapchain,
                 images,
                 full_vram_display,
@@ -415,17 +560,41 @@ impl VkDisplay {
                 }
 
                 let current_image = images[image_num as usize].clone();
-
-                let current_future = psx.blit_to_front(
-                    current_image,
-                    *full_vram_display,
-                    current_future.join(acquire_future).boxed(),
-                );
+                let current_future = current_future.join(acquire_future).boxed();
+
+                let current_future =
+                    if let Some(front_image) = psx.sync_front_image::<Image>(*full_vram_display) {
+                        let mut builder = AutoCommandBufferBuilder::primary(
+                            command_buffer_allocator,
+                            queue.queue_family_index(),
+                            CommandBufferUsage::OneTimeSubmit,
+                        )
+                        .unwrap();
+
+                        builder
+                            .blit_image(BlitImageInfo {
+                                filter: Filter::Nearest,
+                                ..BlitImageInfo::images(front_image, current_image)
+                            })
+                            .unwrap();
+                        let cb = builder.build().unwrap();
+
+                        // TODO: remove wait
+                        current_future
+                            .then_execute(queue.clone(), cb)
+                            .unwrap()
+                            .then_signal_fence_and_flush()
+                            .unwrap()
+                            .boxed()
+                    } else {
+                        // we must flush the future even if we are not using it.
+                        current_future
+                    };
 
                 *future = Some(
                     current_future
                         .then_swapchain_present(
-                            self.queue.clone(),
+                            queue.clone(),
                             SwapchainPresentInfo::swapchain_image_index(
                                 swapchain.clone(),
                                 image_num,
@@ -439,7 +608,11 @@ impl VkDisplay {
                 let elapsed = t.elapsed();
                 self.render_time_average.add(elapsed.as_micros() as f64);
             }
-            DisplayType::Headless => {}
+            DisplayType::Headless => {
+                // nothing is displayed, but wait for the renderer to catch up so
+                // the drawing commands don't pile up
+                let _ = psx.sync_front_image::<SoftwareImage>(false);
+            }
         }
 
         if recreate_swapchain {
@@ -510,11 +683,12 @@ impl VkDisplay {
 #[derive(Parser, Debug)]
 #[command(version, author, about = "PSX emulator")]
 struct PsxEmuArgs {
-    /// The bios file to run
+    /// The bios file to run, or a directory of bios files to pick the one
+    /// matching the region of the disc
     bios: PathBuf,
     /// The disk/exe file to run, without this, it will run the bios only
     disk_file: Option<PathBuf>,
-    /// Turn off window display and run in headless mode
+    /// Turn off window display and run in headless mode, without using the GPU
     #[arg(short = 'e', long)]
     headless: bool,
     /// Initial value for `display full vram`, can be changed later with [V] key
@@ -529,6 +703,24 @@ struct PsxEmuArgs {
     /// Skips the shell
     #[arg(short, long)]
     fast_boot: bool,
+    /// Skips most of the disc seek and spin-up delays, loads faster
+    /// but can break games that depend on them
+    #[arg(long)]
+    fast_disc: bool,
+    /// The sample rate of the audio output, the SPU audio is resampled from 44100Hz
+    #[arg(long, default_value_t = 48000, value_parser = clap::value_parser!(u32).range(1..))]
+    sample_rate: u32,
+    /// Resample the audio with a windowed sinc instead of linear interpolation,
+    /// cleaner but slower
+    #[arg(long)]
+    sinc: bool,
+    /// Record the audio into this WAV file, at 44100Hz
+    #[arg(long)]
+    record_audio: Option<PathBuf>,
+    /// With `--record-audio`, also record each voice and the CD audio
+    /// into their own files next to it
+    #[arg(long, requires = "record_audio")]
+    record_stems: bool,
 }
 
 fn main() {
@@ -537,37 +729,37 @@ fn main() {
         .filter_level(log::LevelFilter::Error)
         .init();
 
-    let args = PsxEmuArgs::parse();
+    let mut args = PsxEmuArgs::parse();
+    args.bios = select_bios(&args.bios, args.disk_file.as_deref());
 
-    let display = if args.headless {
+    let mut display = if args.headless {
         VkDisplay::headless()
     } else {
         VkDisplay::windowed(args.vram)
     };
 
-    let mut psx = Psx::new(
-        &args.bios,
-        args.disk_file,
-        PsxConfig {
-            stdout_debug: args.debug,
-            fast_boot: args.fast_boot,
-        },
-        display.device.clone(),
-        display.queue.clone(),
-    )
-    .unwrap();
+    let mut psx = display.create_psx(&args).unwrap();
+    if psx.disc_region().is_some_and(Region::is_pal) {
+        display.fps = Fps::new(PAL_FPS);
+    }
+    if let Some(path) = &args.record_audio {
+        if let Err(e) = psx.start_audio_recording(path, args.record_stems) {
+            log::error!("Failed to start audio recording: {}", e);
+        }
+    }
 
     let mut shell_state_open = false;
+    let mut disc_swapper = args.disk_file.as_deref().and_then(DiscSwapper::new);
 
     let mut debugger = Debugger::new();
 
     let mut audio_player = if args.audio {
-        let audio_player = AudioPlayer::<f32>::new(44100, BufferSize::QuarterSecond);
+        let audio_player = AudioPlayer::<f32>::new(args.sample_rate, BufferSize::QuarterSecond);
 
         match audio_player {
             Ok(p) => {
                 p.play().expect("Audio device to play");
-                Some(p)
+                Some((p, AudioSync::new(args.sample_rate, AUDIO_LATENCY)))
             }
             Err(e) => {
                 log::error!("Failed to initialize audio player: {:?}", e);
@@ -582,6 +774,9 @@ fn main() {
         if let Event::WindowEvent { event, .. } = event {
             match event {
                 WindowEvent::CloseRequested => {
+                    if let Err(e) = psx.stop_audio_recording() {
+                        log::error!("{}", e);
+                    }
                     return None;
                 }
                 WindowEvent::Resized(_) => {
@@ -632,6 +827,13 @@ fn main() {
                                 shell_state_open = !shell_state_open;
                                 psx.change_cdrom_shell_open_state(shell_state_open);
                             }
+                            PhysicalKey::Code(key @ (KeyCode::Comma | KeyCode::Period)) => {
+                                if let Some(disc_swapper) = &mut disc_swapper {
+                                    disc_swapper.swap(&mut psx, key == KeyCode::Period);
+                                    // the shell is closed by itself after the disc is inserted
+                                    shell_state_open = false;
+                                }
+                            }
                             _ => {}
                         }
                     }
@@ -647,8 +849,9 @@ fn main() {
                         debugger.handle_cpu_state(&mut psx, cpu_state);
 
                         let audio_buffer = psx.take_audio_buffer();
-                        if let Some(audio_player) = &mut audio_player {
+                        if let Some((audio_player, audio_sync)) = &mut audio_player {
                             audio_player.queue(&audio_buffer);
+                            psx.set_audio_rate_adjust(audio_sync.queue(audio_buffer.len() / 2));
                         }
                     }
                     // keep rendering even when debugger is  running so that
@@ -1,15 +1,38 @@
+mod chd;
+mod cue;
+mod disc;
+mod ecc;
+mod ecm;
+mod iso;
+mod m3u;
+mod pbp;
+mod region;
+mod subchannel;
+
 use crate::{
     memory::{interrupts::InterruptRequester, BusLine, Result},
     spu::Spu,
-    PsxError,
+    unimplemented::{self, Report},
+    HardwareComponent, PsxError,
 };
 use bitflags::bitflags;
+use disc::{LEAD_IN_SECTORS, SECTOR_SIZE};
+use region::DiscLicense;
+use subchannel::SubchannelOverrides;
+
+pub use chd::ChdImage;
+pub use cue::CueBinImage;
+pub use disc::{DiscImage, Track, TrackKind};
+pub use iso::IsoImage;
+pub use m3u::read_m3u_playlist;
+pub use pbp::PbpImage;
+pub use region::{disc_region, Region};
+use serde::{Deserialize, Serialize};
 
 use std::{
     collections::VecDeque,
-    fs,
-    io::Read,
-    path::{Path, PathBuf},
+    io::{Read, Write},
+    path::Path,
 };
 
 const CDROM_COMMAND_DEFAULT_DELAY: u32 = 0x1100;
@@ -20,9 +43,40 @@ const CDROM_COMMAND_DEFAULT_DELAY: u32 = 0x1100;
 //
 // Reduced a bit with 0x100, audio felt a bit jagged with the original delay
 const CDROM_READ_PLAY_DELAY: u32 = 0x6e400 - 0x100;
+/// The shortest seek, the time to settle the head
+const CDROM_SEEK_MIN_DELAY: u32 = 20000;
+/// Seeks up to this many sectors away wait for the sector to come under the head,
+/// further ones move the head
+const CDROM_SEEK_NEAR_SECTORS: usize = 4;
+/// Moving the head to another part of the disc
+const CDROM_SEEK_JUMP_DELAY: u32 = 33868800 / 16;
+/// Moving the head across the whole disc, on top of the jump delay
+const CDROM_SEEK_FULL_DELAY: u32 = 33868800 / 3;
+/// The sectors of the whole disc (74 minutes), used to scale the seek delay with the distance
+const CDROM_FULL_DISC_SECTORS: usize = 74 * 60 * 75;
+/// Starting the motor when it's stopped takes about a second
+const CDROM_SPIN_UP_DELAY: u32 = 33868800;
+/// Number of sectors to skip for every sector played when using `Forward` or `Backward`
+const CDROM_SCAN_SECTORS_STEP: usize = 8;
+/// How long the shell stays open when changing the disc, half a second,
+/// so games polling the status see it open before it's closed
+const CDROM_SHELL_CLOSE_DELAY: u32 = 33868800 / 2;
+
+// The delays of the second responses, as measured on hardware
+const CDROM_GETID_DELAY: u32 = 0x4A00;
+const CDROM_INIT_DELAY: u32 = 0x13CCE;
+/// Reading the table of contents again takes about half a second
+const CDROM_READ_TOC_DELAY: u32 = 33868800 / 2;
+const CDROM_STOP_DELAY: u32 = 0xD38ACA;
+const CDROM_STOP_DOUBLE_SPEED_DELAY: u32 = 0x18A6076;
+const CDROM_STOP_STOPPED_DELAY: u32 = 0x1D7B;
+const CDROM_PAUSE_DELAY: u32 = 0x21181C;
+const CDROM_PAUSE_DOUBLE_SPEED_DELAY: u32 = 0x10BD93;
+const CDROM_PAUSE_PAUSED_DELAY: u32 = 0x1DF2;
 
 bitflags! {
-    #[derive(Default)]
+    #[derive(Default, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct FifosStatus: u8 {
         const ADPBUSY                 = 0b00000100;
         /// 1 when empty (triggered before writing 1st byte)
@@ -39,7 +93,8 @@ bitflags! {
 }
 
 bitflags! {
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct BitCdromStatus: u8 {
         const ERROR        = 0b00000001;
         const MOTOR_ON     = 0b00000010;
@@ -53,7 +108,7 @@ bitflags! {
 }
 
 /// Weither the Cdrom is `Reading`, `Seeking`, or `Playing`
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum ActionStatus {
     #[default]
     None,
@@ -61,11 +116,21 @@ enum ActionStatus {
         second_delivery_attempt: bool,
     },
     Seek,
-    #[allow(dead_code)]
-    Play,
+    Play {
+        scan: PlayScan,
+    },
 }
 
-#[derive(Default, Debug)]
+/// The direction of CD-DA playing, changed with `Forward` and `Backward`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum PlayScan {
+    #[default]
+    Normal,
+    Forward,
+    Backward,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
 struct CdromStatus {
     bit_status: BitCdromStatus,
     action_status: ActionStatus,
@@ -84,7 +149,7 @@ impl CdromStatus {
             ActionStatus::None => 0,
             ActionStatus::Read { .. } => 0b00100000,
             ActionStatus::Seek => 0b01000000,
-            ActionStatus::Play => 0b10000000,
+            ActionStatus::Play { .. } => 0b10000000,
         };
         self.bit_status.bits() | action_bits
     }
@@ -99,6 +164,10 @@ impl CdromStatus {
         }
     }
 
+    fn is_motor_on(&self) -> bool {
+        self.bit_status.contains(BitCdromStatus::MOTOR_ON)
+    }
+
     fn start_motor(&mut self) {
         self.bit_status.insert(BitCdromStatus::MOTOR_ON);
     }
@@ -133,7 +202,8 @@ impl CdromStatus {
 }
 
 bitflags! {
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct CdromMode: u8 {
         const DOUBLE_SPEED            = 0b10000000;
         const XA_ADPCM                = 0b01000000;
@@ -146,6 +216,21 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// The submode byte of the subheader of XA sectors
+    #[derive(Default, Debug)]
+    struct SubMode: u8 {
+        const END_OF_FILE             = 0b10000000;
+        const REAL_TIME               = 0b01000000;
+        const FORM_2                  = 0b00100000;
+        const TRIGGER                 = 0b00010000;
+        const DATA                    = 0b00001000;
+        const AUDIO                   = 0b00000100;
+        const VIDEO                   = 0b00000010;
+        // bit 0 is End of Record, the drive ignores it, it's only for the software
+    }
+}
+
 bitflags! {
     #[derive(Default, Debug)]
     struct CodingInfo: u8 {
@@ -159,12 +244,15 @@ bitflags! {
     }
 }
 
+/// The XA-ADPCM data of a sector, 0x12 portions of 128 bytes
+const XA_ADPCM_DATA_SIZE: usize = 0x12 * 128;
+
 const ADPCM_TABLE_POS: &[i32; 4] = &[0, 60, 115, 98];
 const ADPCM_TABLE_NEG: &[i32; 4] = &[0, 0, -52, -55];
 
 /// This is very similar to what we are doing in the SPU, but the data
 /// format is a bit different. That's why its split.
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
 struct AdpcmDecoder {
     old: i32,
     older: i32,
@@ -280,6 +368,7 @@ const ZIGZAG_TABLE: [[i32; 29]; 7] = [
 
 /// Performs interpolation and converts all audio
 /// sample rates (18900Hz or 37800Hz) to 44100Hz
+#[derive(Serialize, Deserialize)]
 struct AdpcmInterpolator {
     samples_ringbuf: [i16; 0x20],
     samples_i: usize,
@@ -346,6 +435,17 @@ fn to_bcd(arg: u8) -> u8 {
     ((arg / 10) << 4) | (arg % 10)
 }
 
+/// Utility function to convert a number of sectors to (minutes, seconds, sectors)
+fn sectors_to_msf(sectors: usize) -> (u8, u8, u8) {
+    let total_seconds = sectors / 75;
+    (
+        (total_seconds / 60) as u8,
+        (total_seconds % 60) as u8,
+        (sectors % 75) as u8,
+    )
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Cdrom {
     index: u8,
     fifo_status: FifosStatus,
@@ -361,19 +461,37 @@ pub struct Cdrom {
     command_delay_timer: u32,
     /// Timer to control how fast we are reading from the cdrom
     read_play_delay_timer: u32,
+    /// Timer to close the shell after a disc is inserted, `0` if not closing
+    shell_close_delay_timer: u32,
     /// A way to be able to execute a command through more than one cycle,
     /// The type and design might change later
     command_state: Option<u8>,
 
-    cue_file: Option<PathBuf>,
-    cue_file_content: String,
-    disk_data: Vec<u8>,
+    // the disk is not part of the save state, it stays the one that is inserted
+    #[serde(skip)]
+    disc: Option<Box<dyn DiscImage>>,
+    /// Detected when the disc is inserted
+    #[serde(skip)]
+    license: DiscLicense,
+    /// The region of the drive, from the BIOS, `None` for the first (Japanese) BIOS versions
+    #[serde(skip)]
+    drive_region: Option<Region>,
+    /// Skip most of the seek and spin-up delays
+    #[serde(skip)]
+    fast_disc_access: bool,
+    #[serde(skip)]
+    pending_unimplemented: Option<Report>,
 
     // commands save buffer
     // params: minutes, seconds, sector (on entire disk)
     set_loc_params: Option<[u8; 3]>,
     // the current position on the disk
     cursor_sector_position: usize,
+    /// The last subchannel Q with a valid CRC, where the drive thinks it is
+    last_subchannel_q: [u8; 12],
+    /// The header and subheader of the last data sector read, `None` after seeking
+    /// or while playing audio
+    last_sector_header: Option<[u8; 8]>,
 
     mode: CdromMode,
 
@@ -383,6 +501,9 @@ pub struct Cdrom {
 
     filter_file: u8,
     filter_channel: u8,
+    /// The (file, channel) of the XA-ADPCM stream being played when not filtering,
+    /// until the end of its file
+    xa_current_stream: Option<(u8, u8)>,
 
     adpcm_decoder_left_mono: AdpcmDecoder,
     adpcm_decoder_right: AdpcmDecoder,
@@ -403,6 +524,10 @@ pub struct Cdrom {
 
     adpcm_mute: bool,
     cd_mute: bool,
+
+    /// XA-ADPCM data written by the CPU, played when a whole sector is written
+    sound_map_data: Vec<u8>,
+    sound_map_coding_info: u8,
 }
 
 impl Default for Cdrom {
@@ -418,14 +543,18 @@ impl Default for Cdrom {
             command: None,
             command_delay_timer: 0,
             read_play_delay_timer: 0,
+            shell_close_delay_timer: 0,
             command_state: None,
-            cue_file: None,
-            // empty vectors are not allocated
-            cue_file_content: String::new(),
-            disk_data: Vec::new(),
+            disc: None,
+            license: DiscLicense::NoDisc,
+            drive_region: None,
+            fast_disc_access: false,
+            pending_unimplemented: None,
 
             set_loc_params: None,
             cursor_sector_position: 0,
+            last_subchannel_q: [0; 12],
+            last_sector_header: None,
 
             mode: CdromMode::empty(),
 
@@ -435,102 +564,203 @@ impl Default for Cdrom {
 
             filter_file: 0,
             filter_channel: 0,
+            xa_current_stream: None,
 
             adpcm_decoder_left_mono: AdpcmDecoder::default(),
             adpcm_decoder_right: AdpcmDecoder::default(),
             adpcm_interpolator_left_mono: AdpcmInterpolator::default(),
             adpcm_interpolator_right: AdpcmInterpolator::default(),
 
-            input_cd_left_to_spu_left: 0,
+            input_cd_left_to_spu_left: 0x80,
             input_cd_left_to_spu_right: 0,
             input_cd_right_to_spu_left: 0,
-            input_cd_right_to_spu_right: 0,
+            input_cd_right_to_spu_right: 0x80,
 
-            vol_cd_left_to_spu_left: 0,
+            vol_cd_left_to_spu_left: 0x80,
             vol_cd_left_to_spu_right: 0,
             vol_cd_right_to_spu_left: 0,
-            vol_cd_right_to_spu_right: 0,
+            vol_cd_right_to_spu_right: 0x80,
 
             adpcm_mute: false,
             cd_mute: true,
+
+            sound_map_data: Vec::new(),
+            sound_map_coding_info: 0,
         }
     }
 }
 
-// file reading and handling
+/// Opens a `.cue`, `.chd`, `.iso`, `.pbp`, or a lone `.bin` or `.ecm` disc image,
+/// its sectors are read when needed.
+///
+/// Only the first disc of a multi-disc `.pbp` or a `.m3u` playlist is opened,
+/// see [`PbpImage::open_discs`] and [`read_m3u_playlist`].
+///
+/// A `.sbi` or `.lsd` file with the same name is used for the subchannel Q
+/// of the LibCrypt protected sectors.
+pub fn open_disc_image(path: &Path) -> Result<Box<dyn DiscImage>, PsxError> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+
+    let disc: Box<dyn DiscImage> = match extension.as_deref() {
+        Some("cue") => Box::new(CueBinImage::open(path)?),
+        Some("chd") => Box::new(ChdImage::open(path)?),
+        Some("iso") => Box::new(IsoImage::open(path)?),
+        Some("pbp") => Box::new(PbpImage::open(path)?),
+        Some("bin" | "ecm") => Box::new(CueBinImage::open_bin(path)?),
+        Some("m3u") => return open_disc_image(&read_m3u_playlist(path)?[0]),
+        _ => {
+            return Err(PsxError::DiskTypeNotSupported {
+                path: path.to_owned(),
+            })
+        }
+    };
+
+    SubchannelOverrides::load(disc, path)
+}
+
+// disc handling
 impl Cdrom {
     pub fn reset(&mut self) {
-        let cue_file = self.cue_file.take();
+        let disc = self.disc.take();
+        let drive_region = self.drive_region;
+        let fast_disc_access = self.fast_disc_access;
         let _ = std::mem::take(self);
-        if let Some(cue_file) = cue_file {
-            let _ = self.set_cue_file(cue_file);
+        self.drive_region = drive_region;
+        self.fast_disc_access = fast_disc_access;
+        if let Some(disc) = disc {
+            self.set_disc_image(disc);
         }
     }
 
-    pub fn set_cue_file<P: AsRef<Path>>(&mut self, cue_file: P) -> Result<(), PsxError> {
-        let a = cue_file.as_ref().to_path_buf();
-        self.load_cue_file(&a)?;
-        self.cue_file = Some(a);
+    /// Sets the region of the drive, which should match the BIOS
+    pub fn set_drive_region(&mut self, region: Option<Region>) {
+        self.drive_region = region;
+    }
+
+    /// Skips most of the seek and spin-up delays, which makes loading faster,
+    /// but some games depend on them
+    pub fn set_fast_disc_access(&mut self, fast_disc_access: bool) {
+        self.fast_disc_access = fast_disc_access;
+    }
+
+    /// Loads a disc image, see [`open_disc_image`] for the supported formats
+    pub fn set_disc_file<P: AsRef<Path>>(&mut self, disc_file: P) -> Result<(), PsxError> {
+        self.set_disc_image(open_disc_image(disc_file.as_ref())?);
         Ok(())
     }
 
-    fn load_cue_file(&mut self, cue_file: &Path) -> Result<(), PsxError> {
-        macro_rules! parse_expect {
-            ($var:ident, $expected:expr) => {
-                if $var.next().unwrap_or("") != $expected {
-                    return Err(PsxError::CouldNotLoadDisk(
-                        concat!("Invalid cue file: at ", $expected).to_string(),
-                    ));
-                }
-            };
-        }
+    pub fn set_disc_image(&mut self, disc: Box<dyn DiscImage>) {
+        log::info!(
+            "Loaded disc with {} tracks, {} sectors",
+            disc.tracks().len(),
+            disc.sectors()
+        );
 
-        // TODO: support parsing and loading the data based on the cue file
-        // TODO: since some Cds can be large, try to do mmap
+        self.insert(disc);
         self.status.start_motor();
+        self.read_subchannel_q(self.cursor_sector_position);
+    }
+
+    fn insert(&mut self, mut disc: Box<dyn DiscImage>) {
+        self.license = DiscLicense::detect(disc.as_mut());
+        log::info!("Disc license: {:?}", self.license);
+        self.disc = Some(disc);
+    }
 
-        // read cue file
-        let mut file =
-            fs::File::open(cue_file).map_err(|e| PsxError::CouldNotLoadDisk(e.to_string()))?;
-        let mut cue_content = String::new();
-        file.read_to_string(&mut cue_content)
-            .map_err(|e| PsxError::CouldNotLoadDisk(e.to_string()))?;
-        // parse cue format
-        let mut parts = cue_content.split_whitespace();
-        parse_expect!(parts, "FILE");
-        let mut bin_file_name = parts
-            .next()
-            .ok_or_else(|| PsxError::CouldNotLoadDisk("Doesn't have bin filename".to_string()))?
-            .to_string();
-        // must be in quotes
-        assert!(bin_file_name.starts_with('"'));
-        while !bin_file_name.ends_with('"') {
-            bin_file_name.push(' ');
-            bin_file_name.push_str(parts.next().unwrap());
+    /// The region of the inserted disc, `None` if there is no disc,
+    /// or it's not a licensed PlayStation disc
+    pub fn disc_region(&self) -> Option<Region> {
+        self.license.region()
+    }
+
+    /// Opens the shell and removes the disc, stopping any reading or playing
+    pub fn eject_disc(&mut self) {
+        log::info!("CDROM disc ejected");
+        self.disc = None;
+        self.license = DiscLicense::NoDisc;
+        self.shell_close_delay_timer = 0;
+        self.last_sector_header = None;
+        self.status.reset_action_status();
+        self.status.stop_motor();
+        self.change_cdrom_shell_open_state(true);
+    }
+
+    /// Changes the disc like a user would, the shell is opened, then closed
+    /// after a short delay, so games notice the disc changed
+    pub fn insert_disc(&mut self, disc: Box<dyn DiscImage>) {
+        self.eject_disc();
+
+        log::info!(
+            "Inserted disc with {} tracks, {} sectors",
+            disc.tracks().len(),
+            disc.sectors()
+        );
+        self.insert(disc);
+        self.cursor_sector_position = 0;
+        self.shell_close_delay_timer = CDROM_SHELL_CLOSE_DELAY;
+    }
+
+    fn handle_shell_close_delay(&mut self, cycles: u32) {
+        if self.shell_close_delay_timer == 0 {
+            return;
         }
-        // remove quotes
-        bin_file_name = bin_file_name.trim_matches('"').to_string();
-        parse_expect!(parts, "BINARY");
-        parse_expect!(parts, "TRACK");
-        parse_expect!(parts, "01");
-        parse_expect!(parts, "MODE2/2352");
-        parse_expect!(parts, "INDEX");
-        parse_expect!(parts, "01");
-        parse_expect!(parts, "00:00:00");
-
-        // load bin file
-        let bin_file_path = cue_file.parent().unwrap().join(bin_file_name);
-        log::info!("Loading bin file: {:?}", bin_file_path);
-        let mut file =
-            fs::File::open(bin_file_path).map_err(|e| PsxError::CouldNotLoadDisk(e.to_string()))?;
-        // read to new vector
-        let mut bin_file_content = Vec::new();
-        file.read_to_end(&mut bin_file_content)
-            .map_err(|e| PsxError::CouldNotLoadDisk(e.to_string()))?;
-        self.cue_file_content = cue_content;
-        self.disk_data = bin_file_content;
 
-        Ok(())
+        self.shell_close_delay_timer = self.shell_close_delay_timer.saturating_sub(cycles);
+        if self.shell_close_delay_timer == 0 {
+            self.change_cdrom_shell_open_state(false);
+            self.status.start_motor();
+            self.read_subchannel_q(self.cursor_sector_position);
+        }
+    }
+
+    fn tracks(&self) -> &[Track] {
+        self.disc.as_ref().map_or(&[], |disc| disc.tracks())
+    }
+
+    fn track_at(&self, position: usize) -> Option<&Track> {
+        self.disc.as_ref()?.track_at(position)
+    }
+
+    fn track(&self, number: u8) -> Option<&Track> {
+        self.disc.as_ref()?.track(number)
+    }
+
+    fn disc_sectors(&self) -> usize {
+        self.disc.as_ref().map_or(0, |disc| disc.sectors())
+    }
+
+    /// Reads the sector at `position`, `None` if there is no disc, or it's outside of it
+    fn read_sector(&mut self, position: usize) -> Option<[u8; SECTOR_SIZE]> {
+        let disc = self.disc.as_mut()?;
+        if position >= disc.sectors() {
+            return None;
+        }
+
+        match disc.read_sector(position) {
+            Ok(sector) => Some(sector),
+            Err(e) => {
+                log::error!("cdrom: could not read sector {}: {}", position, e);
+                None
+            }
+        }
+    }
+
+    /// Reads the subchannel Q at `position`, it's only used if its CRC is valid,
+    /// otherwise the drive keeps the last one
+    fn read_subchannel_q(&mut self, position: usize) {
+        let Some(disc) = self.disc.as_mut() else {
+            return;
+        };
+
+        let q = disc.subchannel_q(position);
+        if subchannel::is_crc_valid(&q) {
+            self.last_subchannel_q = q;
+        } else {
+            log::info!("cdrom: invalid subchannel Q at sector {}", position);
+        }
     }
 
     pub fn change_cdrom_shell_open_state(&mut self, open: bool) {
@@ -539,14 +769,40 @@ impl Cdrom {
     }
 }
 
+// save states
+impl Cdrom {
+    pub fn save_state<W: Write>(&self, writer: W) -> bincode::Result<()> {
+        bincode::serialize_into(writer, self)
+    }
+
+    pub fn load_state<R: Read>(&mut self, reader: R) -> bincode::Result<()> {
+        let mut state: Self = bincode::deserialize_from(reader)?;
+
+        // keep the disk we have, the state only knows where the cursor was
+        state.disc = self.disc.take();
+        state.license = self.license;
+        state.drive_region = self.drive_region;
+        state.fast_disc_access = self.fast_disc_access;
+        *self = state;
+
+        Ok(())
+    }
+}
+
 // clocking and commands
 impl Cdrom {
+    pub(crate) fn take_unimplemented(&mut self) -> Option<Report> {
+        self.pending_unimplemented.take()
+    }
+
     pub fn clock(
         &mut self,
         interrupt_requester: &mut impl InterruptRequester,
         spu: &mut Spu,
         cycles: u32,
     ) {
+        self.handle_shell_close_delay(cycles);
+
         if self.interrupt_flag & 7 == 0 && self.status.shell_open {
             // shell is open, no commands can be executed
             log::warn!("CDROM command ignored, shell is open");
@@ -562,9 +818,15 @@ impl Cdrom {
         }
 
         if self.handle_reading_delay(cycles) {
-            self.handle_reading_data(spu);
+            match self.status.action_status {
+                ActionStatus::Read { .. } => self.handle_reading_data(spu),
+                ActionStatus::Play { scan } => self.handle_playing_audio(scan, spu),
+                _ => unreachable!(),
+            }
         }
 
+        self.handle_sound_map(spu);
+
         // fire irq only if the interrupt is enabled
         if self.interrupt_flag & self.interrupt_enable != 0 {
             interrupt_requester.request_cdrom();
@@ -596,8 +858,6 @@ impl Cdrom {
         // it can do so
         self.command_delay_timer = CDROM_COMMAND_DEFAULT_DELAY;
 
-        // every command starts the motor (if its not already on)
-        self.status.start_motor();
         match cmd {
             0x01 => {
                 // GetStat
@@ -630,11 +890,71 @@ impl Cdrom {
 
                 self.reset_command();
             }
+            0x03 => {
+                // Play
+
+                // the track parameter is optional
+                let track = self.read_next_parameter().map(from_bcd).unwrap_or(0);
+                log::info!("cdrom cmd: Play(track={})", track);
+
+                let seek_delay = if track == 0 {
+                    // play from the SetLoc position, or continue from the current one
+                    self.do_seek()
+                } else if let (Some(first), Some(last)) =
+                    (self.tracks().first(), self.tracks().last())
+                {
+                    // tracks out of range are clamped to the ones in the disc
+                    let track = track.clamp(first.number, last.number);
+                    let position = self
+                        .track(track)
+                        .map_or(self.cursor_sector_position, |t| t.start);
+                    self.set_loc_params = None;
+                    self.seek_to(position)
+                } else {
+                    self.seek_delay(self.cursor_sector_position)
+                };
+
+                self.status.start_motor();
+                self.status.reset_action_status();
+                self.status.action_status = ActionStatus::Play {
+                    scan: PlayScan::Normal,
+                };
+
+                self.set_response(self.status.bits());
+                self.request_interrupt_0_7(3);
+
+                self.read_play_delay_timer = seek_delay + self.read_play_delay();
+
+                self.reset_command();
+            }
+            0x04 | 0x05 => {
+                // Forward/Backward
+
+                let new_scan = if cmd == 0x04 {
+                    PlayScan::Forward
+                } else {
+                    PlayScan::Backward
+                };
+                log::info!("cdrom cmd: {:?}", new_scan);
+
+                if let ActionStatus::Play { scan } = &mut self.status.action_status {
+                    *scan = new_scan;
+
+                    self.set_response(self.status.bits());
+                    self.request_interrupt_0_7(3);
+
+                    self.reset_command();
+                } else {
+                    // only works while playing
+                    self.respond_error(0x80);
+                }
+            }
             0x06 | 0x1B => {
                 // ReadN/ReadS
 
                 log::info!("cdrom cmd: ReadN");
-                self.do_seek();
+                let seek_delay = self.do_seek();
+                self.status.start_motor();
                 self.status.reset_action_status();
 
                 self.status.action_status = ActionStatus::Read {
@@ -644,23 +964,51 @@ impl Cdrom {
                 self.set_response(self.status.bits());
                 self.request_interrupt_0_7(3);
 
-                self.read_play_delay_timer = if self.mode.intersects(CdromMode::DOUBLE_SPEED) {
-                    CDROM_READ_PLAY_DELAY / 2
-                } else {
-                    CDROM_READ_PLAY_DELAY
-                };
+                self.read_play_delay_timer = seek_delay + self.read_play_delay();
 
                 // reset data buffer
                 self.read_data_buffer.clear();
+                self.xa_current_stream = None;
 
                 self.reset_command();
             }
+            0x07 => {
+                // MotorOn
+
+                if self.command_state.is_none() {
+                    // FIRST
+                    log::info!("cdrom cmd: MotorOn");
+                    if self.status.is_motor_on() {
+                        self.respond_error(0x20);
+                        return;
+                    }
+
+                    self.set_response(self.status.bits());
+                    self.request_interrupt_0_7(3);
+                    // any data for now, just to proceed to SECOND
+                    self.command_state = Some(0);
+                    self.command_delay_timer = self.spin_up_delay();
+                    self.status.start_motor();
+                } else {
+                    // SECOND
+                    self.set_response(self.status.bits());
+                    self.request_interrupt_0_7(2);
+                    self.reset_command();
+                }
+            }
             0x08 => {
                 // Stop
 
                 if self.command_state.is_none() {
                     // FIRST
                     log::info!("cdrom cmd: Stop");
+                    let delay = if !self.status.is_motor_on() {
+                        CDROM_STOP_STOPPED_DELAY
+                    } else if self.mode.intersects(CdromMode::DOUBLE_SPEED) {
+                        CDROM_STOP_DOUBLE_SPEED_DELAY
+                    } else {
+                        CDROM_STOP_DELAY
+                    };
                     self.status.stop_motor();
                     self.status.reset_action_status();
 
@@ -668,6 +1016,7 @@ impl Cdrom {
                     self.request_interrupt_0_7(3);
                     // any data for now, just to proceed to SECOND
                     self.command_state = Some(0);
+                    self.command_delay_timer = delay;
                 } else {
                     // SECOND
                     self.set_response(self.status.bits());
@@ -683,12 +1032,20 @@ impl Cdrom {
                 if self.command_state.is_none() {
                     // FIRST
                     log::info!("cdrom cmd: Pause");
+                    let delay = if self.status.action_status == ActionStatus::None {
+                        CDROM_PAUSE_PAUSED_DELAY
+                    } else if self.mode.intersects(CdromMode::DOUBLE_SPEED) {
+                        CDROM_PAUSE_DOUBLE_SPEED_DELAY
+                    } else {
+                        CDROM_PAUSE_DELAY
+                    };
                     self.status.reset_action_status();
 
                     self.set_response(self.status.bits());
                     self.request_interrupt_0_7(3);
                     // any data for now, just to proceed to SECOND
                     self.command_state = Some(0);
+                    self.command_delay_timer = delay;
                 } else {
                     // SECOND
                     self.set_response(self.status.bits());
@@ -702,33 +1059,13 @@ impl Cdrom {
                 if self.command_state.is_none() {
                     // FIRST
                     log::info!("cdrom cmd: Init");
-
-                    // TODO: check what exactly needs to be reset
-                    //       do we reset all fifos?
-                    //       do we reset setloc params and cursor position?
-
-                    self.mode = CdromMode::empty();
-                    // reset the status and run the motor
-                    self.status = CdromStatus::default();
-                    self.status.start_motor();
-                    // reset fifos
-                    self.data_fifo_buffer.clear();
-                    self.data_fifo_buffer_index = 0;
-                    self.read_data_buffer.clear();
-                    self.fifo_status.remove(FifosStatus::DATA_FIFO_NOT_EMPTY);
-                    self.reset_parameter_fifo();
-                    self.response_fifo.clear();
-                    self.fifo_status
-                        .remove(FifosStatus::RESPONSE_FIFO_NOT_EMPTY);
-
-                    // reset cursor and set_loc positions
-                    self.set_loc_params = None;
-                    self.cursor_sector_position = 0;
+                    self.reset_drive();
 
                     self.set_response(self.status.bits());
                     self.request_interrupt_0_7(3);
                     // any data for now, just to proceed to SECOND
                     self.command_state = Some(0);
+                    self.command_delay_timer = CDROM_INIT_DELAY;
                 } else {
                     // SECOND
 
@@ -788,45 +1125,80 @@ impl Cdrom {
 
                 self.reset_command();
             }
+            0x10 => {
+                // GetLocL
+
+                log::info!("cdrom cmd: GetLocL");
+                let Some(header) = self.last_sector_header else {
+                    // not reading data
+                    self.respond_error(0x80);
+                    return;
+                };
+
+                self.set_response_slice(&header);
+                self.request_interrupt_0_7(3);
+
+                self.reset_command();
+            }
             0x11 => {
                 // GetLocP
 
-                // TODO: fix when supporting multiple tracks
-                //       also, min, second, sectors below as well
-
                 log::info!("cdrom cmd: GetLocP");
-                let track = 1;
-                let index = 1;
+                let q = self.last_subchannel_q;
 
-                let sector = self.cursor_sector_position % 75;
-                let total_seconds = (self.cursor_sector_position / 75) + 2;
-                let minutes = total_seconds / 60;
-                let seconds = total_seconds % 60;
-
-                self.set_response_slice(&[
-                    to_bcd(track),
-                    to_bcd(index),
-                    // track
-                    to_bcd(minutes as u8),
-                    to_bcd(seconds as u8),
-                    to_bcd(sector as u8),
-                    // whole disk
-                    to_bcd(minutes as u8),
-                    to_bcd(seconds as u8),
-                    to_bcd(sector as u8),
-                ]);
+                // track, index, the position in the track, then in the whole disk
+                self.set_response_slice(&[q[1], q[2], q[3], q[4], q[5], q[7], q[8], q[9]]);
 
                 self.request_interrupt_0_7(3);
 
                 self.reset_command();
             }
+            0x12 => {
+                // SetSession
+
+                if self.command_state.is_none() {
+                    // FIRST
+                    let Some(session) = self.read_parameters::<1>().map(|[session]| session) else {
+                        return;
+                    };
+                    log::info!("cdrom cmd: SetSession({})", session);
+                    if session == 0 {
+                        // invalid parameter
+                        self.respond_error(0x10);
+                        return;
+                    }
+
+                    // the session is searched from the start of the disc
+                    self.status.reset_action_status();
+                    self.set_loc_params = None;
+                    let seek_delay = self.seek_to(0);
+                    self.status.start_motor();
+
+                    self.set_response(self.status.bits());
+                    self.request_interrupt_0_7(3);
+                    self.command_state = Some(session);
+                    self.command_delay_timer = seek_delay;
+                } else {
+                    // SECOND
+                    self.status.reset_action_status();
+                    if self.command_state != Some(1) {
+                        // only single session discs are supported
+                        self.respond_error(0x40);
+                        return;
+                    }
+                    self.read_subchannel_q(self.cursor_sector_position);
+
+                    self.set_response(self.status.bits());
+                    self.request_interrupt_0_7(2);
+                    self.reset_command();
+                }
+            }
             0x13 => {
                 // GetTN
-                // TODO: fix when supporting multiple tracks
 
                 log::info!("cdrom cmd: GetTN");
-                let first_track = 1;
-                let last_track = 1;
+                let first_track = self.tracks().first().map_or(1, |t| t.number);
+                let last_track = self.tracks().last().map_or(1, |t| t.number);
 
                 self.set_response_slice(&[
                     self.status.bits(),
@@ -840,27 +1212,24 @@ impl Cdrom {
             }
             0x14 => {
                 // GetTD
-                // TODO: fix when supporting multiple tracks
 
                 let track = from_bcd(self.read_next_parameter().unwrap());
 
                 log::info!("cdrom cmd: GetTD: track = {}", track);
 
-                let res_minutes;
-                let res_seconds;
-                // return the end of the last track
-                if track == 0 {
-                    let total_disk_size = self.disk_data.len();
-                    let total_sectors = total_disk_size / 2352;
-                    let total_seconds = total_sectors / 75;
-                    res_minutes = (total_seconds / 60) as u8;
-                    res_seconds = (total_seconds % 60) as u8;
-                } else if track == 1 {
-                    res_minutes = 0;
-                    res_seconds = 2;
+                // track 0 is the end of the last track
+                let position = if track == 0 {
+                    Some(self.disc_sectors())
                 } else {
-                    todo!("Doesn't support more than 1 track");
-                }
+                    self.track(track).map(|t| t.start)
+                };
+
+                let Some(position) = position else {
+                    // invalid parameter
+                    self.respond_error(0x10);
+                    return;
+                };
+                let (res_minutes, res_seconds, _) = sectors_to_msf(position + LEAD_IN_SECTORS);
 
                 self.set_response_slice(&[
                     self.status.bits(),
@@ -875,22 +1244,36 @@ impl Cdrom {
             0x15 => {
                 // SeekL
 
-                // TODO: the two seek commands are different, from the doc
-                //       it seems that this reads the sector headers to know
-                //       where it should go?
-                //       Not really sure, check and fix
+                // the data seek finds where it is from the sector headers,
+                // so it needs to read a sector after moving the head
                 if self.command_state.is_none() {
                     // FIRST
                     log::info!("cdrom cmd: SeekL");
 
-                    self.do_seek();
+                    let seek_delay = self.do_seek();
+                    self.status.start_motor();
 
                     self.set_response(self.status.bits());
                     self.request_interrupt_0_7(3);
                     // any data for now, just to proceed to SECOND
                     self.command_state = Some(0);
+                    self.command_delay_timer = seek_delay + self.read_play_delay();
                 } else {
                     // SECOND
+                    self.status.reset_action_status();
+                    let position = self.cursor_sector_position;
+                    if !self
+                        .track_at(position)
+                        .is_some_and(|track| track.kind == TrackKind::Data)
+                    {
+                        // audio sectors don't have headers
+                        self.respond_error(0x04);
+                        return;
+                    }
+                    self.last_sector_header = self
+                        .read_sector(position)
+                        .map(|sector| sector[12..20].try_into().unwrap());
+
                     self.set_response(self.status.bits());
                     self.request_interrupt_0_7(2);
                     self.reset_command();
@@ -899,18 +1282,23 @@ impl Cdrom {
             0x16 => {
                 // SeekP
 
+                // the audio seek finds where it is from the subchannel Q,
+                // which works on any track
                 if self.command_state.is_none() {
                     // FIRST
                     log::info!("cdrom cmd: SeekP");
 
-                    self.do_seek();
+                    let seek_delay = self.do_seek();
+                    self.status.start_motor();
 
                     self.set_response(self.status.bits());
                     self.request_interrupt_0_7(3);
                     // any data for now, just to proceed to SECOND
                     self.command_state = Some(0);
+                    self.command_delay_timer = seek_delay;
                 } else {
                     // SECOND
+                    self.status.reset_action_status();
                     self.set_response(self.status.bits());
                     self.request_interrupt_0_7(2);
                     self.reset_command();
@@ -934,36 +1322,90 @@ impl Cdrom {
                     self.request_interrupt_0_7(3);
                     // any data for now, just to proceed to SECOND
                     self.command_state = Some(0);
+                    self.command_delay_timer = CDROM_GETID_DELAY;
                 } else {
                     // SECOND
-                    // TODO: rewrite GetID implementation to fill
-                    //       all the details correctly from the state of the cdrom
-                    let (response, interrupt) = if self.cue_file.is_some() {
-                        // last byte is the region code identifier
-                        // A(0x41): NTSC
-                        // E(0x45): PAL
-                        // I(0x49): JP
-                        (&[0x02, 0x00, 0x20, 0x00, 0x53, 0x43, 0x45, 0x41], 2)
-                    } else {
-                        //  5 interrupt means error
-                        (&[0x08, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 5)
+                    // stat, flags (0x80 unlicensed, 0x40 no disc, 0x10 audio),
+                    // disc type (0x20 mode 2), then the region string
+                    let (response, interrupt) = match self.license {
+                        DiscLicense::NoDisc => ([0x08, 0x40, 0x00, 0x00, 0, 0, 0, 0], 5),
+                        DiscLicense::Audio => ([0x0A, 0x90, 0x00, 0x00, 0, 0, 0, 0], 5),
+                        DiscLicense::Unlicensed { mode2, has_audio } => {
+                            let flags = if has_audio { 0x90 } else { 0x80 };
+                            let disc_type = if mode2 { 0x20 } else { 0x00 };
+                            ([0x0A, flags, disc_type, 0x00, 0, 0, 0, 0], 5)
+                        }
+                        DiscLicense::Licensed(region) => (
+                            [0x02, 0x00, 0x20, 0x00, b'S', b'C', b'E', region.id_letter()],
+                            2,
+                        ),
                     };
 
-                    self.set_response_slice(response);
+                    self.set_response_slice(&response);
                     self.request_interrupt_0_7(interrupt);
                     self.reset_command();
                 }
             }
+            0x1C => {
+                // Reset
+
+                log::info!("cdrom cmd: Reset");
+                self.reset_drive();
+
+                self.set_response(self.status.bits());
+                self.request_interrupt_0_7(3);
+
+                self.reset_command();
+            }
+            0x1D => {
+                // GetQ
+
+                if self.command_state.is_none() {
+                    // FIRST
+                    let Some([adr, point]) = self.read_parameters() else {
+                        return;
+                    };
+                    log::info!("cdrom cmd: GetQ(adr={}, point={:02X})", adr, point);
+                    if adr != 1 {
+                        // only positions are in the table of contents
+                        self.respond_error(0x10);
+                        return;
+                    }
+
+                    self.set_response(self.status.bits());
+                    self.request_interrupt_0_7(3);
+                    self.command_state = Some(point);
+                } else {
+                    // SECOND
+                    let Some(point) = self.command_state else {
+                        self.reset_command();
+                        return;
+                    };
+                    let Some(q) = subchannel::lead_in_q(self.tracks(), self.disc_sectors(), point)
+                    else {
+                        // not found in the lead-in
+                        self.respond_error(0x80);
+                        return;
+                    };
+
+                    self.set_response_slice(&q[..10]);
+                    self.request_interrupt_0_7(2);
+                    self.reset_command();
+                }
+            }
             0x1E => {
-                // GetToc
+                // ReadTOC
 
                 if self.command_state.is_none() {
                     // FIRST
-                    log::info!("cdrom cmd: GetToc");
+                    log::info!("cdrom cmd: ReadTOC");
+                    self.status.reset_action_status();
+
                     self.set_response(self.status.bits());
                     self.request_interrupt_0_7(3);
                     // any data for now, just to proceed to SECOND
                     self.command_state = Some(0);
+                    self.command_delay_timer = CDROM_READ_TOC_DELAY;
                 } else {
                     // SECOND
                     self.set_response(self.status.bits());
@@ -971,13 +1413,46 @@ impl Cdrom {
                     self.reset_command();
                 }
             }
-            _ => todo!("cmd={:02X},state={:?}", cmd, self.command_state),
+            0x1F => {
+                // VideoCD
+
+                // only in the Video CD capable consoles (SCPH-5903)
+                log::info!("cdrom cmd: VideoCD, not supported by the drive");
+                self.respond_error(0x40);
+            }
+            0x50..=0x57 => {
+                // SecretUnlock 1-7 ("Licensed by", "Sony", ..., region) and SecretLock
+
+                // the drive answers all of them with the invalid command error,
+                // even when unlocking. Unlicensed discs are read anyway, so it's not tracked
+                log::info!("cdrom cmd: Secret({:02X})", cmd);
+                self.respond_error(0x40);
+            }
+            _ => {
+                unimplemented::report(
+                    &mut self.pending_unimplemented,
+                    HardwareComponent::Cdrom,
+                    format!("cmd={:02X},state={:?}", cmd, self.command_state),
+                );
+                // invalid command
+                self.respond_error(0x40);
+            }
+        }
+    }
+
+    fn read_play_delay(&self) -> u32 {
+        if self.mode.intersects(CdromMode::DOUBLE_SPEED) {
+            CDROM_READ_PLAY_DELAY / 2
+        } else {
+            CDROM_READ_PLAY_DELAY
         }
     }
 
     fn handle_reading_delay(&mut self, cycles: u32) -> bool {
-        let ActionStatus::Read { .. } = &mut self.status.action_status else {
-            return false;
+        let is_reading = match self.status.action_status {
+            ActionStatus::Read { .. } => true,
+            ActionStatus::Play { .. } => false,
+            _ => return false,
         };
 
         // delay
@@ -987,14 +1462,11 @@ impl Cdrom {
         }
 
         // refresh the delay timer
-        self.read_play_delay_timer += if self.mode.intersects(CdromMode::DOUBLE_SPEED) {
-            CDROM_READ_PLAY_DELAY / 2
-        } else {
-            CDROM_READ_PLAY_DELAY
-        };
+        self.read_play_delay_timer += self.read_play_delay();
 
         // if we can't execute yet, return false
-        if self.interrupt_flag & 7 != 0 {
+        // audio keeps playing, only the reports are skipped
+        if is_reading && self.interrupt_flag & 7 != 0 {
             // pending interrupts, waiting for acknowledgement
             return false;
         }
@@ -1003,30 +1475,42 @@ impl Cdrom {
     }
 
     fn handle_reading_data(&mut self, spu: &mut Spu) {
+        let Some(raw_sector) = self.read_sector(self.cursor_sector_position) else {
+            log::warn!(
+                "cdrom: ReadN: sector {} is outside of the disc",
+                self.cursor_sector_position
+            );
+            self.status.reset_action_status();
+            // seek failed
+            self.set_response_slice(&[self.status.bits() | 1, 0x04]);
+            self.request_interrupt_0_7(5);
+            return;
+        };
+
+        self.read_subchannel_q(self.cursor_sector_position);
+        self.last_sector_header = Some(raw_sector[12..20].try_into().unwrap());
+
         let ActionStatus::Read {
             second_delivery_attempt,
-        } = &mut self.status.action_status
+        } = self.status.action_status
         else {
             unreachable!()
         };
 
-        let sector_start = self.cursor_sector_position * 2352;
-
         // skip the sync bytes
-        let whole_sector = &self.disk_data[sector_start + 12..sector_start + 0x930];
+        let whole_sector = &raw_sector[12..];
 
-        // TODO: add filtering and coding info handling
+        let is_audio_track = self
+            .track_at(self.cursor_sector_position)
+            .is_some_and(|track| track.kind == TrackKind::Audio);
         let mode = whole_sector[3];
         let file = whole_sector[4];
         let channel = whole_sector[5] & 0x1F;
-        let submode = whole_sector[6];
+        let submode = SubMode::from_bits_retain(whole_sector[6]);
         let coding_info = whole_sector[7];
-        let submode_audio = submode & 0x4 != 0;
-        let submode_realtime = submode & 0x40 != 0;
-
-        // was the current sector read, and should we move to the next?
-        let mut sector_read = false;
+        let is_adpcm = submode.contains(SubMode::AUDIO | SubMode::REAL_TIME);
 
+        let filter_enabled = self.mode.intersects(CdromMode::XA_FILTER);
         let filter_match = self.filter_file == file && self.filter_channel == channel;
 
         // convert from cursor pos to sector,seconds,minutes
@@ -1038,43 +1522,12 @@ impl Cdrom {
 
         // delivery options:
         //   try_deliver_as_adpcm_sector:
-        //    TODO: reject if CD-DA AUDIO format
+        //    reject if CD-DA AUDIO format
         //    reject if sector isn't MODE2 format
         //    reject if adpcm_disabled(setmode.6)
         //    reject if filter_enabled(setmode.3) AND selected file/channel doesn't match
         //    reject if submode isn't audio+realtime (bit2 and bit6 must be both set)
         //    deliver: send sector to xa-adpcm decoder when passing above cases
-
-        // mode 2 is CD-XA mode, which is the mode of the sector
-        if mode == 2
-            && self.mode.intersects(CdromMode::XA_ADPCM)
-            && (!self.mode.intersects(CdromMode::XA_FILTER) || filter_match)
-            && submode_audio
-            && submode_realtime
-        {
-            *second_delivery_attempt = false; // reset data delivery attempts
-
-            self.deliver_adpcm_to_spu(
-                self.cursor_sector_position,
-                CodingInfo::from_bits_retain(coding_info),
-                spu,
-            );
-            log::info!(
-                "cdrom: ReadN: sector {} [{:02}:{:02}:{:02}] deilverd to ADPCM-SPU",
-                self.cursor_sector_position,
-                minutes,
-                seconds,
-                sector
-            );
-            sector_read = true;
-
-        // TODO: for some reason, this doesn't work on CTR,
-        //       it expects to get data interrupts on other channels
-        //       when reading from XA interleaved sectors,
-        //       the current implementation doesn't do the below check to the
-        //       letter, it only does it in the second attempt, so the first
-        //       attempt will always send something.
-        //       Try to find what is the best approach to this
         //
         //  try_deliver_as_data_sector:
         //    reject data-delivery if "try_deliver_as_adpcm_sector" did do adpcm-delivery
@@ -1083,56 +1536,51 @@ impl Cdrom {
         //    delay, and retry at later time... but this time with file/channel checking!
         //    reject if filter_enabled(setmode.3) AND selected file/channel doesn't match
         //    2nd delivery attempt: send INT1+data, unless there's another INT pending
-        } else if !(*second_delivery_attempt)
-            || !self.mode.intersects(CdromMode::XA_FILTER)
-            || !(submode_audio && submode_realtime)
-                && (!self.mode.intersects(CdromMode::XA_FILTER) || filter_match)
-        {
-            // only refill the data if the buffer is taken, else
-            // just interrupt
-            //
-            // if we're on the second attempt, and the buffer is still not empty
-            // perform buffer overrun, i.e. replace the data of the current buffer
-            if self.read_data_buffer.is_empty() || *second_delivery_attempt {
-                // wait until the data fifo buffer is empty
+        //
+        //  else:
+        //    ignore sector silently
+        let try_adpcm = !is_audio_track
+            && mode == 2
+            && self.mode.intersects(CdromMode::XA_ADPCM)
+            && (!filter_enabled || filter_match)
+            && is_adpcm;
+
+        let mut second_delivery_attempt = second_delivery_attempt;
+        // was the current sector read, and should we move to the next?
+        let sector_read = if try_adpcm {
+            second_delivery_attempt = false;
+
+            // without the filter, the first stream found is played until its end,
+            // and the other interleaved streams are skipped
+            let stream = (file, channel);
+            if filter_enabled || *self.xa_current_stream.get_or_insert(stream) == stream {
+                if submode.intersects(SubMode::END_OF_FILE) {
+                    self.xa_current_stream = None;
+                }
+
+                self.deliver_adpcm_to_spu(
+                    &raw_sector[24..24 + XA_ADPCM_DATA_SIZE],
+                    CodingInfo::from_bits_retain(coding_info),
+                    spu,
+                );
                 log::info!(
-                    "cdrom cmd: ReadN: pushing sector {} [{:02}:{:02}:{:02}] to data fifo buffer",
+                    "cdrom: ReadN: sector {} [{:02}:{:02}:{:02}] deilverd to ADPCM-SPU",
                     self.cursor_sector_position,
                     minutes,
                     seconds,
                     sector
                 );
-
-                let data = if self.mode.intersects(CdromMode::USE_WHOLE_SECTOR) {
-                    whole_sector
-                } else {
-                    // skip the sub header
-                    &whole_sector[12..12 + 0x800]
-                };
-
-                // if there is something, override it
-                self.read_data_buffer.clear();
-                self.read_data_buffer.extend_from_slice(data);
-
-                *second_delivery_attempt = false;
-                sector_read = true;
-
-                self.set_response(self.status.bits());
-                self.request_interrupt_0_7(1);
             } else {
-                // set the second delivery attempt flag, so that next time we perform the
-                // file/channel checks
-                *second_delivery_attempt = true;
-
-                // when retrying for the second time, do not wait for a full delay
-                // as this results in audio stuttering
-                self.read_play_delay_timer = 0;
+                log::info!(
+                    "cdrom: ReadN: skipping ADPCM sector {} of file {}, channel {}",
+                    self.cursor_sector_position,
+                    file,
+                    channel
+                );
             }
-
-        //  else:
-        //    ignore sector silently
-        } else {
-            *second_delivery_attempt = false; // reset data delivery attempts
+            true
+        } else if filter_enabled && (is_adpcm || (second_delivery_attempt && !filter_match)) {
+            second_delivery_attempt = false;
 
             log::info!(
                 "cdrom: ReadN: skipping sector {} [{:02}:{:02}:{:02}]",
@@ -1141,9 +1589,49 @@ impl Cdrom {
                 seconds,
                 sector
             );
-            // skip sector
-            sector_read = true;
-        }
+            true
+        } else if self.read_data_buffer.is_empty() || second_delivery_attempt {
+            // only refill the data if the buffer is taken,
+            // if we're on the second attempt, and the buffer is still not empty
+            // perform buffer overrun, i.e. replace the data of the current buffer
+            log::info!(
+                "cdrom cmd: ReadN: pushing sector {} [{:02}:{:02}:{:02}] to data fifo buffer",
+                self.cursor_sector_position,
+                minutes,
+                seconds,
+                sector
+            );
+
+            let data = if self.mode.intersects(CdromMode::USE_WHOLE_SECTOR) {
+                whole_sector
+            } else {
+                // skip the sub header
+                &whole_sector[12..12 + 0x800]
+            };
+
+            // if there is something, override it
+            self.read_data_buffer.clear();
+            self.read_data_buffer.extend_from_slice(data);
+
+            second_delivery_attempt = false;
+
+            self.set_response(self.status.bits());
+            self.request_interrupt_0_7(1);
+            true
+        } else {
+            // set the second delivery attempt flag, so that next time we perform the
+            // file/channel checks
+            second_delivery_attempt = true;
+
+            // when retrying for the second time, do not wait for a full delay
+            // as this results in audio stuttering
+            self.read_play_delay_timer = 0;
+            false
+        };
+
+        self.status.action_status = ActionStatus::Read {
+            second_delivery_attempt,
+        };
 
         // if we haven't read, just wait the default delay and re-interrupt.
         if sector_read {
@@ -1151,18 +1639,7 @@ impl Cdrom {
         }
     }
 
-    // because of `&self` and `&mut self` conflict, we can't pass the
-    // sector data directly (even though we already have it).
-    // TODO: look to see if there is a better way for this
-    fn deliver_adpcm_to_spu(
-        &mut self,
-        sector_position: usize,
-        coding_info: CodingInfo,
-        spu: &mut Spu,
-    ) {
-        let sector_start = sector_position * 2352;
-        let data = &self.disk_data[sector_start + 24..sector_start + 24 + 0x900];
-
+    fn deliver_adpcm_to_spu(&mut self, data: &[u8], coding_info: CodingInfo, spu: &mut Spu) {
         let sample_8bit = coding_info.intersects(CodingInfo::BITS_PER_SAMPLE);
 
         // TODO: try to use static allocation/slab or anything that is not heap intensive
@@ -1211,16 +1688,153 @@ impl Cdrom {
                 }
             }
         }
-        let mut spu_audio_left = vec![0; cd_audio_left.len()];
-        let mut spu_audio_right = vec![0; cd_audio_left.len()];
-
         let (audio_left, audio_right) = if coding_info.intersects(CodingInfo::STEREO) {
             (&cd_audio_left, &cd_audio_right)
         } else {
             (&cd_audio_left, &cd_audio_left)
         };
 
-        if !self.cd_mute && !self.adpcm_mute {
+        self.mix_audio_to_spu(audio_left, audio_right, self.adpcm_mute, spu);
+    }
+
+    fn handle_playing_audio(&mut self, scan: PlayScan, spu: &mut Spu) {
+        let position = self.cursor_sector_position;
+
+        let (Some(track), Some(sector)) =
+            (self.track_at(position).cloned(), self.read_sector(position))
+        else {
+            log::info!("cdrom: Play: reached the end of the disc");
+            self.status.reset_action_status();
+            self.set_response(self.status.bits());
+            // data end
+            self.request_interrupt_0_7(4);
+            return;
+        };
+
+        self.read_subchannel_q(position);
+        self.last_sector_header = None;
+
+        let mut left = [0; SECTOR_SIZE / 4];
+        let mut right = [0; SECTOR_SIZE / 4];
+        // data tracks are played as silence
+        if track.kind == TrackKind::Audio {
+            for (i, sample) in sector.chunks_exact(4).enumerate() {
+                left[i] = i16::from_le_bytes([sample[0], sample[1]]);
+                right[i] = i16::from_le_bytes([sample[2], sample[3]]);
+            }
+        }
+        self.mix_audio_to_spu(&left, &right, false, spu);
+
+        self.cursor_sector_position = match scan {
+            PlayScan::Normal => position + 1,
+            PlayScan::Forward => position + CDROM_SCAN_SECTORS_STEP,
+            PlayScan::Backward => {
+                if position < CDROM_SCAN_SECTORS_STEP {
+                    // reached the start, continue playing normally
+                    self.status.action_status = ActionStatus::Play {
+                        scan: PlayScan::Normal,
+                    };
+                }
+                position.saturating_sub(CDROM_SCAN_SECTORS_STEP)
+            }
+        };
+
+        if self.mode.intersects(CdromMode::AUTO_PAUSE)
+            && scan != PlayScan::Backward
+            && self.cursor_sector_position >= track.end()
+        {
+            log::info!(
+                "cdrom: Play: auto pause at the end of track {}",
+                track.number
+            );
+            self.status.reset_action_status();
+            self.set_response(self.status.bits());
+            // data end
+            self.request_interrupt_0_7(4);
+            return;
+        }
+
+        // report 10 times per second, alternating between absolute and relative positions
+        let q = self.last_subchannel_q;
+        let frame = from_bcd(q[9]);
+        if self.mode.intersects(CdromMode::REPORT_INTERRUPT_ENABLE)
+            && frame.is_multiple_of(10)
+            && self.interrupt_flag & 7 == 0
+        {
+            let relative = (frame / 10) % 2 == 1;
+
+            let (minutes, seconds, sector) = if relative {
+                (q[3], q[4] | 0x80, q[5])
+            } else {
+                (q[7], q[8], q[9])
+            };
+
+            // the peak of the left channel in absolute reports, and right in relative ones
+            // with bit15 set
+            let peak = if relative {
+                right
+                    .iter()
+                    .map(|s| s.unsigned_abs())
+                    .max()
+                    .unwrap_or(0)
+                    .min(0x7FFF)
+                    | 0x8000
+            } else {
+                left.iter()
+                    .map(|s| s.unsigned_abs())
+                    .max()
+                    .unwrap_or(0)
+                    .min(0x7FFF)
+            };
+
+            self.set_response_slice(&[
+                self.status.bits(),
+                q[1],
+                q[2],
+                minutes,
+                seconds,
+                sector,
+                peak as u8,
+                (peak >> 8) as u8,
+            ]);
+            self.request_interrupt_0_7(1);
+        }
+    }
+
+    /// Plays the XA-ADPCM data written by the CPU, like a sector read from the disc
+    fn handle_sound_map(&mut self, spu: &mut Spu) {
+        if self.sound_map_data.len() < XA_ADPCM_DATA_SIZE {
+            return;
+        }
+
+        let data = self
+            .sound_map_data
+            .drain(..XA_ADPCM_DATA_SIZE)
+            .collect::<Vec<_>>();
+        log::info!(
+            "cdrom: Sound Map: playing sector with coding info {:02X}",
+            self.sound_map_coding_info
+        );
+        self.deliver_adpcm_to_spu(
+            &data,
+            CodingInfo::from_bits_retain(self.sound_map_coding_info),
+            spu,
+        );
+    }
+
+    /// Applies the cd volumes and sends the audio to the spu,
+    /// it will be silent if the cd or `muted` is muted
+    fn mix_audio_to_spu(
+        &self,
+        audio_left: &[i16],
+        audio_right: &[i16],
+        muted: bool,
+        spu: &mut Spu,
+    ) {
+        let mut spu_audio_left = vec![0; audio_left.len()];
+        let mut spu_audio_right = vec![0; audio_left.len()];
+
+        if !self.cd_mute && !muted {
             for (i, (&left, &right)) in audio_left.iter().zip(audio_right.iter()).enumerate() {
                 let l = (left as i32 * self.vol_cd_left_to_spu_left as i32 / 0x80)
                     + (right as i32 * self.vol_cd_right_to_spu_left as i32 / 0x80);
@@ -1235,13 +1849,65 @@ impl Cdrom {
         spu.add_cdrom_audio(&spu_audio_left, &spu_audio_right);
     }
 
+    /// Resets the drive for the `Init` and `Reset` commands
+    fn reset_drive(&mut self) {
+        // TODO: check what exactly needs to be reset
+        //       do we reset all fifos?
+        //       do we reset setloc params and cursor position?
+
+        self.mode = CdromMode::empty();
+        // reset the status and run the motor
+        self.status = CdromStatus::default();
+        self.status.start_motor();
+        // reset fifos
+        self.data_fifo_buffer.clear();
+        self.data_fifo_buffer_index = 0;
+        self.read_data_buffer.clear();
+        self.fifo_status.remove(FifosStatus::DATA_FIFO_NOT_EMPTY);
+        self.reset_parameter_fifo();
+        self.response_fifo.clear();
+        self.fifo_status
+            .remove(FifosStatus::RESPONSE_FIFO_NOT_EMPTY);
+
+        // reset cursor and set_loc positions
+        self.set_loc_params = None;
+        self.cursor_sector_position = 0;
+    }
+
     fn execute_test(&mut self, test_code: u8) {
         match test_code {
+            0x00..=0x03 | 0x10..=0x1A => {
+                // Servo and focus adjustments, not emulated
+                self.set_response(self.status.bits());
+                self.request_interrupt_0_7(3);
+            }
             0x20 => {
                 // Get the CD-ROM hardware version
                 self.set_response_slice(&[0x99u8, 0x02, 0x01, 0xC3]);
                 self.request_interrupt_0_7(3);
             }
+            0x21 => {
+                // Get the drive switches, bit0: the head is at the start, bit1: the shell is open
+                let head_at_start = self.cursor_sector_position == 0;
+                self.set_response(head_at_start as u8 | ((self.status.shell_open as u8) << 1));
+                self.request_interrupt_0_7(3);
+            }
+            0x22 => {
+                // Get the region string of the drive
+                let region: &[u8] = match self.drive_region {
+                    Some(Region::NorthAmerica) => b"for U/S",
+                    Some(Region::Europe) => b"for Europe",
+                    Some(Region::Japan) | None => b"for Japan",
+                };
+                self.set_response_slice(region);
+                self.request_interrupt_0_7(3);
+            }
+            0x23..=0x25 => {
+                // Get the chip ID of the servo amplifier, signal processor and decoder,
+                // the drive of this version has them all in one chip
+                self.set_response_slice(b"CXD2940Q");
+                self.request_interrupt_0_7(3);
+            }
             0x04 | 0x05 => {
                 // Read SCEx strings
                 // Get SCEx counters
@@ -1253,34 +1919,88 @@ impl Cdrom {
                 self.set_response(self.status.bits());
                 self.request_interrupt_0_7(3);
             }
-            _ => todo!("Test code {:02X}", test_code),
+            _ => {
+                unimplemented::report(
+                    &mut self.pending_unimplemented,
+                    HardwareComponent::Cdrom,
+                    format!("Test code {:02X}", test_code),
+                );
+                // invalid sub function
+                self.respond_error(0x10);
+            }
         }
     }
 
-    #[inline]
-    fn do_seek(&mut self) {
-        if let Some(params) = self.set_loc_params {
+    /// Seeks to the `SetLoc` position if there is one, otherwise stays at the cursor,
+    /// returns how long it takes
+    fn do_seek(&mut self) -> u32 {
+        let Some(params) = self.set_loc_params.take() else {
+            return self.seek_to(self.cursor_sector_position);
+        };
+
+        // setting the position from the setLoc data
+        let minutes = params[0] as usize;
+        let seconds = params[1] as usize;
+        let sector = params[2] as usize;
+
+        let total_seconds = minutes * 60 + seconds;
+        // the first 2 seconds are the lead-in (pregap), which is not in the disc image,
+        // seeking into it goes to the first sector
+        let position = (total_seconds * 75 + sector).saturating_sub(2 * 75);
+
+        log::info!(
+            "cdrom seek: ({:02}:{:02}:{:02}) => {:08X}",
+            minutes,
+            seconds,
+            sector,
+            position
+        );
+
+        self.seek_to(position)
+    }
+
+    /// Moves the cursor to `position`, returns how long it takes
+    fn seek_to(&mut self, position: usize) -> u32 {
+        let delay = self.seek_delay(position);
+
+        if position != self.cursor_sector_position {
             self.status.action_status = ActionStatus::Seek;
+        }
+        self.cursor_sector_position = position;
+        self.last_sector_header = None;
+        self.read_subchannel_q(self.cursor_sector_position);
 
-            // setting the position from the setLoc data
-            let minutes = params[0] as usize;
-            let seconds = params[1] as usize;
-            let sector = params[2] as usize;
+        delay
+    }
 
-            let total_seconds = minutes * 60 + seconds;
-            // there is an missing 2 seconds offset (for some reason)
-            assert!(total_seconds >= 2);
-            self.cursor_sector_position = (total_seconds - 2) * 75 + sector;
+    /// How long it takes to move the head from the cursor to `position`,
+    /// including starting the motor if it's stopped
+    fn seek_delay(&self, position: usize) -> u32 {
+        if self.fast_disc_access {
+            return CDROM_SEEK_MIN_DELAY;
+        }
 
-            log::info!(
-                "cdrom seek: ({:02}:{:02}:{:02}) => {:08X}",
-                minutes,
-                seconds,
-                sector,
-                self.cursor_sector_position
-            );
+        let distance = position.abs_diff(self.cursor_sector_position);
+        let move_delay = if distance <= CDROM_SEEK_NEAR_SECTORS {
+            // wait for the sector to come under the head
+            distance as u32 * self.read_play_delay()
+        } else {
+            let distance = distance.min(CDROM_FULL_DISC_SECTORS) as u64;
+            CDROM_SEEK_JUMP_DELAY
+                + (distance * CDROM_SEEK_FULL_DELAY as u64 / CDROM_FULL_DISC_SECTORS as u64) as u32
+        };
+
+        CDROM_SEEK_MIN_DELAY + move_delay + self.spin_up_delay()
+    }
 
-            self.set_loc_params = None;
+    /// How long it takes to start the motor, `0` if it's already running
+    fn spin_up_delay(&self) -> u32 {
+        if self.status.is_motor_on() {
+            0
+        } else if self.fast_disc_access {
+            CDROM_SEEK_MIN_DELAY
+        } else {
+            CDROM_SPIN_UP_DELAY
         }
     }
 
@@ -1291,6 +2011,28 @@ impl Cdrom {
         self.fifo_status.insert(FifosStatus::BUSY);
     }
 
+    /// Reads exactly `N` parameters, if there are more or less, responds with the
+    /// wrong number of parameters error and returns `None`
+    fn read_parameters<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.parameter_fifo.len() != N {
+            log::warn!(
+                "cdrom: expected {} parameters, got {}",
+                N,
+                self.parameter_fifo.len()
+            );
+            self.respond_error(0x20);
+            return None;
+        }
+        Some(std::array::from_fn(|_| self.read_next_parameter().unwrap()))
+    }
+
+    /// Respond with `INT5` and the error `code` in the second byte
+    fn respond_error(&mut self, code: u8) {
+        self.set_response_slice(&[self.status.bits() | 1, code]);
+        self.request_interrupt_0_7(5);
+        self.reset_command();
+    }
+
     fn reset_command(&mut self) {
         self.command = None;
         self.command_delay_timer = 0;
@@ -1460,6 +2202,8 @@ impl Cdrom {
 }
 
 impl BusLine for Cdrom {
+    const COMPONENT: HardwareComponent = HardwareComponent::Cdrom;
+
     fn read_u8(&mut self, addr: u32) -> Result<u8> {
         let r = match addr {
             0 => self.read_index_status(),
@@ -1483,12 +2227,15 @@ impl BusLine for Cdrom {
             1 => match self.index {
                 0 => self.write_command_register(data),
                 1 => {
-                    todo!("write 1.1 Sound Map Data Out");
+                    // write 1.1 Sound Map Data Out
+                    self.sound_map_data.push(data);
                 }
                 2 => {
-                    todo!("write 1.2 Sound Map Coding Info");
+                    // write 1.2 Sound Map Coding Info
+                    self.sound_map_coding_info = data;
                 }
                 3 => {
+                    // write 1.3 Right-CD to Right-SPU Volume
                     self.input_cd_right_to_spu_right = data;
                 }
                 _ => unreachable!(),
@@ -1497,9 +2244,11 @@ impl BusLine for Cdrom {
                 0 => self.write_to_parameter_fifo(data),
                 1 => self.write_interrupt_enable_register(data),
                 2 => {
+                    // write 2.2 Left-CD to Left-SPU Volume
                     self.input_cd_left_to_spu_left = data;
                 }
                 3 => {
+                    // write 2.3 Right-CD to Left-SPU Volume
                     self.input_cd_right_to_spu_left = data;
                 }
                 _ => unreachable!(),
@@ -0,0 +1,844 @@
+//! MAME's Compressed Hunks of Data (CHD) disc images.
+//!
+//! Only version 5 without a parent is supported, with the CD codecs `cdzl`, `cdlz` and `cdfl`.
+
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use super::{
+    disc::{DiscImage, ImageTrack, Track, TrackKind, TrackLayout, SECTOR_SIZE},
+    ecc,
+};
+use crate::PsxError;
+
+const HEADER_TAG: &[u8; 8] = b"MComprHD";
+const HEADER_V5_SIZE: usize = 124;
+
+const SUBCODE_SIZE: usize = 96;
+/// A sector with its subchannel data, the unit of CD hunks
+const FRAME_SIZE: usize = SECTOR_SIZE + SUBCODE_SIZE;
+/// The frames of every track are padded to a multiple of this
+const TRACK_PADDING: usize = 4;
+
+const fn tag(name: &[u8; 4]) -> u32 {
+    u32::from_be_bytes(*name)
+}
+
+const CODEC_CD_ZLIB: u32 = tag(b"cdzl");
+const CODEC_CD_LZMA: u32 = tag(b"cdlz");
+const CODEC_CD_FLAC: u32 = tag(b"cdfl");
+
+const METADATA_CD_TRACK: u32 = tag(b"CHTR");
+const METADATA_CD_TRACK_2: u32 = tag(b"CHT2");
+
+// compression types in the compressed map
+const COMPRESSION_TYPE_3: u8 = 3;
+const COMPRESSION_NONE: u8 = 4;
+const COMPRESSION_SELF: u8 = 5;
+const COMPRESSION_PARENT: u8 = 6;
+const COMPRESSION_RLE_SMALL: u8 = 7;
+const COMPRESSION_RLE_LARGE: u8 = 8;
+const COMPRESSION_SELF_0: u8 = 9;
+const COMPRESSION_SELF_1: u8 = 10;
+const COMPRESSION_PARENT_SELF: u8 = 11;
+const COMPRESSION_PARENT_0: u8 = 12;
+const COMPRESSION_PARENT_1: u8 = 13;
+
+#[derive(Debug)]
+enum Error {
+    Io(io::Error),
+    Invalid(String),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+fn invalid<T>(reason: impl Into<String>) -> Result<T, Error> {
+    Err(Error::Invalid(reason.into()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MapEntry {
+    Compressed {
+        codec: u32,
+        offset: u64,
+        length: u32,
+    },
+    Uncompressed {
+        offset: u64,
+    },
+    /// A copy of an earlier hunk
+    CopyOf(usize),
+    Zeros,
+}
+
+/// Reads bits from the most significant bit of every byte, zeros are read after the end
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn peek(&self, count: u32) -> u64 {
+        (0..count as usize).fold(0, |value, i| {
+            let bit = self.position + i;
+            let byte = self.data.get(bit / 8).copied().unwrap_or(0);
+            (value << 1) | ((byte >> (7 - bit % 8)) & 1) as u64
+        })
+    }
+
+    fn read(&mut self, count: u32) -> u64 {
+        let value = self.peek(count);
+        self.position += count as usize;
+        value
+    }
+
+    fn overflowed(&self) -> bool {
+        self.position > self.data.len() * 8
+    }
+}
+
+/// The canonical huffman decoder used for the compression types of the map,
+/// 16 symbols of at most 8 bits
+struct Huffman {
+    /// (symbol, number of bits) for every 8 bit value
+    lookup: [(u8, u8); 1 << Self::MAX_BITS],
+}
+
+impl Huffman {
+    const NUM_CODES: usize = 16;
+    const MAX_BITS: u32 = 8;
+
+    fn import_tree_rle(bits: &mut BitReader) -> Result<Self, Error> {
+        let mut code_lengths = [0u8; Self::NUM_CODES];
+        let mut current = 0;
+        while current < Self::NUM_CODES {
+            let length = bits.read(4) as u8;
+            // 1 is an escape code, followed by 1 for a single 1, or a length with a repeat count
+            let (length, repeat) = if length != 1 {
+                (length, 1)
+            } else {
+                match bits.read(4) as u8 {
+                    1 => (1, 1),
+                    length => (length, bits.read(4) as usize + 3),
+                }
+            };
+            if current + repeat > Self::NUM_CODES || length as u32 > Self::MAX_BITS {
+                return invalid("invalid huffman tree in CHD map");
+            }
+            code_lengths[current..current + repeat].fill(length);
+            current += repeat;
+        }
+
+        // assign the canonical codes, starting from the longest
+        let mut histogram = [0u32; 33];
+        for &length in &code_lengths {
+            histogram[length as usize] += 1;
+        }
+        let mut start = 0;
+        for length in (1..=32).rev() {
+            let next_start = (start + histogram[length]) >> 1;
+            if length != 1 && next_start * 2 != start + histogram[length] {
+                return invalid("invalid huffman tree in CHD map");
+            }
+            histogram[length] = start;
+            start = next_start;
+        }
+
+        let mut lookup = [(0, 0); 1 << Self::MAX_BITS];
+        for (symbol, &length) in code_lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let code = histogram[length as usize];
+            histogram[length as usize] += 1;
+
+            let shift = Self::MAX_BITS - length as u32;
+            let first = (code << shift) as usize;
+            let entries = lookup
+                .get_mut(first..first + (1 << shift))
+                .ok_or(Error::Invalid("invalid huffman tree in CHD map".into()))?;
+            entries.fill((symbol as u8, length));
+        }
+
+        Ok(Self { lookup })
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> u8 {
+        let (symbol, length) = self.lookup[bits.peek(Self::MAX_BITS) as usize];
+        bits.read(length as u32);
+        symbol
+    }
+}
+
+struct TrackMetadata {
+    number: u8,
+    kind: TrackKind,
+    frames: usize,
+    pregap: usize,
+    /// The pregap is stored in the file, as part of `frames`
+    pregap_in_file: bool,
+    postgap: usize,
+}
+
+impl TrackMetadata {
+    /// Parses the text of `CHTR` and `CHT2` entries,
+    /// `TRACK:%d TYPE:%s SUBTYPE:%s FRAMES:%d PREGAP:%d PGTYPE:%s PGSUB:%s POSTGAP:%d`
+    fn parse(text: &str) -> Result<Self, Error> {
+        let mut number = None;
+        let mut kind = None;
+        let mut frames = None;
+        let mut pregap = 0;
+        let mut pregap_in_file = false;
+        let mut postgap = 0;
+
+        for field in text.trim_end_matches('\0').split_whitespace() {
+            let Some((key, value)) = field.split_once(':') else {
+                return invalid(format!("invalid track metadata `{}`", text));
+            };
+            let number_value = || {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| Error::Invalid(format!("invalid track metadata `{}`", text)))
+            };
+            match key {
+                "TRACK" => number = Some(number_value()? as u8),
+                "TYPE" => {
+                    kind = Some(match value {
+                        "AUDIO" => TrackKind::Audio,
+                        "MODE1_RAW" | "MODE2_RAW" => TrackKind::Data,
+                        _ => return invalid(format!("unsupported track type {}", value)),
+                    })
+                }
+                "FRAMES" => frames = Some(number_value()?),
+                "PREGAP" => pregap = number_value()?,
+                "PGTYPE" => pregap_in_file = value.starts_with('V'),
+                "POSTGAP" => postgap = number_value()?,
+                _ => {}
+            }
+        }
+
+        match (number, kind, frames) {
+            (Some(number), Some(kind), Some(frames)) => Ok(Self {
+                number,
+                kind,
+                frames,
+                pregap,
+                pregap_in_file,
+                postgap,
+            }),
+            _ => invalid(format!("incomplete track metadata `{}`", text)),
+        }
+    }
+}
+
+struct Chd<R> {
+    file: R,
+    compressors: [u32; 4],
+    hunk_bytes: usize,
+    meta_offset: u64,
+    map: Vec<MapEntry>,
+
+    cached_hunk: Option<usize>,
+    hunk_buffer: Vec<u8>,
+}
+
+impl<R: Read + Seek> Chd<R> {
+    fn open(mut file: R) -> Result<Self, Error> {
+        let mut header = [0; HEADER_V5_SIZE];
+        file.read_exact(&mut header)?;
+
+        if &header[0..8] != HEADER_TAG {
+            return invalid("not a CHD file");
+        }
+        let be32 =
+            |offset: usize| u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+        let be64 =
+            |offset: usize| u64::from_be_bytes(header[offset..offset + 8].try_into().unwrap());
+
+        let version = be32(12);
+        if version != 5 {
+            return invalid(format!("unsupported CHD version {}", version));
+        }
+        if header[104..124].iter().any(|&b| b != 0) {
+            return invalid("CHD files with a parent are not supported");
+        }
+
+        let compressors = [be32(16), be32(20), be32(24), be32(28)];
+        let logical_bytes = be64(32);
+        let map_offset = be64(40);
+        let meta_offset = be64(48);
+        let hunk_bytes = be32(56) as usize;
+        let unit_bytes = be32(60) as usize;
+
+        if unit_bytes != FRAME_SIZE || hunk_bytes == 0 || !hunk_bytes.is_multiple_of(FRAME_SIZE) {
+            return invalid("not a CD CHD file");
+        }
+        let hunk_count = logical_bytes.div_ceil(hunk_bytes as u64) as usize;
+
+        let mut chd = Self {
+            file,
+            compressors,
+            hunk_bytes,
+            meta_offset,
+            map: Vec::new(),
+            cached_hunk: None,
+            hunk_buffer: vec![0; hunk_bytes],
+        };
+        chd.map = if compressors[0] == 0 {
+            chd.read_uncompressed_map(map_offset, hunk_count)?
+        } else {
+            chd.read_compressed_map(map_offset, hunk_count)?
+        };
+
+        Ok(chd)
+    }
+
+    fn read_at(&mut self, offset: u64, length: usize) -> Result<Vec<u8>, Error> {
+        let mut data = vec![0; length];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn read_uncompressed_map(
+        &mut self,
+        map_offset: u64,
+        hunk_count: usize,
+    ) -> Result<Vec<MapEntry>, Error> {
+        let raw_map = self.read_at(map_offset, hunk_count * 4)?;
+
+        Ok(raw_map
+            .chunks_exact(4)
+            .map(
+                |entry| match u32::from_be_bytes(entry.try_into().unwrap()) {
+                    0 => MapEntry::Zeros,
+                    block => MapEntry::Uncompressed {
+                        offset: block as u64 * self.hunk_bytes as u64,
+                    },
+                },
+            )
+            .collect())
+    }
+
+    fn read_compressed_map(
+        &mut self,
+        map_offset: u64,
+        hunk_count: usize,
+    ) -> Result<Vec<MapEntry>, Error> {
+        let header = self.read_at(map_offset, 16)?;
+        let map_bytes = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let mut first_offset = [0; 8];
+        first_offset[2..].copy_from_slice(&header[4..10]);
+        let first_offset = u64::from_be_bytes(first_offset);
+        let length_bits = header[12] as u32;
+        let self_bits = header[13] as u32;
+        let parent_bits = header[14] as u32;
+
+        let compressed = self.read_at(map_offset + 16, map_bytes)?;
+        let mut bits = BitReader::new(&compressed);
+        let huffman = Huffman::import_tree_rle(&mut bits)?;
+
+        // first, all the compression types, with run length encoding
+        let mut types = Vec::with_capacity(hunk_count);
+        let mut last_type = 0;
+        let mut repeat = 0;
+        for _ in 0..hunk_count {
+            if repeat > 0 {
+                repeat -= 1;
+            } else {
+                match huffman.decode(&mut bits) {
+                    COMPRESSION_RLE_SMALL => repeat = 2 + huffman.decode(&mut bits) as usize,
+                    COMPRESSION_RLE_LARGE => {
+                        repeat = 2 + 16 + ((huffman.decode(&mut bits) as usize) << 4);
+                        repeat += huffman.decode(&mut bits) as usize;
+                    }
+                    value => last_type = value,
+                }
+            }
+            types.push(last_type);
+        }
+
+        // then, the offsets and lengths of every hunk
+        let mut current_offset = first_offset;
+        let mut last_self = 0;
+        let mut map = Vec::with_capacity(hunk_count);
+        for (hunk, compression_type) in types.into_iter().enumerate() {
+            let entry = match compression_type {
+                0..=COMPRESSION_TYPE_3 => {
+                    let length = bits.read(length_bits) as u32;
+                    // crc16
+                    bits.read(16);
+                    let codec = self.compressors[compression_type as usize];
+                    if codec == 0 {
+                        return invalid("hunk uses a missing compressor");
+                    }
+                    let entry = MapEntry::Compressed {
+                        codec,
+                        offset: current_offset,
+                        length,
+                    };
+                    current_offset += length as u64;
+                    entry
+                }
+                COMPRESSION_NONE => {
+                    // crc16
+                    bits.read(16);
+                    let entry = MapEntry::Uncompressed {
+                        offset: current_offset,
+                    };
+                    current_offset += self.hunk_bytes as u64;
+                    entry
+                }
+                COMPRESSION_SELF | COMPRESSION_SELF_0 | COMPRESSION_SELF_1 => {
+                    if compression_type == COMPRESSION_SELF {
+                        last_self = bits.read(self_bits) as usize;
+                    } else {
+                        last_self += (compression_type - COMPRESSION_SELF_0) as usize;
+                    }
+                    if last_self >= hunk {
+                        return invalid("hunk is a copy of a later hunk");
+                    }
+                    MapEntry::CopyOf(last_self)
+                }
+                COMPRESSION_PARENT => {
+                    bits.read(parent_bits);
+                    return invalid("hunk is stored in a parent CHD");
+                }
+                COMPRESSION_PARENT_SELF | COMPRESSION_PARENT_0 | COMPRESSION_PARENT_1 => {
+                    return invalid("hunk is stored in a parent CHD");
+                }
+                _ => return invalid(format!("invalid compression type {}", compression_type)),
+            };
+            map.push(entry);
+        }
+
+        if bits.overflowed() {
+            return invalid("CHD map is truncated");
+        }
+
+        Ok(map)
+    }
+
+    /// All the metadata entries with `tag`, in order
+    fn read_metadata(&mut self, tag: u32) -> Result<Vec<Vec<u8>>, Error> {
+        let mut entries = Vec::new();
+        let mut offset = self.meta_offset;
+        while offset != 0 {
+            let header = self.read_at(offset, 16)?;
+            let entry_tag = u32::from_be_bytes(header[0..4].try_into().unwrap());
+            let length = u32::from_be_bytes(header[4..8].try_into().unwrap()) & 0xFFFFFF;
+            let next = u64::from_be_bytes(header[8..16].try_into().unwrap());
+
+            if entry_tag == tag {
+                entries.push(self.read_at(offset + 16, length as usize)?);
+            }
+            if next != 0 && next <= offset {
+                return invalid("CHD metadata has a loop");
+            }
+            offset = next;
+        }
+        Ok(entries)
+    }
+
+    fn read_hunk(&mut self, hunk: usize) -> Result<&[u8], Error> {
+        if self.cached_hunk != Some(hunk) {
+            let mut buffer = std::mem::take(&mut self.hunk_buffer);
+            self.cached_hunk = None;
+            let result = self.decompress_hunk(hunk, &mut buffer);
+            self.hunk_buffer = buffer;
+            result?;
+            self.cached_hunk = Some(hunk);
+        }
+        Ok(&self.hunk_buffer)
+    }
+
+    fn decompress_hunk(&mut self, hunk: usize, out: &mut [u8]) -> Result<(), Error> {
+        let Some(&entry) = self.map.get(hunk) else {
+            return invalid(format!("hunk {} is outside of the CHD", hunk));
+        };
+
+        match entry {
+            MapEntry::Compressed {
+                codec,
+                offset,
+                length,
+            } => {
+                let data = self.read_at(offset, length as usize)?;
+                decompress_cd_hunk(codec, &data, out)
+            }
+            MapEntry::Uncompressed { offset } => {
+                self.file.seek(SeekFrom::Start(offset))?;
+                self.file.read_exact(out)?;
+                Ok(())
+            }
+            MapEntry::CopyOf(source) => self.decompress_hunk(source, out),
+            MapEntry::Zeros => {
+                out.fill(0);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Where a region of the disc is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ChdSource {
+    /// The frame where the region starts
+    frame: usize,
+    audio: bool,
+}
+
+/// A CHD file, its hunks are decompressed when their sectors are read
+pub struct ChdImage<R = io::BufReader<fs::File>> {
+    chd: Chd<R>,
+    layout: TrackLayout<ChdSource>,
+}
+
+impl ChdImage {
+    pub fn open(path: &Path) -> Result<Self, PsxError> {
+        let open = || {
+            let file = io::BufReader::new(fs::File::open(path)?);
+            Self::new(Chd::open(file)?)
+        };
+
+        open().map_err(|e| match e {
+            Error::Io(source) => PsxError::CouldNotLoadDisk {
+                path: path.to_owned(),
+                source,
+            },
+            Error::Invalid(reason) => PsxError::InvalidDisk {
+                path: path.to_owned(),
+                reason,
+            },
+        })
+    }
+}
+
+impl<R: Read + Seek> ChdImage<R> {
+    fn new(mut chd: Chd<R>) -> Result<Self, Error> {
+        let mut metadata = chd.read_metadata(METADATA_CD_TRACK_2)?;
+        if metadata.is_empty() {
+            metadata = chd.read_metadata(METADATA_CD_TRACK)?;
+        }
+        if metadata.is_empty() {
+            return invalid("no CD track metadata");
+        }
+
+        let mut layout = TrackLayout::default();
+        let mut frame = 0;
+        for (i, entry) in metadata.iter().enumerate() {
+            let track = TrackMetadata::parse(&String::from_utf8_lossy(entry))?;
+
+            let (pregap, index1_offset) = if track.pregap_in_file {
+                (0, track.pregap)
+            } else if i == 0 {
+                // the pregap of the first track is the lead-in
+                (0, 0)
+            } else {
+                (track.pregap, 0)
+            };
+
+            layout.push_track(
+                ImageTrack {
+                    number: track.number,
+                    kind: track.kind,
+                    pregap,
+                    length: track.frames,
+                    index1_offset,
+                    postgap: track.postgap,
+                },
+                ChdSource {
+                    frame,
+                    audio: track.kind == TrackKind::Audio,
+                },
+            );
+            frame += track.frames.next_multiple_of(TRACK_PADDING);
+        }
+
+        Ok(Self { chd, layout })
+    }
+}
+
+impl<R: Read + Seek + Send> DiscImage for ChdImage<R> {
+    fn tracks(&self) -> &[Track] {
+        &self.layout.tracks
+    }
+
+    fn read_sector(&mut self, position: usize) -> io::Result<[u8; SECTOR_SIZE]> {
+        let mut sector = [0; SECTOR_SIZE];
+        let Some((source, offset)) = self.layout.region_at(position) else {
+            return Ok(sector);
+        };
+
+        let frames_per_hunk = self.chd.hunk_bytes / FRAME_SIZE;
+        let frame = source.frame + offset;
+        let hunk = self
+            .chd
+            .read_hunk(frame / frames_per_hunk)
+            .map_err(|e| match e {
+                Error::Io(e) => e,
+                Error::Invalid(reason) => io::Error::new(io::ErrorKind::InvalidData, reason),
+            })?;
+        let frame_start = (frame % frames_per_hunk) * FRAME_SIZE;
+        sector.copy_from_slice(&hunk[frame_start..frame_start + SECTOR_SIZE]);
+
+        // audio is stored big endian
+        if source.audio {
+            sector
+                .chunks_exact_mut(2)
+                .for_each(|sample| sample.swap(0, 1));
+        }
+
+        Ok(sector)
+    }
+}
+
+/// Decompresses the sectors of a hunk with one of the CD codecs, the subchannel data
+/// is not used, so it is left as zeros
+fn decompress_cd_hunk(codec: u32, data: &[u8], out: &mut [u8]) -> Result<(), Error> {
+    let frames = out.len() / FRAME_SIZE;
+    let mut sectors = vec![0; frames * SECTOR_SIZE];
+
+    match codec {
+        CODEC_CD_ZLIB | CODEC_CD_LZMA => {
+            // a bitmap of the sectors that had their sync and ECC removed,
+            // then the length of the sectors data, and the subchannel data after it
+            let ecc_bytes = frames.div_ceil(8);
+            let length_bytes = if out.len() < 65536 { 2 } else { 3 };
+            let header_bytes = ecc_bytes + length_bytes;
+            let Some(header) = data.get(..header_bytes) else {
+                return invalid("CD hunk is truncated");
+            };
+            let base_length = header[ecc_bytes..]
+                .iter()
+                .fold(0, |length, &b| (length << 8) | b as usize);
+            let Some(base) = data.get(header_bytes..header_bytes + base_length) else {
+                return invalid("CD hunk is truncated");
+            };
+
+            if codec == CODEC_CD_ZLIB {
+                flate2::read::DeflateDecoder::new(base)
+                    .read_exact(&mut sectors)
+                    .map_err(|e| Error::Invalid(format!("invalid deflate data: {}", e)))?;
+            } else {
+                decompress_lzma(base, &mut sectors)?;
+            }
+
+            for (i, sector) in sectors.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+                if header[i / 8] & (1 << (i % 8)) != 0 {
+                    sector[..12].copy_from_slice(&ecc::SYNC_PATTERN);
+                    ecc::generate(sector);
+                }
+            }
+        }
+        CODEC_CD_FLAC => decompress_flac(data, &mut sectors)?,
+        _ => {
+            return invalid(format!(
+                "unsupported CHD codec {}",
+                String::from_utf8_lossy(&codec.to_be_bytes())
+            ))
+        }
+    }
+
+    out.fill(0);
+    for (frame, sector) in out
+        .chunks_exact_mut(FRAME_SIZE)
+        .zip(sectors.chunks_exact(SECTOR_SIZE))
+    {
+        frame[..SECTOR_SIZE].copy_from_slice(sector);
+    }
+
+    Ok(())
+}
+
+/// LZMA without the header, using the properties of the CHD encoder
+fn decompress_lzma(data: &[u8], out: &mut [u8]) -> Result<(), Error> {
+    // lc=3, lp=0, pb=2, any dictionary that is as large as the output will do
+    let mut header = vec![(2 * 5) * 9 + 3];
+    header.extend_from_slice(&(out.len() as u32).to_le_bytes());
+
+    let mut output = Vec::with_capacity(out.len());
+    lzma_rs::lzma_decompress_with_options(
+        &mut header.chain(data),
+        &mut output,
+        &lzma_rs::decompress::Options {
+            unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(out.len() as u64)),
+            ..Default::default()
+        },
+    )
+    .map_err(|e| Error::Invalid(format!("invalid lzma data: {}", e)))?;
+
+    out.copy_from_slice(&output);
+    Ok(())
+}
+
+/// FLAC frames without the stream header, stereo 16bit samples stored big endian
+fn decompress_flac(data: &[u8], out: &mut [u8]) -> Result<(), Error> {
+    let mut reader = claxon::frame::FrameReader::new(claxon::input::BufferedReader::new(data));
+    let mut buffer = Vec::new();
+    let mut samples = out.chunks_exact_mut(4);
+
+    loop {
+        let block = reader
+            .read_next_or_eof(buffer)
+            .map_err(|e| Error::Invalid(format!("invalid flac data: {}", e)))?
+            .ok_or(Error::Invalid("flac data is truncated".into()))?;
+        if block.channels() != 2 {
+            return invalid("flac data is not stereo");
+        }
+
+        for (left, right) in block.stereo_samples() {
+            let Some(sample) = samples.next() else {
+                return Ok(());
+            };
+            sample[..2].copy_from_slice(&(left as i16).to_be_bytes());
+            sample[2..].copy_from_slice(&(right as i16).to_be_bytes());
+        }
+        if samples.len() == 0 {
+            return Ok(());
+        }
+        buffer = block.into_buffer();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    const FRAMES_PER_HUNK: usize = 8;
+    const HUNK_BYTES: usize = FRAMES_PER_HUNK * FRAME_SIZE;
+
+    /// Writes bits from the most significant bit, like [`BitReader`] reads them
+    #[derive(Default)]
+    struct BitWriter {
+        data: Vec<u8>,
+        position: usize,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u64, count: u32) {
+            for i in (0..count).rev() {
+                if self.position.is_multiple_of(8) {
+                    self.data.push(0);
+                }
+                let bit = ((value >> i) & 1) as u8;
+                *self.data.last_mut().unwrap() |= bit << (7 - self.position % 8);
+                self.position += 1;
+            }
+        }
+    }
+
+    /// Compresses the sectors of a hunk with `cdzl` or `cdlz`, without subchannel data
+    fn compress_cd_hunk(codec: u32, sectors: &[u8]) -> Vec<u8> {
+        let base = if codec == CODEC_CD_ZLIB {
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(sectors).unwrap();
+            encoder.finish().unwrap()
+        } else {
+            let mut out = Vec::new();
+            lzma_rs::lzma_compress_with_options(
+                &mut &sectors[..],
+                &mut out,
+                &lzma_rs::compress::Options {
+                    unpacked_size: lzma_rs::compress::UnpackedSize::SkipWritingToHeader,
+                },
+            )
+            .unwrap();
+            // remove the properties and dictionary size
+            out.split_off(5)
+        };
+
+        // no sectors had their ECC removed
+        let mut hunk = vec![0; FRAMES_PER_HUNK.div_ceil(8)];
+        hunk.extend((base.len() as u16).to_be_bytes());
+        hunk.extend(base);
+        hunk
+    }
+
+    #[test]
+    fn compressed_cd_hunks() {
+        // one audio track of 12 frames, padded to 16 frames in 2 hunks
+        let sectors: Vec<u8> = (0..16 * SECTOR_SIZE).map(|i| (i % 251) as u8).collect();
+        let hunks = [
+            compress_cd_hunk(CODEC_CD_ZLIB, &sectors[..8 * SECTOR_SIZE]),
+            compress_cd_hunk(CODEC_CD_LZMA, &sectors[8 * SECTOR_SIZE..]),
+        ];
+        let metadata =
+            b"TRACK:1 TYPE:AUDIO SUBTYPE:NONE FRAMES:12 PREGAP:0 PGTYPE:MODE1 PGSUB:RW POSTGAP:0\0";
+
+        // all 16 huffman codes are 4 bits, then the compression types 0 and 1
+        let mut map = BitWriter::default();
+        for _ in 0..16 {
+            map.write(4, 4);
+        }
+        map.write(0, 4);
+        map.write(1, 4);
+        for hunk in &hunks {
+            map.write(hunk.len() as u64, 24);
+            // crc16
+            map.write(0, 16);
+        }
+
+        let map_offset = HEADER_V5_SIZE;
+        let meta_offset = map_offset + 16 + map.data.len();
+        let hunks_offset = meta_offset + 16 + metadata.len();
+
+        let mut file = Vec::new();
+        file.extend(HEADER_TAG);
+        file.extend((HEADER_V5_SIZE as u32).to_be_bytes());
+        file.extend(5u32.to_be_bytes());
+        for codec in [CODEC_CD_ZLIB, CODEC_CD_LZMA, 0, 0] {
+            file.extend(codec.to_be_bytes());
+        }
+        file.extend(((2 * HUNK_BYTES) as u64).to_be_bytes());
+        file.extend((map_offset as u64).to_be_bytes());
+        file.extend((meta_offset as u64).to_be_bytes());
+        file.extend((HUNK_BYTES as u32).to_be_bytes());
+        file.extend((FRAME_SIZE as u32).to_be_bytes());
+        // no hashes or parent
+        file.resize(HEADER_V5_SIZE, 0);
+
+        file.extend((map.data.len() as u32).to_be_bytes());
+        file.extend(&(hunks_offset as u64).to_be_bytes()[2..]);
+        // crc16, length bits, self bits, parent bits, reserved
+        file.extend([0, 0, 24, 0, 0, 0]);
+        file.extend(&map.data);
+
+        file.extend(METADATA_CD_TRACK_2.to_be_bytes());
+        file.extend((metadata.len() as u32).to_be_bytes());
+        file.extend(0u64.to_be_bytes());
+        file.extend(metadata);
+
+        for hunk in &hunks {
+            file.extend(hunk);
+        }
+
+        let mut image = ChdImage::new(Chd::open(Cursor::new(file)).unwrap()).unwrap();
+
+        assert_eq!(image.sectors(), 12);
+        assert_eq!(image.tracks().len(), 1);
+        assert_eq!(image.tracks()[0].kind, TrackKind::Audio);
+
+        // audio is converted to little endian
+        let mut expected = sectors[..12 * SECTOR_SIZE].to_vec();
+        expected
+            .chunks_exact_mut(2)
+            .for_each(|sample| sample.swap(0, 1));
+        for (i, expected) in expected.chunks_exact(SECTOR_SIZE).enumerate() {
+            assert!(image.read_sector(i).unwrap() == expected);
+        }
+    }
+}
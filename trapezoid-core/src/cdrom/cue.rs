@@ -0,0 +1,413 @@
+//! Cue sheet parsing, and building the disc layout from the `BINARY` files it references.
+
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use super::{
+    disc::{DiscImage, ImageTrack, Track, TrackKind, TrackLayout, SECTOR_SIZE},
+    ecm::EcmFile,
+};
+use crate::PsxError;
+
+#[derive(Debug, PartialEq, Eq)]
+struct CueTrack {
+    number: u8,
+    kind: TrackKind,
+    pregap: usize,
+    postgap: usize,
+    /// (index number, position in the file)
+    indices: Vec<(u8, usize)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct CueFile {
+    name: String,
+    tracks: Vec<CueTrack>,
+}
+
+/// Parses `mm:ss:ff` into sectors
+fn parse_msf(msf: &str) -> Option<usize> {
+    let mut parts = msf.split(':').map(|p| p.parse::<usize>().ok());
+    let minutes = parts.next()??;
+    let seconds = parts.next()??;
+    let frames = parts.next()??;
+    if parts.next().is_some() || seconds >= 60 || frames >= 75 {
+        return None;
+    }
+    Some((minutes * 60 + seconds) * 75 + frames)
+}
+
+/// Splits a cue line into words, a quoted string is one word without the quotes
+fn split_words(line: &str) -> Result<Vec<&str>, String> {
+    let mut words = Vec::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted
+                .find('"')
+                .ok_or_else(|| format!("Unterminated quotes in `{}`", line.trim()))?;
+            words.push(&quoted[..end]);
+            rest = quoted[end + 1..].trim_start();
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            words.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+    }
+    Ok(words)
+}
+
+fn last_track(files: &mut [CueFile]) -> Option<&mut CueTrack> {
+    files.last_mut().and_then(|file| file.tracks.last_mut())
+}
+
+fn parse_cue(content: &str) -> Result<Vec<CueFile>, String> {
+    let mut files: Vec<CueFile> = Vec::new();
+
+    for (line_number, line) in content.lines().enumerate() {
+        let error = |reason: &str| format!("line {}: {}", line_number + 1, reason);
+        let words = split_words(line).map_err(|e| error(&e))?;
+
+        let Some(&command) = words.first() else {
+            continue;
+        };
+        let before_track = || error(&format!("{} before TRACK", command));
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" => {
+                let [_, name, file_type] = words[..] else {
+                    return Err(error("FILE expects a name and a type"));
+                };
+                if !file_type.eq_ignore_ascii_case("BINARY") {
+                    return Err(error(&format!("unsupported file type {}", file_type)));
+                }
+                files.push(CueFile {
+                    name: name.to_string(),
+                    tracks: Vec::new(),
+                });
+            }
+            "TRACK" => {
+                let [_, number, mode] = words[..] else {
+                    return Err(error("TRACK expects a number and a mode"));
+                };
+                let number = number
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|n| (1..=99).contains(n))
+                    .ok_or_else(|| error("invalid track number"))?;
+                let kind = match mode.to_ascii_uppercase().as_str() {
+                    "AUDIO" => TrackKind::Audio,
+                    "MODE1/2352" | "MODE2/2352" => TrackKind::Data,
+                    _ => return Err(error(&format!("unsupported track mode {}", mode))),
+                };
+                let file = files.last_mut().ok_or_else(|| error("TRACK before FILE"))?;
+                file.tracks.push(CueTrack {
+                    number,
+                    kind,
+                    pregap: 0,
+                    postgap: 0,
+                    indices: Vec::new(),
+                });
+            }
+            "INDEX" => {
+                let [_, number, msf] = words[..] else {
+                    return Err(error("INDEX expects a number and a position"));
+                };
+                let number = number
+                    .parse::<u8>()
+                    .map_err(|_| error("invalid index number"))?;
+                let position = parse_msf(msf).ok_or_else(|| error("invalid index position"))?;
+                last_track(&mut files)
+                    .ok_or_else(before_track)?
+                    .indices
+                    .push((number, position));
+            }
+            "PREGAP" | "POSTGAP" => {
+                let gap = words
+                    .get(1)
+                    .and_then(|msf| parse_msf(msf))
+                    .ok_or_else(|| error("invalid gap length"))?;
+                let track = last_track(&mut files).ok_or_else(before_track)?;
+                if command.eq_ignore_ascii_case("PREGAP") {
+                    track.pregap = gap;
+                } else {
+                    track.postgap = gap;
+                }
+            }
+            // metadata that doesn't affect the layout
+            "REM" | "CATALOG" | "CDTEXTFILE" | "TITLE" | "PERFORMER" | "SONGWRITER" | "FLAGS"
+            | "ISRC" => {}
+            _ => return Err(error(&format!("unknown command {}", command))),
+        }
+    }
+
+    if files.iter().all(|file| file.tracks.is_empty()) {
+        return Err("no tracks".to_string());
+    }
+
+    Ok(files)
+}
+
+/// Where a region of the disc is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileSource {
+    file: usize,
+    /// The sector in the file where the region starts
+    sector: usize,
+}
+
+/// Lays out the files one after the other, `file_sectors` gives the size of a file by its index
+fn build_layout<E>(
+    files: &[CueFile],
+    mut file_sectors: impl FnMut(usize) -> Result<usize, E>,
+    invalid: impl Fn(String) -> E,
+) -> Result<TrackLayout<FileSource>, E> {
+    let mut layout = TrackLayout::default();
+
+    for (file_index, file) in files.iter().enumerate() {
+        let file_sectors = file_sectors(file_index)?;
+
+        for (i, track) in file.tracks.iter().enumerate() {
+            let index = |n: u8| {
+                track
+                    .indices
+                    .iter()
+                    .find(|(number, _)| *number == n)
+                    .map(|(_, position)| *position)
+            };
+            let index1 = index(1)
+                .ok_or_else(|| invalid(format!("track {} doesn't have INDEX 01", track.number)))?;
+            let track_file_start = index(0).unwrap_or(index1).min(index1);
+            let track_file_end = match file.tracks.get(i + 1) {
+                Some(next) => next
+                    .indices
+                    .iter()
+                    .map(|(_, position)| *position)
+                    .min()
+                    .unwrap_or(file_sectors),
+                None => file_sectors,
+            };
+            if track_file_end < index1 || track_file_end > file_sectors {
+                return Err(invalid(format!(
+                    "track {} is outside of its file",
+                    track.number
+                )));
+            }
+
+            layout.push_track(
+                ImageTrack {
+                    number: track.number,
+                    kind: track.kind,
+                    pregap: track.pregap,
+                    length: track_file_end - track_file_start,
+                    index1_offset: index1 - track_file_start,
+                    postgap: track.postgap,
+                },
+                FileSource {
+                    file: file_index,
+                    sector: track_file_start,
+                },
+            );
+        }
+    }
+
+    Ok(layout)
+}
+
+trait BinFile: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> BinFile for T {}
+
+/// Opens a `BINARY` file and gets its size, it can be ECM encoded,
+/// in which case the cue sheet can refer to it without the `.ecm` extension
+fn open_bin_file(path: &Path) -> Result<(Box<dyn BinFile>, u64), PsxError> {
+    let is_ecm = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("ecm"));
+    let mut ecm_path = path.as_os_str().to_owned();
+    ecm_path.push(".ecm");
+    let ecm_path = Path::new(&ecm_path);
+
+    let path = if !is_ecm && !path.exists() && ecm_path.exists() {
+        ecm_path
+    } else {
+        path
+    };
+    log::info!("Opening bin file: {:?}", path);
+
+    let io_error = |source| PsxError::CouldNotLoadDisk {
+        path: path.to_owned(),
+        source,
+    };
+    let file = fs::File::open(path).map_err(io_error)?;
+    if is_ecm || path == ecm_path {
+        let file = EcmFile::new(file).map_err(io_error)?;
+        let size = file.len();
+        Ok((Box::new(file), size))
+    } else {
+        let size = file.metadata().map_err(io_error)?.len();
+        Ok((Box::new(file), size))
+    }
+}
+
+/// A cue sheet and the `BINARY` files it references, read on demand
+pub struct CueBinImage {
+    files: Vec<Box<dyn BinFile>>,
+    layout: TrackLayout<FileSource>,
+}
+
+impl CueBinImage {
+    /// Opens the cue sheet at `cue_file` and all the files it references
+    pub fn open(cue_file: &Path) -> Result<Self, PsxError> {
+        let invalid = |reason: String| PsxError::InvalidDisk {
+            path: cue_file.to_owned(),
+            reason,
+        };
+
+        let content =
+            fs::read_to_string(cue_file).map_err(|source| PsxError::CouldNotLoadDisk {
+                path: cue_file.to_owned(),
+                source,
+            })?;
+        let cue_files =
+            parse_cue(&content).map_err(|e| invalid(format!("Invalid cue file: {}", e)))?;
+
+        let directory = cue_file.parent().unwrap_or(Path::new(""));
+        let mut files = Vec::with_capacity(cue_files.len());
+        let mut file_sizes = Vec::with_capacity(cue_files.len());
+        for cue_file in &cue_files {
+            let (file, size) = open_bin_file(&directory.join(&cue_file.name))?;
+            file_sizes.push(size as usize / SECTOR_SIZE);
+            files.push(file);
+        }
+
+        let layout = build_layout(&cue_files, |i| Ok(file_sizes[i]), invalid)?;
+
+        Ok(Self { files, layout })
+    }
+
+    /// Opens a lone `.bin` file with 2352 bytes sectors as a single data track,
+    /// or a `.ecm` encoded one
+    pub fn open_bin(bin_file: &Path) -> Result<Self, PsxError> {
+        let invalid = |reason: String| PsxError::InvalidDisk {
+            path: bin_file.to_owned(),
+            reason,
+        };
+
+        let (file, size) = open_bin_file(bin_file)?;
+        if size == 0 || !size.is_multiple_of(SECTOR_SIZE as u64) {
+            return Err(invalid(format!(
+                "size is not a multiple of {} bytes sectors",
+                SECTOR_SIZE
+            )));
+        }
+
+        let cue_files = [CueFile {
+            name: bin_file
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            tracks: vec![CueTrack {
+                number: 1,
+                kind: TrackKind::Data,
+                pregap: 0,
+                postgap: 0,
+                indices: vec![(1, 0)],
+            }],
+        }];
+        let layout = build_layout(&cue_files, |_| Ok(size as usize / SECTOR_SIZE), invalid)?;
+
+        Ok(Self {
+            files: vec![file],
+            layout,
+        })
+    }
+}
+
+impl DiscImage for CueBinImage {
+    fn tracks(&self) -> &[Track] {
+        &self.layout.tracks
+    }
+
+    fn read_sector(&mut self, position: usize) -> io::Result<[u8; SECTOR_SIZE]> {
+        let mut sector = [0; SECTOR_SIZE];
+        if let Some((source, offset)) = self.layout.region_at(position) {
+            let file = &mut self.files[source.file];
+            file.seek(SeekFrom::Start(
+                ((source.sector + offset) * SECTOR_SIZE) as u64,
+            ))?;
+            file.read_exact(&mut sector)?;
+        }
+        Ok(sector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUE: &str = r#"
+REM a game with audio tracks
+FILE "Game (Track 1).bin" BINARY
+  TRACK 01 MODE2/2352
+    INDEX 01 00:00:00
+FILE "Game (Track 2).bin" BINARY
+  TRACK 02 AUDIO
+    INDEX 00 00:00:00
+    INDEX 01 00:00:10
+  TRACK 03 AUDIO
+    PREGAP 00:00:05
+    INDEX 01 00:00:30
+"#;
+
+    #[test]
+    fn multiple_files_and_gaps() {
+        let files = parse_cue(CUE).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1].name, "Game (Track 2).bin");
+
+        let layout = build_layout(
+            &files,
+            |i| -> Result<_, String> { Ok(if i == 0 { 100 } else { 50 }) },
+            |e| e,
+        )
+        .unwrap();
+
+        let starts: Vec<_> = layout
+            .tracks
+            .iter()
+            .map(|t| (t.number, t.kind, t.pregap_start, t.start, t.length))
+            .collect();
+        assert_eq!(
+            starts,
+            [
+                (1, TrackKind::Data, 0, 0, 100),
+                (2, TrackKind::Audio, 100, 110, 20),
+                (3, TrackKind::Audio, 130, 135, 20),
+            ]
+        );
+        assert_eq!(layout.sectors, 155);
+        // the gaps are not stored in the files
+        assert_eq!(layout.region_at(132), None);
+        assert_eq!(
+            layout.region_at(140),
+            Some((
+                FileSource {
+                    file: 1,
+                    sector: 30
+                },
+                5
+            ))
+        );
+    }
+
+    #[test]
+    fn invalid_cue() {
+        assert!(parse_cue("TRACK 01 AUDIO").is_err());
+        assert!(parse_cue("FILE \"a.bin\" BINARY\nTRACK 01 MODE1/2048").is_err());
+        assert!(parse_cue("FILE \"a.bin BINARY").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! The disc image abstraction used by the CD-ROM controller, and its track table.
+//!
+//! All positions here are in sectors from `00:02:00`, i.e. the first sector
+//! after the 2 seconds lead-in, which is the start of `INDEX 01` of the first track.
+
+use std::io;
+
+pub const SECTOR_SIZE: usize = 2352;
+/// Number of sectors in the 2 seconds lead-in before the first track
+pub const LEAD_IN_SECTORS: usize = 150;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackKind {
+    Data,
+    Audio,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Track {
+    pub number: u8,
+    pub kind: TrackKind,
+    /// Where the pregap (`PREGAP` or `INDEX 00`) starts, equal to `start` if there is none
+    pub pregap_start: usize,
+    /// Where `INDEX 01` starts
+    pub start: usize,
+    /// Number of sectors from `start` until the next track's pregap or the end of the disc
+    pub length: usize,
+}
+
+impl Track {
+    pub fn end(&self) -> usize {
+        self.start + self.length
+    }
+}
+
+/// A CD image that the CD-ROM controller reads sectors from on demand.
+///
+/// Implemented for all the supported image formats, and can be implemented
+/// to provide discs from other sources, see [`crate::Psx::set_disc_image`].
+pub trait DiscImage: Send {
+    /// The tracks of the disc in order, they must cover the disc without holes
+    fn tracks(&self) -> &[Track];
+
+    /// Reads the raw 2352 bytes of the sector at `position`, which is in `0..self.sectors()`
+    fn read_sector(&mut self, position: usize) -> io::Result<[u8; SECTOR_SIZE]>;
+
+    /// The 12 bytes of subchannel Q of the sector at `position`, with its CRC,
+    /// generated from the track table by default
+    fn subchannel_q(&mut self, position: usize) -> [u8; 12] {
+        super::subchannel::generate(self, position)
+    }
+
+    /// Total number of sectors, not including the lead-in
+    fn sectors(&self) -> usize {
+        self.tracks().last().map_or(0, Track::end)
+    }
+
+    /// The track containing `position`, including its pregap
+    fn track_at(&self, position: usize) -> Option<&Track> {
+        if position >= self.sectors() {
+            return None;
+        }
+        self.tracks()
+            .iter()
+            .rev()
+            .find(|track| track.pregap_start <= position)
+    }
+
+    fn track(&self, number: u8) -> Option<&Track> {
+        self.tracks().iter().find(|track| track.number == number)
+    }
+}
+
+/// A part of the disc that is stored in the image, `source` identifies where
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region<S> {
+    pub start: usize,
+    pub length: usize,
+    pub source: S,
+}
+
+/// A track as stored in an image, before it's placed on the disc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageTrack {
+    pub number: u8,
+    pub kind: TrackKind,
+    /// Silent sectors before the track, which are not stored in the image
+    pub pregap: usize,
+    /// Number of sectors stored in the image
+    pub length: usize,
+    /// Where `INDEX 01` is in the stored sectors, anything before it is also part of the pregap
+    pub index1_offset: usize,
+    /// Silent sectors after the track, which are not stored in the image
+    pub postgap: usize,
+}
+
+/// Builds the track table of an image, laying out the tracks one after the other,
+/// and remembering which parts are stored in the image
+#[derive(Debug)]
+pub struct TrackLayout<S> {
+    pub tracks: Vec<Track>,
+    pub regions: Vec<Region<S>>,
+    pub sectors: usize,
+}
+
+impl<S> Default for TrackLayout<S> {
+    fn default() -> Self {
+        Self {
+            tracks: Vec::new(),
+            regions: Vec::new(),
+            sectors: 0,
+        }
+    }
+}
+
+impl<S: Copy> TrackLayout<S> {
+    /// Appends a track after the previous one, its data is stored in `source`
+    pub fn push_track(&mut self, track: ImageTrack, source: S) {
+        let pregap_start = self.sectors;
+        self.sectors += track.pregap;
+        self.regions.push(Region {
+            start: self.sectors,
+            length: track.length,
+            source,
+        });
+        let start = self.sectors + track.index1_offset;
+        self.sectors += track.length + track.postgap;
+
+        self.tracks.push(Track {
+            number: track.number,
+            kind: track.kind,
+            pregap_start,
+            start,
+            length: self.sectors - start,
+        });
+    }
+
+    /// The source of `position` and the offset into it, `None` for the gaps
+    pub fn region_at(&self, position: usize) -> Option<(S, usize)> {
+        self.regions
+            .iter()
+            .find(|region| (region.start..region.start + region.length).contains(&position))
+            .map(|region| (region.source, position - region.start))
+    }
+}
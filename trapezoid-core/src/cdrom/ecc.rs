@@ -0,0 +1,131 @@
+//! Regeneration of the error correction codes of CD-ROM sectors,
+//! for image formats that strip them to save space.
+
+use super::disc::SECTOR_SIZE;
+
+/// The 12 bytes at the start of every data sector
+pub const SYNC_PATTERN: [u8; 12] = [
+    0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+];
+
+const P_PARITY_OFFSET: usize = 0x81C;
+const Q_PARITY_OFFSET: usize = 0x8C8;
+
+/// Multiplication by 2 in GF(2^8), and its inverse after xoring with the input
+const fn ecc_tables() -> ([u8; 256], [u8; 256]) {
+    let mut f = [0; 256];
+    let mut b = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let j = ((i << 1) ^ (if i & 0x80 != 0 { 0x11D } else { 0 })) as u8;
+        f[i] = j;
+        b[i ^ j as usize] = i as u8;
+        i += 1;
+    }
+    (f, b)
+}
+
+const ECC_TABLES: ([u8; 256], [u8; 256]) = ecc_tables();
+
+/// The CRC32 table of the error detection code, with the polynomial `0xD8018001`
+const fn edc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut edc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            edc = (edc >> 1) ^ (if edc & 1 != 0 { 0xD8018001 } else { 0 });
+            bit += 1;
+        }
+        table[i] = edc;
+        i += 1;
+    }
+    table
+}
+
+const EDC_TABLE: [u32; 256] = edc_table();
+
+/// The error detection code of `data`, stored little endian after it
+pub fn edc(data: &[u8]) -> u32 {
+    data.iter().fold(0, |edc, &b| {
+        (edc >> 8) ^ EDC_TABLE[((edc ^ b as u32) & 0xFF) as usize]
+    })
+}
+
+/// Computes one of the two parity blocks of the data starting at the header (offset 12)
+fn compute_parity(
+    data: &[u8],
+    major_count: usize,
+    minor_count: usize,
+    major_mult: usize,
+    minor_inc: usize,
+    dest: &mut [u8],
+) {
+    let (f_lut, b_lut) = &ECC_TABLES;
+    let size = major_count * minor_count;
+
+    for major in 0..major_count {
+        let mut index = (major >> 1) * major_mult + (major & 1);
+        let mut ecc_a = 0;
+        let mut ecc_b = 0;
+        for _ in 0..minor_count {
+            let value = data[index];
+            index += minor_inc;
+            if index >= size {
+                index -= size;
+            }
+            ecc_a ^= value;
+            ecc_b ^= value;
+            ecc_a = f_lut[ecc_a as usize];
+        }
+        ecc_a = b_lut[(f_lut[ecc_a as usize] ^ ecc_b) as usize];
+        dest[major] = ecc_a;
+        dest[major + major_count] = ecc_a ^ ecc_b;
+    }
+}
+
+/// Generates the EDC, and the P and Q parity bytes of a Mode 1 sector
+pub fn generate_mode1(sector: &mut [u8]) {
+    assert_eq!(sector.len(), SECTOR_SIZE);
+
+    let edc = edc(&sector[..0x810]);
+    sector[0x810..0x814].copy_from_slice(&edc.to_le_bytes());
+    generate(sector);
+}
+
+/// Generates the EDC, and the P and Q parity bytes of a Mode 2 Form 1 sector,
+/// from its subheader and data, the parity doesn't include the header
+pub fn generate_mode2_form1(sector: &mut [u8]) {
+    assert_eq!(sector.len(), SECTOR_SIZE);
+
+    let edc = edc(&sector[0x10..0x818]);
+    sector[0x818..0x81C].copy_from_slice(&edc.to_le_bytes());
+
+    let mut header = [0; 4];
+    header.copy_from_slice(&sector[12..16]);
+    sector[12..16].fill(0);
+    generate(sector);
+    sector[12..16].copy_from_slice(&header);
+}
+
+/// Generates the EDC of a Mode 2 Form 2 sector, which has no parity bytes
+pub fn generate_mode2_form2(sector: &mut [u8]) {
+    assert_eq!(sector.len(), SECTOR_SIZE);
+
+    let edc = edc(&sector[0x10..0x92C]);
+    sector[0x92C..0x930].copy_from_slice(&edc.to_le_bytes());
+}
+
+/// Generates the P and Q parity bytes of a Mode 1 sector from its header and data
+pub fn generate(sector: &mut [u8]) {
+    assert_eq!(sector.len(), SECTOR_SIZE);
+
+    let mut p_parity = [0; Q_PARITY_OFFSET - P_PARITY_OFFSET];
+    compute_parity(&sector[12..], 86, 24, 2, 86, &mut p_parity);
+    sector[P_PARITY_OFFSET..Q_PARITY_OFFSET].copy_from_slice(&p_parity);
+
+    let mut q_parity = [0; SECTOR_SIZE - Q_PARITY_OFFSET];
+    compute_parity(&sector[12..], 52, 43, 86, 88, &mut q_parity);
+    sector[Q_PARITY_OFFSET..].copy_from_slice(&q_parity);
+}
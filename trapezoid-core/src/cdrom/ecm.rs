@@ -0,0 +1,304 @@
+//! Neill Corlett's Error Code Modeler (ECM) files, which store a raw image without
+//! the sync, EDC and ECC of its sectors, as they can be regenerated.
+//!
+//! [`EcmFile`] decodes the original file on the fly, so it can replace the `.bin`
+//! referenced by a cue sheet.
+
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+use super::{disc::SECTOR_SIZE, ecc};
+
+const MAGIC: &[u8; 4] = b"ECM\0";
+/// Mode 2 sectors are stored without their sync and header
+const MODE2_SIZE: usize = SECTOR_SIZE - 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordKind {
+    /// Bytes stored as is
+    Raw,
+    Mode1,
+    Mode2Form1,
+    Mode2Form2,
+}
+
+impl RecordKind {
+    /// The size of every item of the record in the ECM file
+    fn stored_size(self) -> usize {
+        match self {
+            RecordKind::Raw => 1,
+            // the header address, then the data
+            RecordKind::Mode1 => 3 + 0x800,
+            // the subheader, then the data
+            RecordKind::Mode2Form1 => 4 + 0x800,
+            RecordKind::Mode2Form2 => 4 + 0x914,
+        }
+    }
+
+    /// The size of every item of the record once decoded
+    fn decoded_size(self) -> usize {
+        match self {
+            RecordKind::Raw => 1,
+            RecordKind::Mode1 => SECTOR_SIZE,
+            RecordKind::Mode2Form1 | RecordKind::Mode2Form2 => MODE2_SIZE,
+        }
+    }
+}
+
+/// A run of `count` items of the same kind
+#[derive(Debug)]
+struct Record {
+    kind: RecordKind,
+    count: u64,
+    /// Where the items are in the ECM file
+    stored_offset: u64,
+    /// Where the items are in the decoded file
+    decoded_offset: u64,
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// The decoded content of an ECM file, with random access
+pub struct EcmFile<R> {
+    file: BufReader<R>,
+    records: Vec<Record>,
+    len: u64,
+    position: u64,
+    /// The last decoded item, as (record index, item index)
+    cached_item: Option<(usize, u64)>,
+    item: [u8; SECTOR_SIZE],
+}
+
+impl<R: Read + Seek> EcmFile<R> {
+    /// Reads the records of the file, the sectors are only decoded when read
+    pub fn new(file: R) -> io::Result<Self> {
+        let mut file = BufReader::new(file);
+
+        let mut magic = [0; 4];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not an ECM file"));
+        }
+
+        let mut records = Vec::new();
+        let mut stored_offset = MAGIC.len() as u64;
+        let mut decoded_offset = 0;
+        loop {
+            // the kind in 2 bits and the count in a variable length number,
+            // 7 bits per byte except the first, while the top bit is set
+            let mut byte = read_u8(&mut file)?;
+            stored_offset += 1;
+            let kind = match byte & 3 {
+                0 => RecordKind::Raw,
+                1 => RecordKind::Mode1,
+                2 => RecordKind::Mode2Form1,
+                _ => RecordKind::Mode2Form2,
+            };
+            let mut count = ((byte >> 2) & 0x1F) as u64;
+            let mut shift = 5;
+            while byte & 0x80 != 0 {
+                if shift > 31 {
+                    return Err(invalid_data("invalid ECM record count"));
+                }
+                byte = read_u8(&mut file)?;
+                stored_offset += 1;
+                count |= ((byte & 0x7F) as u64) << shift;
+                shift += 7;
+            }
+            if count == 0xFFFF_FFFF {
+                break;
+            }
+            count += 1;
+
+            let stored_size = count * kind.stored_size() as u64;
+            records.push(Record {
+                kind,
+                count,
+                stored_offset,
+                decoded_offset,
+            });
+            file.seek_relative(stored_size as i64)?;
+            stored_offset += stored_size;
+            decoded_offset += count * kind.decoded_size() as u64;
+        }
+
+        Ok(Self {
+            file,
+            records,
+            len: decoded_offset,
+            position: 0,
+            cached_item: None,
+            item: [0; SECTOR_SIZE],
+        })
+    }
+
+    /// The size of the decoded file
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Decodes a sector item of a record into `self.item`
+    fn decode_item(&mut self, record_index: usize, item: u64) -> io::Result<()> {
+        if self.cached_item == Some((record_index, item)) {
+            return Ok(());
+        }
+        self.cached_item = None;
+
+        let record = &self.records[record_index];
+        let kind = record.kind;
+        self.file.seek(SeekFrom::Start(
+            record.stored_offset + item * kind.stored_size() as u64,
+        ))?;
+
+        let sector = &mut self.item;
+        sector.fill(0);
+        sector[..12].copy_from_slice(&ecc::SYNC_PATTERN);
+        match kind {
+            RecordKind::Raw => unreachable!("raw records are not decoded"),
+            RecordKind::Mode1 => {
+                self.file.read_exact(&mut sector[12..15])?;
+                sector[15] = 1;
+                self.file.read_exact(&mut sector[0x10..0x810])?;
+                ecc::generate_mode1(sector);
+            }
+            RecordKind::Mode2Form1 | RecordKind::Mode2Form2 => {
+                let end = 0x14 + kind.stored_size();
+                self.file.read_exact(&mut sector[0x14..end])?;
+                // the subheader is repeated twice, and only one copy is stored
+                sector.copy_within(0x14..0x18, 0x10);
+                if kind == RecordKind::Mode2Form1 {
+                    ecc::generate_mode2_form1(sector);
+                } else {
+                    ecc::generate_mode2_form2(sector);
+                }
+                // the sync and header are stored separately as raw bytes
+                sector.copy_within(0x10.., 0);
+            }
+        }
+
+        self.cached_item = Some((record_index, item));
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for EcmFile<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let record_index = self
+            .records
+            .partition_point(|record| record.decoded_offset <= self.position)
+            - 1;
+        let record = &self.records[record_index];
+        let decoded_size = record.kind.decoded_size() as u64;
+        let offset = self.position - record.decoded_offset;
+
+        let read = if record.kind == RecordKind::Raw {
+            let length = buf.len().min((record.count - offset) as usize);
+            self.file
+                .seek(SeekFrom::Start(record.stored_offset + offset))?;
+            self.file.read_exact(&mut buf[..length])?;
+            length
+        } else {
+            let item = offset / decoded_size;
+            let item_offset = (offset % decoded_size) as usize;
+            self.decode_item(record_index, item)?;
+            let length = buf.len().min(decoded_size as usize - item_offset);
+            buf[..length].copy_from_slice(&self.item[item_offset..item_offset + length]);
+            length
+        };
+
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for EcmFile<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn record_header(out: &mut Vec<u8>, kind: u8, count: u32) {
+        let mut count = count - 1;
+        let mut byte = ((count & 0x1F) << 2) as u8 | kind;
+        count >>= 5;
+        while count != 0 {
+            out.push(byte | 0x80);
+            byte = (count & 0x7F) as u8;
+            count >>= 7;
+        }
+        out.push(byte);
+    }
+
+    #[test]
+    fn decode_sectors() {
+        // a Mode 1 sector, then a Mode 2 Form 1 sector with its sync and header
+        let mut mode1 = [0; SECTOR_SIZE];
+        mode1[..12].copy_from_slice(&ecc::SYNC_PATTERN);
+        mode1[12..16].copy_from_slice(&[0x00, 0x02, 0x00, 1]);
+        for (i, b) in mode1[0x10..0x810].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        ecc::generate_mode1(&mut mode1);
+
+        let mut mode2 = [0; SECTOR_SIZE];
+        mode2[..12].copy_from_slice(&ecc::SYNC_PATTERN);
+        mode2[12..16].copy_from_slice(&[0x00, 0x02, 0x01, 2]);
+        mode2[0x10..0x18].copy_from_slice(&[0, 0, 8, 0, 0, 0, 8, 0]);
+        for (i, b) in mode2[0x18..0x818].iter_mut().enumerate() {
+            *b = (i * 7) as u8;
+        }
+        ecc::generate_mode2_form1(&mut mode2);
+
+        let mut ecm = MAGIC.to_vec();
+        record_header(&mut ecm, 1, 1);
+        ecm.extend_from_slice(&mode1[12..15]);
+        ecm.extend_from_slice(&mode1[0x10..0x810]);
+        record_header(&mut ecm, 0, 16);
+        ecm.extend_from_slice(&mode2[..0x10]);
+        record_header(&mut ecm, 2, 1);
+        ecm.extend_from_slice(&mode2[0x14..0x818]);
+        // the end marker, then the EDC of the whole file which isn't checked
+        ecm.extend_from_slice(&[0xFC, 0xFF, 0xFF, 0xFF, 0x3F, 0, 0, 0, 0]);
+
+        let mut file = EcmFile::new(Cursor::new(ecm)).unwrap();
+        assert_eq!(file.len(), 2 * SECTOR_SIZE as u64);
+
+        let mut decoded = vec![0; 2 * SECTOR_SIZE];
+        file.read_exact(&mut decoded).unwrap();
+        assert_eq!(decoded[..SECTOR_SIZE], mode1);
+        assert_eq!(decoded[SECTOR_SIZE..], mode2);
+
+        // reading across records after seeking
+        file.seek(SeekFrom::Start(SECTOR_SIZE as u64 - 4)).unwrap();
+        let mut middle = [0; 24];
+        file.read_exact(&mut middle).unwrap();
+        assert_eq!(middle, decoded[SECTOR_SIZE - 4..SECTOR_SIZE + 20]);
+    }
+}
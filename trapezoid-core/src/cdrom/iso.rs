@@ -0,0 +1,81 @@
+//! `.iso` images, only the 2048 bytes of user data of every sector are stored,
+//! the rest of the sector is synthesized as Mode 2 Form 1.
+
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use super::{
+    disc::{DiscImage, Track, TrackKind, LEAD_IN_SECTORS, SECTOR_SIZE},
+    ecc, sectors_to_msf, to_bcd,
+};
+use crate::PsxError;
+
+const ISO_SECTOR_SIZE: usize = 2048;
+
+/// The subheader of a data sector, file 0, channel 0, and the `Data` submode
+const DATA_SUBHEADER: [u8; 4] = [0x00, 0x00, 0x08, 0x00];
+
+pub struct IsoImage {
+    file: fs::File,
+    tracks: [Track; 1],
+}
+
+impl IsoImage {
+    pub fn open(path: &Path) -> Result<Self, PsxError> {
+        let io_error = |source| PsxError::CouldNotLoadDisk {
+            path: path.to_owned(),
+            source,
+        };
+        let file = fs::File::open(path).map_err(io_error)?;
+        let size = file.metadata().map_err(io_error)?.len() as usize;
+
+        if size == 0 || !size.is_multiple_of(ISO_SECTOR_SIZE) {
+            return Err(PsxError::InvalidDisk {
+                path: path.to_owned(),
+                reason: format!(
+                    "size is not a multiple of {} bytes sectors",
+                    ISO_SECTOR_SIZE
+                ),
+            });
+        }
+
+        Ok(Self {
+            file,
+            tracks: [Track {
+                number: 1,
+                kind: TrackKind::Data,
+                pregap_start: 0,
+                start: 0,
+                length: size / ISO_SECTOR_SIZE,
+            }],
+        })
+    }
+}
+
+impl DiscImage for IsoImage {
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn read_sector(&mut self, position: usize) -> io::Result<[u8; SECTOR_SIZE]> {
+        let mut sector = [0; SECTOR_SIZE];
+
+        self.file
+            .seek(SeekFrom::Start((position * ISO_SECTOR_SIZE) as u64))?;
+        self.file
+            .read_exact(&mut sector[24..24 + ISO_SECTOR_SIZE])?;
+
+        let (minutes, seconds, frames) = sectors_to_msf(position + LEAD_IN_SECTORS);
+        sector[..12].copy_from_slice(&ecc::SYNC_PATTERN);
+        sector[12..16].copy_from_slice(&[to_bcd(minutes), to_bcd(seconds), to_bcd(frames), 2]);
+        // the subheader is repeated twice
+        sector[16..20].copy_from_slice(&DATA_SUBHEADER);
+        sector[20..24].copy_from_slice(&DATA_SUBHEADER);
+        ecc::generate_mode2_form1(&mut sector);
+
+        Ok(sector)
+    }
+}
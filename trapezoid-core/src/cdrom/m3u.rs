@@ -0,0 +1,66 @@
+//! `.m3u` playlists listing the discs of a multi-disc game, one path per line.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::PsxError;
+
+/// The non empty lines that are not comments, relative paths are relative to `directory`
+fn parse_m3u(content: &str, directory: &Path) -> Vec<PathBuf> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| directory.join(line))
+        .collect()
+}
+
+fn is_m3u(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("m3u"))
+}
+
+/// Reads the disc image paths of the `.m3u` playlist at `path`, in order
+pub fn read_m3u_playlist(path: &Path) -> Result<Vec<PathBuf>, PsxError> {
+    let content = fs::read_to_string(path).map_err(|source| PsxError::CouldNotLoadDisk {
+        path: path.to_owned(),
+        source,
+    })?;
+
+    let discs = parse_m3u(&content, path.parent().unwrap_or(Path::new("")));
+    if discs.is_empty() {
+        return Err(PsxError::InvalidDisk {
+            path: path.to_owned(),
+            reason: "playlist doesn't have any disc".to_string(),
+        });
+    }
+    // a playlist can't list other playlists, or itself
+    if let Some(nested) = discs.iter().find(|disc| is_m3u(disc)) {
+        return Err(PsxError::InvalidDisk {
+            path: path.to_owned(),
+            reason: format!("nested playlist {}", nested.display()),
+        });
+    }
+    Ok(discs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_and_relative_paths() {
+        let m3u =
+            "#EXTM3U\r\nGame (Disc 1).cue\r\n\r\n# the second disc\r\n/games/Game (Disc 2).chd\r\n";
+        assert_eq!(
+            parse_m3u(m3u, Path::new("/playlists")),
+            [
+                PathBuf::from("/playlists/Game (Disc 1).cue"),
+                PathBuf::from("/games/Game (Disc 2).chd"),
+            ]
+        );
+        assert!(is_m3u(Path::new("/playlists/Game.M3U")));
+    }
+}
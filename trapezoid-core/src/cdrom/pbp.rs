@@ -0,0 +1,344 @@
+//! PlayStation Network `EBOOT.PBP` archives, as made by popstation and similar tools.
+//!
+//! The disc images are compressed in blocks of 16 sectors with raw deflate,
+//! and an archive can contain up to 5 discs. Encrypted official archives are not supported.
+
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use super::{
+    disc::{DiscImage, Track, TrackKind, LEAD_IN_SECTORS, SECTOR_SIZE},
+    from_bcd,
+};
+use crate::PsxError;
+
+const PBP_MAGIC: &[u8; 4] = b"\0PBP";
+/// Where the offset of the `DATA.PSAR` file is in the PBP header
+const PSAR_OFFSET_POSITION: u64 = 0x24;
+
+const DISC_MAGIC: &[u8; 12] = b"PSISOIMG0000";
+const MULTI_DISC_MAGIC: &[u8; 16] = b"PSTITLEIMG000000";
+const MAX_DISCS: usize = 5;
+/// Where the offsets of the discs are in a multi-disc `DATA.PSAR`, relative to it
+const DISC_OFFSETS_POSITION: u64 = 0x200;
+
+// positions in the header of a disc
+const DISC_ID_POSITION: u64 = 0x400;
+const TOC_POSITION: u64 = 0x800;
+const BLOCK_INDEX_POSITION: u64 = 0x4000;
+const BLOCKS_POSITION: u64 = 0x100000;
+
+const TOC_ENTRY_SIZE: usize = 10;
+const BLOCK_INDEX_ENTRY_SIZE: usize = 32;
+const SECTORS_PER_BLOCK: usize = 16;
+const BLOCK_SIZE: usize = SECTORS_PER_BLOCK * SECTOR_SIZE;
+
+fn invalid_data(reason: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.into())
+}
+
+fn read_at<R: Read + Seek>(file: &mut R, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; length];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn read_u32_at<R: Read + Seek>(file: &mut R, offset: u64) -> io::Result<u32> {
+    let data = read_at(file, offset, 4)?;
+    Ok(u32::from_le_bytes(data.try_into().unwrap()))
+}
+
+/// The offsets of the headers of all the discs in the archive
+fn disc_offsets<R: Read + Seek>(file: &mut R) -> io::Result<Vec<u64>> {
+    if &read_at(file, 0, 4)?[..] != PBP_MAGIC {
+        return Err(invalid_data("not a PBP file"));
+    }
+    let psar = read_u32_at(file, PSAR_OFFSET_POSITION)? as u64;
+
+    let magic = read_at(file, psar, MULTI_DISC_MAGIC.len())?;
+    if &magic[..] == MULTI_DISC_MAGIC {
+        let mut offsets = Vec::new();
+        for i in 0..MAX_DISCS {
+            let offset = read_u32_at(file, psar + DISC_OFFSETS_POSITION + i as u64 * 4)?;
+            if offset == 0 {
+                break;
+            }
+            offsets.push(psar + offset as u64);
+        }
+        Ok(offsets)
+    } else if magic.starts_with(DISC_MAGIC) {
+        Ok(vec![psar])
+    } else {
+        Err(invalid_data(
+            "PBP doesn't contain a PlayStation disc, or is encrypted",
+        ))
+    }
+}
+
+/// Converts an error from reading the archive, the invalid data errors are our own
+fn pbp_error(path: &Path, e: io::Error) -> PsxError {
+    if e.kind() == io::ErrorKind::InvalidData {
+        PsxError::InvalidDisk {
+            path: path.to_owned(),
+            reason: e.to_string(),
+        }
+    } else {
+        PsxError::CouldNotLoadDisk {
+            path: path.to_owned(),
+            source: e,
+        }
+    }
+}
+
+/// A disc in a PBP archive, use [`PbpImage::open_discs`] to get all of them
+pub struct PbpImage<R = io::BufReader<fs::File>> {
+    file: R,
+    disc_id: String,
+    tracks: Vec<Track>,
+    /// (offset in the file, compressed size) of every block
+    blocks: Vec<(u64, usize)>,
+    cached_block: Option<usize>,
+    block_buffer: Vec<u8>,
+}
+
+impl PbpImage {
+    /// Opens the first disc of the archive
+    pub fn open(path: &Path) -> Result<Self, PsxError> {
+        Self::open_discs(path)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| PsxError::InvalidDisk {
+                path: path.to_owned(),
+                reason: "PBP doesn't contain any disc".to_string(),
+            })
+    }
+
+    /// Opens all the discs of the archive in order, so they can be swapped
+    pub fn open_discs(path: &Path) -> Result<Vec<Self>, PsxError> {
+        let open = || {
+            fs::File::open(path)
+                .map(io::BufReader::new)
+                .map_err(|e| pbp_error(path, e))
+        };
+
+        let offsets = disc_offsets(&mut open()?).map_err(|e| pbp_error(path, e))?;
+        offsets
+            .into_iter()
+            .map(|offset| Self::new(open()?, offset).map_err(|e| pbp_error(path, e)))
+            .collect()
+    }
+}
+
+impl<R: Read + Seek> PbpImage<R> {
+    /// Reads the disc whose header is at `offset`
+    fn new(mut file: R, offset: u64) -> io::Result<Self> {
+        if &read_at(&mut file, offset, DISC_MAGIC.len())?[..] != DISC_MAGIC {
+            return Err(invalid_data("invalid PBP disc header"));
+        }
+
+        let disc_id = read_at(&mut file, offset + DISC_ID_POSITION, 16)?;
+        let disc_id = String::from_utf8_lossy(&disc_id)
+            .trim_end_matches('\0')
+            .to_string();
+
+        // the TOC is in the format of the subchannel Q of the lead-in, first the
+        // first track (A0), last track (A1) and lead-out (A2) entries, then the tracks
+        let toc = read_at(&mut file, offset + TOC_POSITION, TOC_ENTRY_SIZE * (3 + 99))?;
+        let entries: Vec<&[u8]> = toc.chunks_exact(TOC_ENTRY_SIZE).collect();
+        let msf_sectors = |msf: &[u8]| {
+            ((from_bcd(msf[0]) as usize * 60 + from_bcd(msf[1]) as usize) * 75
+                + from_bcd(msf[2]) as usize)
+                .checked_sub(LEAD_IN_SECTORS)
+                .ok_or_else(|| invalid_data("PBP track is in the lead-in"))
+        };
+        let last_track = from_bcd(entries[1][7]) as usize;
+        let sectors = msf_sectors(&entries[2][7..10])?;
+        if last_track == 0 || last_track > 99 {
+            return Err(invalid_data("invalid PBP track count"));
+        }
+
+        let mut tracks: Vec<Track> = Vec::with_capacity(last_track);
+        for entry in &entries[3..3 + last_track] {
+            let start = msf_sectors(&entry[7..10])?;
+            if let Some(previous) = tracks.last_mut() {
+                previous.length = start
+                    .checked_sub(previous.start)
+                    .ok_or_else(|| invalid_data("PBP tracks are not in order"))?;
+            }
+            tracks.push(Track {
+                number: from_bcd(entry[2]),
+                kind: if entry[0] & 0x40 != 0 {
+                    TrackKind::Data
+                } else {
+                    TrackKind::Audio
+                },
+                pregap_start: start,
+                start,
+                length: 0,
+            });
+        }
+        let last = tracks.last_mut().unwrap();
+        last.length = sectors
+            .checked_sub(last.start)
+            .ok_or_else(|| invalid_data("PBP lead-out is before the last track"))?;
+
+        let block_count = sectors.div_ceil(SECTORS_PER_BLOCK);
+        if BLOCK_INDEX_POSITION as usize + block_count * BLOCK_INDEX_ENTRY_SIZE
+            > BLOCKS_POSITION as usize
+        {
+            return Err(invalid_data("PBP disc is too large"));
+        }
+        let index = read_at(
+            &mut file,
+            offset + BLOCK_INDEX_POSITION,
+            block_count * BLOCK_INDEX_ENTRY_SIZE,
+        )?;
+        let blocks = index
+            .chunks_exact(BLOCK_INDEX_ENTRY_SIZE)
+            .map(|entry| {
+                let block_offset = u32::from_le_bytes(entry[0..4].try_into().unwrap());
+                let size = u16::from_le_bytes(entry[4..6].try_into().unwrap());
+                (
+                    offset + BLOCKS_POSITION + block_offset as u64,
+                    size as usize,
+                )
+            })
+            .collect();
+
+        Ok(Self {
+            file,
+            disc_id,
+            tracks,
+            blocks,
+            cached_block: None,
+            block_buffer: vec![0; BLOCK_SIZE],
+        })
+    }
+
+    /// The serial of the game on this disc, like `_SLUS_00594`
+    pub fn disc_id(&self) -> &str {
+        &self.disc_id
+    }
+
+    fn read_block(&mut self, block: usize) -> io::Result<&[u8]> {
+        if self.cached_block != Some(block) {
+            self.cached_block = None;
+            let (offset, size) = self.blocks[block];
+            let data = read_at(&mut self.file, offset, size)?;
+            self.block_buffer.fill(0);
+            // blocks that don't compress are stored as is
+            if size == BLOCK_SIZE {
+                self.block_buffer.copy_from_slice(&data);
+            } else {
+                let mut decoded = Vec::with_capacity(BLOCK_SIZE);
+                flate2::read::DeflateDecoder::new(&data[..])
+                    .take(BLOCK_SIZE as u64)
+                    .read_to_end(&mut decoded)
+                    .map_err(|e| invalid_data(format!("invalid deflate data: {}", e)))?;
+                self.block_buffer[..decoded.len()].copy_from_slice(&decoded);
+            }
+            self.cached_block = Some(block);
+        }
+        Ok(&self.block_buffer)
+    }
+}
+
+impl<R: Read + Seek + Send> DiscImage for PbpImage<R> {
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn read_sector(&mut self, position: usize) -> io::Result<[u8; SECTOR_SIZE]> {
+        let mut sector = [0; SECTOR_SIZE];
+        let block = self.read_block(position / SECTORS_PER_BLOCK)?;
+        let start = (position % SECTORS_PER_BLOCK) * SECTOR_SIZE;
+        sector.copy_from_slice(&block[start..start + SECTOR_SIZE]);
+        Ok(sector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    /// A disc header with a data track and an audio track, and 2 blocks,
+    /// the first is compressed and the second is stored
+    fn disc(id: &str, sectors: &[u8]) -> Vec<u8> {
+        let mut disc = vec![0; BLOCKS_POSITION as usize];
+        disc[..12].copy_from_slice(DISC_MAGIC);
+        disc[0x400..0x400 + id.len()].copy_from_slice(id.as_bytes());
+
+        let toc = [
+            [0x41, 0, 0xA0, 0, 0, 0, 0, 0x01, 0x20, 0],
+            [0x01, 0, 0xA1, 0, 0, 0, 0, 0x02, 0, 0],
+            [0x01, 0, 0xA2, 0, 0, 0, 0, 0x00, 0x02, 0x32],
+            [0x41, 0, 0x01, 0, 0, 0, 0, 0x00, 0x02, 0x00],
+            [0x01, 0, 0x02, 0, 0, 0, 0, 0x00, 0x02, 0x20],
+        ];
+        for (i, entry) in toc.iter().enumerate() {
+            let position = TOC_POSITION as usize + i * TOC_ENTRY_SIZE;
+            disc[position..position + TOC_ENTRY_SIZE].copy_from_slice(entry);
+        }
+
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&sectors[..BLOCK_SIZE]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let blocks = [(0, compressed.len()), (compressed.len(), BLOCK_SIZE)];
+        for (i, (offset, size)) in blocks.into_iter().enumerate() {
+            let position = BLOCK_INDEX_POSITION as usize + i * BLOCK_INDEX_ENTRY_SIZE;
+            disc[position..position + 4].copy_from_slice(&(offset as u32).to_le_bytes());
+            disc[position + 4..position + 6].copy_from_slice(&(size as u16).to_le_bytes());
+        }
+        disc.extend_from_slice(&compressed);
+        disc.extend_from_slice(&sectors[BLOCK_SIZE..]);
+        disc
+    }
+
+    #[test]
+    fn multi_disc() {
+        // the lead-out is at 00:02:32, so the second block is full
+        let sectors: Vec<u8> = (0..2 * BLOCK_SIZE).map(|i| (i / 7) as u8).collect();
+
+        let psar = 0x100;
+        let mut pbp = vec![0; psar];
+        pbp[..4].copy_from_slice(PBP_MAGIC);
+        pbp[0x24..0x28].copy_from_slice(&(psar as u32).to_le_bytes());
+        let mut title = vec![0; 0x400];
+        title[..16].copy_from_slice(MULTI_DISC_MAGIC);
+        title[0x200..0x204].copy_from_slice(&0x400u32.to_le_bytes());
+        let disc1 = disc("_SLUS_00001", &sectors);
+        title[0x204..0x208].copy_from_slice(&(0x400 + disc1.len() as u32).to_le_bytes());
+        pbp.extend_from_slice(&title);
+        pbp.extend_from_slice(&disc1);
+        pbp.extend_from_slice(&disc("_SLUS_00002", &sectors));
+
+        let offsets = disc_offsets(&mut Cursor::new(&pbp)).unwrap();
+        assert_eq!(offsets.len(), 2);
+
+        let mut disc2 = PbpImage::new(Cursor::new(&pbp), offsets[1]).unwrap();
+        assert_eq!(disc2.disc_id(), "_SLUS_00002");
+        let tracks: Vec<_> = disc2
+            .tracks()
+            .iter()
+            .map(|t| (t.number, t.kind, t.start, t.length))
+            .collect();
+        assert_eq!(
+            tracks,
+            [(1, TrackKind::Data, 0, 20), (2, TrackKind::Audio, 20, 12)]
+        );
+
+        for position in [0, 15, 16, 31] {
+            let start = position * SECTOR_SIZE;
+            assert_eq!(
+                disc2.read_sector(position).unwrap()[..],
+                sectors[start..start + SECTOR_SIZE]
+            );
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Region detection from the license string of the disc, which the drive
+//! checks to answer `GetID`.
+
+use super::disc::{DiscImage, TrackKind, SECTOR_SIZE};
+
+/// Where the license string is, `00:02:04`
+const LICENSE_SECTOR: usize = 4;
+
+const LICENSE_JAPAN: &[u8] = b"          Licensed  by          Sony Computer Entertainment Inc.";
+const LICENSE_NORTH_AMERICA: &[u8] =
+    b"          Licensed  by          Sony Computer Entertainment Amer  ica ";
+const LICENSE_EUROPE: &[u8] =
+    b"          Licensed  by          Sony Computer Entertainment Euro pe";
+
+/// The region of a disc or a BIOS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    /// SCEI, NTSC-J
+    Japan,
+    /// SCEA, NTSC-U
+    NorthAmerica,
+    /// SCEE, PAL
+    Europe,
+}
+
+impl Region {
+    pub fn is_pal(self) -> bool {
+        self == Region::Europe
+    }
+
+    /// The last letter of `SCEx` in the `GetID` response
+    pub(super) fn id_letter(self) -> u8 {
+        match self {
+            Region::Japan => b'I',
+            Region::NorthAmerica => b'A',
+            Region::Europe => b'E',
+        }
+    }
+}
+
+/// What the drive reports about the disc in `GetID`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) enum DiscLicense {
+    #[default]
+    NoDisc,
+    /// The first track is audio
+    Audio,
+    Unlicensed {
+        mode2: bool,
+        has_audio: bool,
+    },
+    Licensed(Region),
+}
+
+impl DiscLicense {
+    pub fn detect<D: DiscImage + ?Sized>(disc: &mut D) -> Self {
+        let has_audio = disc.tracks().iter().any(|t| t.kind == TrackKind::Audio);
+        let Some(first_track) = disc.tracks().first().cloned() else {
+            return DiscLicense::NoDisc;
+        };
+        if first_track.kind == TrackKind::Audio {
+            return DiscLicense::Audio;
+        }
+
+        let sector = match disc.read_sector(first_track.start + LICENSE_SECTOR) {
+            Ok(sector) => sector,
+            Err(e) => {
+                log::error!("cdrom: could not read the license sector: {}", e);
+                [0; SECTOR_SIZE]
+            }
+        };
+        let mode2 = sector[15] == 2;
+        // skip the subheader of mode 2 sectors
+        let data = if mode2 { &sector[24..] } else { &sector[16..] };
+
+        let region = [
+            (LICENSE_JAPAN, Region::Japan),
+            (LICENSE_NORTH_AMERICA, Region::NorthAmerica),
+            (LICENSE_EUROPE, Region::Europe),
+        ]
+        .into_iter()
+        .find(|(license, _)| data.starts_with(license))
+        .map(|(_, region)| region);
+
+        match region {
+            Some(region) => DiscLicense::Licensed(region),
+            None => DiscLicense::Unlicensed { mode2, has_audio },
+        }
+    }
+
+    pub fn region(self) -> Option<Region> {
+        match self {
+            DiscLicense::Licensed(region) => Some(region),
+            _ => None,
+        }
+    }
+}
+
+/// Detects the region of `disc` from its license string,
+/// `None` if it's not a licensed PlayStation disc
+pub fn disc_region<D: DiscImage + ?Sized>(disc: &mut D) -> Option<Region> {
+    DiscLicense::detect(disc).region()
+}
@@ -0,0 +1,390 @@
+//! Subchannel Q, which tells the drive where the head is while reading or playing.
+//!
+//! It's generated from the track table, except for the sectors listed in `.sbi` or `.lsd`
+//! files, which are dumps of the sectors that differ from it. LibCrypt protected games
+//! check that these sectors have their subchannel Q corrupted.
+
+use std::{collections::HashMap, fs, io, path::Path};
+
+use super::{
+    disc::{DiscImage, Track, TrackKind, LEAD_IN_SECTORS, SECTOR_SIZE},
+    from_bcd, sectors_to_msf, to_bcd,
+};
+use crate::PsxError;
+
+/// The track number of the lead-out
+const LEAD_OUT_TRACK: u8 = 0xAA;
+
+/// CRC-16-CCITT of the first 10 bytes, stored inverted and big endian after them
+fn crc(q: &[u8; 12]) -> u16 {
+    let crc = q[..10].iter().fold(0u16, |mut crc, &b| {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    });
+    !crc
+}
+
+pub fn is_crc_valid(q: &[u8; 12]) -> bool {
+    u16::from_be_bytes([q[10], q[11]]) == crc(q)
+}
+
+fn set_crc(q: &mut [u8; 12]) {
+    let crc = crc(q);
+    q[10..].copy_from_slice(&crc.to_be_bytes());
+}
+
+/// Generates the subchannel Q of the sector at `position` from the track table of `disc`
+pub fn generate<D: DiscImage + ?Sized>(disc: &D, position: usize) -> [u8; 12] {
+    let tracks = disc.tracks();
+    let (track, index, relative) = match disc.track_at(position) {
+        // in the pregap, the relative position counts down to the start
+        Some(track) if position < track.start => (track, 0, track.start - position),
+        Some(track) => (track, 1, position - track.start),
+        None => {
+            let sectors = disc.sectors();
+            let Some(last) = tracks.last() else {
+                return [0; 12];
+            };
+            let lead_out = Track {
+                number: LEAD_OUT_TRACK,
+                kind: last.kind,
+                pregap_start: sectors,
+                start: sectors,
+                length: 0,
+            };
+            return generate_in_track(&lead_out, 1, position - sectors, position);
+        }
+    };
+    generate_in_track(track, index, relative, position)
+}
+
+fn generate_in_track(track: &Track, index: u8, relative: usize, position: usize) -> [u8; 12] {
+    // control (data or audio) and ADR 1 (position)
+    let control_adr = match track.kind {
+        TrackKind::Data => 0x41,
+        TrackKind::Audio => 0x01,
+    };
+    let number = if track.number == LEAD_OUT_TRACK {
+        LEAD_OUT_TRACK
+    } else {
+        to_bcd(track.number)
+    };
+    let (minutes, seconds, sector) = sectors_to_msf(relative);
+    let (abs_minutes, abs_seconds, abs_sector) = sectors_to_msf(position + LEAD_IN_SECTORS);
+
+    let mut q = [
+        control_adr,
+        number,
+        to_bcd(index),
+        to_bcd(minutes),
+        to_bcd(seconds),
+        to_bcd(sector),
+        0,
+        to_bcd(abs_minutes),
+        to_bcd(abs_seconds),
+        to_bcd(abs_sector),
+        0,
+        0,
+    ];
+    set_crc(&mut q);
+    q
+}
+
+/// The subchannel Q of the table of contents in the lead-in for `point`, which is
+/// a BCD track number, or `A0h`/`A1h`/`A2h` for the first track, last track and lead-out
+pub fn lead_in_q(tracks: &[Track], sectors: usize, point: u8) -> Option<[u8; 12]> {
+    let (first, last) = (tracks.first()?, tracks.last()?);
+    let (kind, p_position) = match point {
+        // the second is the disc type, CD-ROM XA if it has data
+        0xA0 => {
+            let disc_type = if first.kind == TrackKind::Data {
+                0x20
+            } else {
+                0
+            };
+            (first.kind, [to_bcd(first.number), disc_type, 0])
+        }
+        0xA1 => (last.kind, [to_bcd(last.number), 0, 0]),
+        0xA2 => (last.kind, msf_bcd(sectors)),
+        _ => {
+            let track = tracks.iter().find(|t| to_bcd(t.number) == point)?;
+            (track.kind, msf_bcd(track.start))
+        }
+    };
+    let control_adr = match kind {
+        TrackKind::Data => 0x41,
+        TrackKind::Audio => 0x01,
+    };
+
+    let mut q = [
+        control_adr,
+        0,
+        point,
+        0,
+        0,
+        0,
+        0,
+        p_position[0],
+        p_position[1],
+        p_position[2],
+        0,
+        0,
+    ];
+    set_crc(&mut q);
+    Some(q)
+}
+
+/// The BCD `mm:ss:ff` absolute position of a sector after the lead-in
+fn msf_bcd(position: usize) -> [u8; 3] {
+    let (minutes, seconds, sector) = sectors_to_msf(position + LEAD_IN_SECTORS);
+    [to_bcd(minutes), to_bcd(seconds), to_bcd(sector)]
+}
+
+/// Converts a BCD `mm:ss:ff` absolute position to a sector after the lead-in
+fn msf_position(msf: &[u8]) -> Option<usize> {
+    ((from_bcd(msf[0]) as usize * 60 + from_bcd(msf[1]) as usize) * 75 + from_bcd(msf[2]) as usize)
+        .checked_sub(LEAD_IN_SECTORS)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Replacement {
+    /// Bytes replacing the generated ones starting at `offset`,
+    /// the CRC is made invalid, as only the protected sectors are dumped
+    Patch { offset: usize, data: Vec<u8> },
+    /// The whole subchannel Q with its CRC
+    Whole([u8; 12]),
+}
+
+type Replacements = HashMap<usize, Replacement>;
+
+/// `.sbi` files, records of the position, a type and the replaced bytes
+fn parse_sbi(data: &[u8]) -> Result<Replacements, String> {
+    let mut data = data
+        .strip_prefix(b"SBI\0")
+        .ok_or_else(|| "not an SBI file".to_string())?;
+
+    let mut replacements = HashMap::new();
+    while !data.is_empty() {
+        let Some((header, rest)) = data.split_first_chunk::<4>() else {
+            return Err("SBI file is truncated".to_string());
+        };
+        let position = msf_position(&header[..3]).ok_or("SBI sector is in the lead-in")?;
+        // the full subchannel Q without the CRC, the relative or the absolute position
+        let (offset, length) = match header[3] {
+            1 => (0, 10),
+            2 => (3, 3),
+            3 => (7, 3),
+            kind => return Err(format!("invalid SBI record type {}", kind)),
+        };
+        let patch = rest.get(..length).ok_or("SBI file is truncated")?;
+        replacements.insert(
+            position,
+            Replacement::Patch {
+                offset,
+                data: patch.to_vec(),
+            },
+        );
+        data = &rest[length..];
+    }
+    Ok(replacements)
+}
+
+/// `.lsd` files, records of the position and the whole subchannel Q
+fn parse_lsd(data: &[u8]) -> Result<Replacements, String> {
+    if !data.len().is_multiple_of(15) {
+        return Err("LSD file is truncated".to_string());
+    }
+
+    data.chunks_exact(15)
+        .map(|record| {
+            let position = msf_position(&record[..3]).ok_or("LSD sector is in the lead-in")?;
+            Ok((
+                position,
+                Replacement::Whole(record[3..].try_into().unwrap()),
+            ))
+        })
+        .collect()
+}
+
+/// A disc with the subchannel Q of some sectors replaced
+pub struct SubchannelOverrides {
+    disc: Box<dyn DiscImage>,
+    replacements: Replacements,
+}
+
+impl SubchannelOverrides {
+    /// Looks for a `.sbi` or `.lsd` file with the same name as `path`,
+    /// and applies it to `disc`, if there is none, `disc` is returned as is
+    pub fn load(disc: Box<dyn DiscImage>, path: &Path) -> Result<Box<dyn DiscImage>, PsxError> {
+        for (extension, parse) in [
+            (
+                "sbi",
+                parse_sbi as fn(&[u8]) -> Result<Replacements, String>,
+            ),
+            ("lsd", parse_lsd as _),
+        ] {
+            let subchannel_file = path.with_extension(extension);
+            let data = match fs::read(&subchannel_file) {
+                Ok(data) => data,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(source) => {
+                    return Err(PsxError::CouldNotLoadDisk {
+                        path: subchannel_file,
+                        source,
+                    })
+                }
+            };
+            let replacements = parse(&data).map_err(|reason| PsxError::InvalidDisk {
+                path: subchannel_file.clone(),
+                reason,
+            })?;
+
+            log::info!(
+                "Loaded {} subchannel Q replacements from {:?}",
+                replacements.len(),
+                subchannel_file
+            );
+            return Ok(Box::new(Self { disc, replacements }));
+        }
+
+        Ok(disc)
+    }
+}
+
+impl DiscImage for SubchannelOverrides {
+    fn tracks(&self) -> &[Track] {
+        self.disc.tracks()
+    }
+
+    fn read_sector(&mut self, position: usize) -> io::Result<[u8; SECTOR_SIZE]> {
+        self.disc.read_sector(position)
+    }
+
+    fn subchannel_q(&mut self, position: usize) -> [u8; 12] {
+        let mut q = self.disc.subchannel_q(position);
+        match self.replacements.get(&position) {
+            Some(Replacement::Patch { offset, data }) => {
+                q[*offset..*offset + data.len()].copy_from_slice(data);
+                set_crc(&mut q);
+                q[11] ^= 0xFF;
+            }
+            Some(Replacement::Whole(whole)) => q = *whole,
+            None => {}
+        }
+        q
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Tracks(Vec<Track>);
+
+    impl DiscImage for Tracks {
+        fn tracks(&self) -> &[Track] {
+            &self.0
+        }
+
+        fn read_sector(&mut self, _position: usize) -> io::Result<[u8; SECTOR_SIZE]> {
+            Ok([0; SECTOR_SIZE])
+        }
+    }
+
+    fn disc() -> Tracks {
+        Tracks(vec![
+            Track {
+                number: 1,
+                kind: TrackKind::Data,
+                pregap_start: 0,
+                start: 0,
+                length: 1000,
+            },
+            Track {
+                number: 2,
+                kind: TrackKind::Audio,
+                pregap_start: 1000,
+                start: 1150,
+                length: 5000,
+            },
+        ])
+    }
+
+    #[test]
+    fn generated_positions() {
+        let disc = disc();
+
+        // in the pregap of track 2, 2 seconds before its start
+        let q = generate(&disc, 1000);
+        assert_eq!(
+            q[..10],
+            [0x01, 0x02, 0x00, 0x00, 0x02, 0x00, 0, 0x00, 0x15, 0x25]
+        );
+        assert!(is_crc_valid(&q));
+
+        let q = generate(&disc, 1150 + 75 * 61 + 3);
+        assert_eq!(
+            q[..10],
+            [0x01, 0x02, 0x01, 0x01, 0x01, 0x03, 0, 0x01, 0x18, 0x28]
+        );
+
+        let q = generate(&disc, 6150);
+        assert_eq!(q[1..3], [LEAD_OUT_TRACK, 0x01]);
+    }
+
+    #[test]
+    fn table_of_contents() {
+        let disc = disc();
+
+        let q = lead_in_q(disc.tracks(), disc.sectors(), 0xA0).unwrap();
+        assert_eq!(q[..10], [0x41, 0, 0xA0, 0, 0, 0, 0, 0x01, 0x20, 0x00]);
+        let q = lead_in_q(disc.tracks(), disc.sectors(), 0x02).unwrap();
+        assert_eq!(q[..10], [0x01, 0, 0x02, 0, 0, 0, 0, 0x00, 0x17, 0x25]);
+        assert!(is_crc_valid(&q));
+        let q = lead_in_q(disc.tracks(), disc.sectors(), 0xA2).unwrap();
+        assert_eq!(q[7..10], [0x01, 0x24, 0x00]);
+
+        assert!(lead_in_q(disc.tracks(), disc.sectors(), 0x03).is_none());
+    }
+
+    #[test]
+    fn sbi_replacements() {
+        // a LibCrypt sector with its relative position replaced, then one with all of it
+        let sbi = [
+            b"SBI\0".as_slice(),
+            &[0x00, 0x04, 0x00, 2, 0x00, 0x12, 0x34],
+            &[
+                0x00, 0x04, 0x01, 1, 0x41, 0x01, 0x01, 0x00, 0x02, 0x01, 0, 0x00, 0x04, 0x01,
+            ],
+        ]
+        .concat();
+        let replacements = parse_sbi(&sbi).unwrap();
+        let mut disc = SubchannelOverrides {
+            disc: Box::new(disc()),
+            replacements,
+        };
+
+        let q = disc.subchannel_q(150);
+        assert_eq!(
+            q[..10],
+            [0x41, 0x01, 0x01, 0x00, 0x12, 0x34, 0, 0x00, 0x04, 0x00]
+        );
+        assert!(!is_crc_valid(&q));
+        let q = disc.subchannel_q(151);
+        assert_eq!(
+            q[..10],
+            [0x41, 0x01, 0x01, 0x00, 0x02, 0x01, 0, 0x00, 0x04, 0x01]
+        );
+        assert!(!is_crc_valid(&q));
+        assert!(is_crc_valid(&disc.subchannel_q(152)));
+
+        assert!(parse_sbi(b"SBI\0\x00\x04\x00\x01\x41").is_err());
+    }
+}
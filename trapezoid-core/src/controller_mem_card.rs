@@ -1,7 +1,13 @@
 use crate::memory::{interrupts::InterruptRequester, BusLine, Result};
+use crate::unimplemented::Report;
+use crate::HardwareComponent;
 use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
 
-use std::collections::VecDeque;
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+};
 
 #[derive(Clone, Copy)]
 pub enum DigitalControllerKey {
@@ -32,7 +38,8 @@ impl DigitalControllerKey {
 const JOY_CTRL_ACKKNOWLEDGE: u16 = 0b0000000000010000;
 const JOY_CTRL_RESET: u16 = 0b0000000001000000;
 bitflags! {
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct JoyControl: u16 {
         const TX_ENABLE            = 0b0000000000000001;
         const JOY_SELECT           = 0b0000000000000010;
@@ -73,7 +80,8 @@ impl JoyControl {
 }
 
 bitflags! {
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct JoyMode: u16 {
         const BAUDRATE_RELOAD_FACTOR = 0b0000000000000011;
         const CHARACTER_LENGTH       = 0b0000000000001100;
@@ -110,7 +118,8 @@ impl JoyMode {
 }
 
 bitflags! {
-    #[derive(Default)]
+    #[derive(Default, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct JoyStat: u32 {
         const TX_READY_1             = 0b0000000000000001;
         const RX_FIFO_NOT_EMPTY      = 0b0000000000000010;
@@ -124,7 +133,11 @@ bitflags! {
 }
 
 mod controller {
-    #[derive(Debug, Clone, Copy)]
+    use crate::unimplemented::{self, Report};
+    use crate::HardwareComponent;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
     pub enum ControllerMode {
         ReadButtons,
         Config,
@@ -142,6 +155,7 @@ mod controller {
     }
 
     /// Emulate Digital pad controller communication
+    #[derive(Serialize, Deserialize)]
     pub struct Controller {
         state: u8,
         device_id: u16,
@@ -156,6 +170,9 @@ mod controller {
         /// Internal value with many purposes in the input state flow
         /// Used to store a value that may be used later in the flow
         cache_value: u8,
+
+        #[serde(skip)]
+        pending_unimplemented: Option<Report>,
     }
 
     impl Controller {
@@ -171,6 +188,7 @@ mod controller {
                 led: false,
                 rumble_config: [0xFF; 6],
                 cache_value: 0,
+                pending_unimplemented: None,
             }
         }
 
@@ -199,7 +217,16 @@ mod controller {
                     self.current_mode = match inp {
                         0x42 => ControllerMode::ReadButtons,
                         0x43 => ControllerMode::Config,
-                        _ => todo!("Controller first input {:02X} is not supported", inp),
+                        _ => {
+                            unimplemented::report(
+                                &mut self.pending_unimplemented,
+                                HardwareComponent::Controller,
+                                format!("Controller first input {:02X} is not supported", inp),
+                            );
+                            // don't acknowledge, as if the controller is not there
+                            self.state = 0;
+                            return (0xFF, true);
+                        }
                     };
 
                     self.state = 2;
@@ -260,7 +287,16 @@ mod controller {
                         0x48 => ControllerMode::Unknown4010,
                         0x4C => ControllerMode::GetVariableResponseB,
                         0x4D => ControllerMode::SetRumble,
-                        _ => todo!("unknown controller mode: {:02X}", inp),
+                        _ => {
+                            unimplemented::report(
+                                &mut self.pending_unimplemented,
+                                HardwareComponent::Controller,
+                                format!("unknown controller mode: {:02X}", inp),
+                            );
+                            // don't acknowledge, as if the controller is not there
+                            self.state = 0;
+                            return (0xFF, true);
+                        }
                     };
 
                     self.state = 2;
@@ -465,13 +501,25 @@ mod controller {
                 r
             }
         }
+
+        pub fn take_unimplemented(&mut self) -> Option<Report> {
+            self.pending_unimplemented.take()
+        }
+
+        /// The buttons and the connection are controlled by the frontend, a loaded state
+        /// shouldn't change them, or else keys will be stuck until pressed again
+        pub fn keep_host_input_from(&mut self, old: &Controller) {
+            self.digital_switches = old.digital_switches;
+            self.connected = old.connected;
+        }
     }
 }
 
 mod memcard {
+    use serde::{Deserialize, Serialize};
     use std::{fmt::Write, fs};
 
-    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
     pub enum CardReadStage {
         Command,
         MemoryCardId1,
@@ -492,6 +540,7 @@ mod memcard {
         CmdIdEnd4,
     }
 
+    #[derive(Serialize, Deserialize)]
     pub enum CardCmd {
         Read,
         Write,
@@ -499,6 +548,7 @@ mod memcard {
         Invalid,
     }
 
+    #[derive(Serialize, Deserialize)]
     pub struct MemoryCard {
         id: u8,
         stage: CardReadStage,
@@ -509,9 +559,16 @@ mod memcard {
         checksum: u8,
         status: u8,
         previous: u8,
+        // the content is saved in its own file, and must not go back in time
+        // with the save state
+        #[serde(skip, default = "empty_card_data")]
         data: Box<[u8; 0x400 * 128]>,
     }
 
+    fn empty_card_data() -> Box<[u8; 0x400 * 128]> {
+        Box::new([0; 0x400 * 128])
+    }
+
     impl MemoryCard {
         pub fn new(id: u8) -> Self {
             let mut data = Box::new([0; 0x400 * 128]);
@@ -791,10 +848,15 @@ mod memcard {
         fn flush(&mut self) {
             fs::write(format!("memcard{}.mcd", self.id), &self.data[..]).unwrap();
         }
+
+        pub fn keep_data_from(&mut self, old: &mut MemoryCard) {
+            std::mem::swap(&mut self.data, &mut old.data);
+        }
     }
 }
 
 /// Groups the controller and memory_card components for communication
+#[derive(Serialize, Deserialize)]
 struct CommunicationHandler {
     /// which component we are communicating with now
     state: u8,
@@ -864,6 +926,7 @@ impl CommunicationHandler {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct ControllerAndMemoryCard {
     ctrl: JoyControl,
     mode: JoyMode,
@@ -901,6 +964,28 @@ impl Default for ControllerAndMemoryCard {
     }
 }
 
+impl ControllerAndMemoryCard {
+    pub fn save_state<W: Write>(&self, writer: W) -> bincode::Result<()> {
+        bincode::serialize_into(writer, self)
+    }
+
+    pub fn load_state<R: Read>(&mut self, reader: R) -> bincode::Result<()> {
+        let mut state: Self = bincode::deserialize_from(reader)?;
+
+        for (new, old) in state
+            .communication_handlers
+            .iter_mut()
+            .zip(self.communication_handlers.iter_mut())
+        {
+            new.controller.keep_host_input_from(&old.controller);
+            new.memory_card.keep_data_from(&mut old.memory_card);
+        }
+        *self = state;
+
+        Ok(())
+    }
+}
+
 impl ControllerAndMemoryCard {
     pub fn clock(&mut self, interrupt_requester: &mut impl InterruptRequester, mut cycles: u32) {
         while cycles > 0 {
@@ -961,6 +1046,12 @@ impl ControllerAndMemoryCard {
     pub fn change_controller_key_state(&mut self, key: DigitalControllerKey, pressed: bool) {
         self.communication_handlers[0].change_controller_key_state(key, pressed);
     }
+
+    pub(crate) fn take_unimplemented(&mut self) -> Option<Report> {
+        self.communication_handlers
+            .iter_mut()
+            .find_map(|handler| handler.controller.take_unimplemented())
+    }
 }
 
 impl ControllerAndMemoryCard {
@@ -1010,6 +1101,8 @@ impl ControllerAndMemoryCard {
 }
 
 impl BusLine for ControllerAndMemoryCard {
+    const COMPONENT: HardwareComponent = HardwareComponent::Controller;
+
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         let r = match addr {
             0x4 => self.get_stat(),
@@ -1,7 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+use crate::unimplemented::{self, Report};
+use crate::HardwareComponent;
+
 /// CXD8606CQ CPU ID
 const PRID: u32 = 0x2;
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct SystemControlCoprocessor {
     bpc: u32,
     bda: u32,
@@ -13,6 +18,9 @@ pub struct SystemControlCoprocessor {
     sr: u32,
     cause: u32,
     epc: u32,
+
+    #[serde(skip)]
+    pending_unimplemented: Option<Report>,
 }
 
 impl SystemControlCoprocessor {
@@ -43,22 +51,35 @@ impl SystemControlCoprocessor {
     pub fn write_bad_vaddr(&mut self, addr: u32) {
         self.bad_vaddr = addr;
     }
+
+    pub(crate) fn take_unimplemented(&mut self) -> Option<Report> {
+        self.pending_unimplemented.take()
+    }
 }
 
 impl SystemControlCoprocessor {
-    pub fn read_ctrl(&self, num: u8) -> u32 {
+    pub fn read_ctrl(&mut self, num: u8) -> u32 {
         assert!(num <= 0x1F);
         // no control registers
-        todo!("cop0 ctrl read {}", num)
+        unimplemented::report(
+            &mut self.pending_unimplemented,
+            HardwareComponent::Cop0,
+            format!("cop0 ctrl read {}", num),
+        );
+        0
     }
 
     pub fn write_ctrl(&mut self, num: u8, data: u32) {
         assert!(num <= 0x1F);
         // no control registers
-        todo!("cop0 ctrl write {}, data={:08X}", num, data)
+        unimplemented::report(
+            &mut self.pending_unimplemented,
+            HardwareComponent::Cop0,
+            format!("cop0 ctrl write {}, data={:08X}", num, data),
+        );
     }
 
-    pub fn read_data(&self, num: u8) -> u32 {
+    pub fn read_data(&mut self, num: u8) -> u32 {
         assert!(num <= 0x1F);
 
         let out = match num {
@@ -81,7 +102,14 @@ impl SystemControlCoprocessor {
             // the return value is usually 00000020h, or when reading much
             // later it returns 00000040h, or even 00000100h.
             16..=31 => 0xFF,
-            0..=15 => todo!("cop0 data read {}", num),
+            0..=15 => {
+                unimplemented::report(
+                    &mut self.pending_unimplemented,
+                    HardwareComponent::Cop0,
+                    format!("cop0 data read {}", num),
+                );
+                0
+            }
             _ => unreachable!(),
         };
         log::info!("cop0 data read {}, data={:08X}", num, out);
@@ -110,7 +138,11 @@ impl SystemControlCoprocessor {
             //14 => {}
             //15 -> {}
             16..=31 => {} // garbage
-            0..=15 => todo!("cop0 data write {}, vaule {:08X}", num, data),
+            0..=15 => unimplemented::report(
+                &mut self.pending_unimplemented,
+                HardwareComponent::Cop0,
+                format!("cop0 data write {}, vaule {:08X}", num, data),
+            ),
             _ => unreachable!(),
         }
     }
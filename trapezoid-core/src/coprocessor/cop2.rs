@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug)]
 enum GteCommandOpcode {
     Na,
@@ -99,7 +101,8 @@ impl GteCommand {
 }
 
 bitflags::bitflags! {
-    #[derive(Default, Clone, Copy)]
+    #[derive(Default, Clone, Copy, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct Flag: u32 {
         const IR0_SATURATED_TO_P0000_P1000                = 0b00000000000000000001000000000000;
         const SY2_SATURATED_TO_N0400_P03FF                = 0b00000000000000000010000000000000;
@@ -141,7 +144,7 @@ fn get_rgb(rgb: u32) -> (i64, i64, i64) {
     (r, g, b)
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct Gte {
     vectors: [[i16; 3]; 3],
     rgbc: u32,
@@ -4,8 +4,12 @@ mod instruction;
 mod instructions_table;
 mod register;
 
+use std::io::{Read, Write};
+
 use crate::coprocessor::{Gte, SystemControlCoprocessor};
 use crate::memory::BusLine;
+use crate::unimplemented::{self, Report};
+use crate::HardwareComponent;
 
 pub use instruction::{Instruction, Opcode};
 pub use register::{RegisterType, Registers, CPU_REGISTERS};
@@ -88,7 +92,7 @@ enum Exception {
     ArithmeticOverflow = 0x0C,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CpuState {
     /// Normal execution, no breakpoints
     Normal,
@@ -120,6 +124,13 @@ pub enum CpuState {
     #[cfg(feature = "debugger")]
     /// Continue execution until the CPU exit the current function
     StepOut,
+
+    /// A hardware feature that is not emulated was used, the emulation continues
+    /// with a best-effort stub, so things might not behave correctly afterwards.
+    UnimplementedHardware {
+        component: HardwareComponent,
+        detail: String,
+    },
 }
 
 pub struct Cpu {
@@ -134,6 +145,8 @@ pub struct Cpu {
     shell_reached: bool,
     current_instr_pc: u32,
 
+    pending_unimplemented: Option<Report>,
+
     debugger: Debugger,
 }
 
@@ -150,6 +163,8 @@ impl Cpu {
             shell_reached: false,
             current_instr_pc: 0,
 
+            pending_unimplemented: None,
+
             debugger: Debugger::new(),
         }
     }
@@ -162,6 +177,39 @@ impl Cpu {
         self.elapsed_cycles = 0;
         self.shell_reached = false;
         self.current_instr_pc = 0;
+        self.pending_unimplemented = None;
+    }
+
+    /// The first unimplemented instruction or cop0 register used since the last call
+    pub(crate) fn take_unimplemented(&mut self) -> Option<Report> {
+        self.pending_unimplemented
+            .take()
+            .or_else(|| self.cop0.take_unimplemented())
+    }
+
+    /// The debugger is not part of the state, breakpoints stay as they are
+    pub(crate) fn save_state<W: Write>(&self, mut writer: W) -> bincode::Result<()> {
+        bincode::serialize_into(&mut writer, &self.regs)?;
+        bincode::serialize_into(&mut writer, &self.cop0)?;
+        bincode::serialize_into(&mut writer, &self.cop2)?;
+        bincode::serialize_into(&mut writer, &self.jump_dest_next)?;
+        bincode::serialize_into(&mut writer, &self.elapsed_cycles)?;
+        bincode::serialize_into(&mut writer, &self.shell_reached)?;
+        bincode::serialize_into(&mut writer, &self.current_instr_pc)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn load_state<R: Read>(&mut self, mut reader: R) -> bincode::Result<()> {
+        self.regs = bincode::deserialize_from(&mut reader)?;
+        self.cop0 = bincode::deserialize_from(&mut reader)?;
+        self.cop2 = bincode::deserialize_from(&mut reader)?;
+        self.jump_dest_next = bincode::deserialize_from(&mut reader)?;
+        self.elapsed_cycles = bincode::deserialize_from(&mut reader)?;
+        self.shell_reached = bincode::deserialize_from(&mut reader)?;
+        self.current_instr_pc = bincode::deserialize_from(&mut reader)?;
+
+        Ok(())
     }
 
     pub fn registers(&self) -> &Registers {
@@ -861,7 +909,18 @@ impl Cpu {
                 self.execute_exception(Exception::ReservedInstruction);
             }
             Opcode::SecondaryOpcode => unreachable!(),
-            _ => todo!("unimplemented_instruction {:?}", instruction.opcode),
+            _ => {
+                unimplemented::report(
+                    &mut self.pending_unimplemented,
+                    HardwareComponent::Cpu,
+                    format!(
+                        "unimplemented_instruction {:?} at {:08X}",
+                        instruction.opcode, self.current_instr_pc
+                    ),
+                );
+                // best we can do is to act like the instruction doesn't exist
+                self.execute_exception(Exception::ReservedInstruction);
+            }
         }
     }
 }
@@ -65,7 +65,7 @@ impl Debugger {
     }
 
     pub(crate) fn last_state(&self) -> CpuState {
-        self.last_state
+        self.last_state.clone()
     }
 
     pub(crate) fn clear_state(&mut self) {
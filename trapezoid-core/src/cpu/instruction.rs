@@ -548,8 +548,9 @@ impl fmt::Display for Instruction {
             Opcode::Mfc(_) | Opcode::Cfc(_) | Opcode::Mtc(_) | Opcode::Ctc(_) => {
                 format_cop_ops(f, self)
             }
-            Opcode::Bcf(_) => todo!(),
-            Opcode::Bct(_) => todo!(),
+            Opcode::Bcf(_) | Opcode::Bct(_) => {
+                write!(f, "{} 0x{:04X}", opcode_str(self.opcode), self.imm16())
+            }
             Opcode::Lwc(_) | Opcode::Swc(_) => format_load_store(f, self),
             Opcode::Invalid => write!(f, "Invalid instruction"),
             _ => unreachable!(),
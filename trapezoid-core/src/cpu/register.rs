@@ -1,5 +1,7 @@
 use std::fmt;
 
+use serde::{Deserialize, Serialize};
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(u8)]
 pub enum RegisterType {
@@ -134,6 +136,7 @@ impl fmt::Display for RegisterType {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Registers {
     pub(crate) general_regs: [u32; 32],
     pub(crate) pc: u32,
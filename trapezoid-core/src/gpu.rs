@@ -1,42 +1,39 @@
 mod command;
 mod common;
+mod frame_buffer;
+pub mod renderer;
+pub mod software;
 
 #[cfg(feature = "vulkan")]
 pub mod vulkan;
 
-#[cfg(feature = "vulkan")]
-pub use vulkan as backend;
-
-#[cfg(not(feature = "vulkan"))]
-pub mod dummy_render;
-
-#[cfg(not(feature = "vulkan"))]
-pub use dummy_render as backend;
-
 use crate::memory::{interrupts::InterruptRequester, BusLine, Result};
-use backend::StandardCommandBufferAllocator;
+use crate::unimplemented::{self, Report};
+use crate::HardwareComponent;
 use command::{instantiate_gp0_command, Gp0CmdType, Gp0Command};
+use renderer::{AnyFrontImage, GpuBackend, Renderer};
+
+pub use frame_buffer::{FrameBuffer, PixelFormat};
 
 use crossbeam::{
     atomic::AtomicCell,
     channel::{Receiver, Sender},
 };
+use serde::{Deserialize, Serialize};
 
-#[cfg(feature = "vulkan")]
-use std::thread::JoinHandle;
-
-use std::{ops::Range, sync::Arc};
+use std::{
+    io::{Read, Write},
+    ops::Range,
+    sync::Arc,
+    thread::JoinHandle,
+};
 
 use common::{DrawingTextureParams, DrawingVertex};
 
-pub use backend::{Device, GpuFuture, Image, Queue};
-
-#[cfg(feature = "vulkan")]
-pub use backend::{AutoCommandBufferBuilder, BlitImageInfo, CommandBufferUsage, Filter};
-
 bitflags::bitflags! {
-    #[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
-    struct GpuStat: u32 {
+    #[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(transparent)]
+    pub struct GpuStat: u32 {
         const TEXTURE_PAGE_X_BASE      = 0b00000000000000000000000000001111;
         const TEXTURE_PAGE_Y_BASE      = 0b00000000000000000000000000010000;
         const SEMI_TRASPARENCY         = 0b00000000000000000000000001100000;
@@ -73,7 +70,7 @@ impl GpuStat {
         (x, y)
     }
 
-    fn horizontal_resolution(&self) -> u32 {
+    pub fn horizontal_resolution(&self) -> u32 {
         if self.intersects(Self::HORIZONTAL_RESOLUTION2) {
             368
         } else {
@@ -94,7 +91,7 @@ impl GpuStat {
 
     // divider to get the dots per scanline
     // dots_per_line = cycles_per_line / divider
-    fn horizontal_dots_divider(&self) -> u32 {
+    pub fn horizontal_dots_divider(&self) -> u32 {
         if self.intersects(Self::HORIZONTAL_RESOLUTION2) {
             7
         } else {
@@ -115,16 +112,16 @@ impl GpuStat {
         }
     }
 
-    fn vertical_resolution(&self) -> u32 {
+    pub fn vertical_resolution(&self) -> u32 {
         240 << (self.intersects(Self::VERTICAL_RESOLUTION)
             && self.intersects(Self::VERTICAL_INTERLACE)) as u32
     }
 
-    fn is_24bit_color_depth(&self) -> bool {
+    pub fn is_24bit_color_depth(&self) -> bool {
         self.intersects(Self::DISPLAY_AREA_COLOR_DEPTH)
     }
 
-    fn is_ntsc_video_mode(&self) -> bool {
+    pub fn is_ntsc_video_mode(&self) -> bool {
         !self.intersects(Self::VIDEO_MODE)
     }
 
@@ -132,11 +129,11 @@ impl GpuStat {
         !self.intersects(Self::DISPLAY_DISABLED)
     }
 
-    fn semi_transparency_mode(&self) -> u8 {
+    pub fn semi_transparency_mode(&self) -> u8 {
         ((self.bits() & Self::SEMI_TRASPARENCY.bits()) >> 5) as u8
     }
 
-    fn dither_enabled(&self) -> bool {
+    pub fn dither_enabled(&self) -> bool {
         self.intersects(Self::DITHER_ENABLED)
     }
 
@@ -156,22 +153,27 @@ impl GpuStat {
 /// The state of the gpu at the execution of the command in the rendering thread
 /// Because the state can chanage after setting the command but before execution,
 /// we need to send the current state and keep it unmodified until the command is executed.
-#[derive(Clone, Default)]
-struct GpuStateSnapshot {
-    gpu_stat: GpuStat,
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct GpuStateSnapshot {
+    pub gpu_stat: GpuStat,
 
     allow_texture_disable: bool,
     textured_rect_flip: (bool, bool),
 
-    drawing_area_top_left: (u32, u32),
-    drawing_area_bottom_right: (u32, u32),
-    drawing_offset: (i32, i32),
-    texture_window_mask: (u32, u32),
-    texture_window_offset: (u32, u32),
-
-    vram_display_area_start: (u32, u32),
-    display_horizontal_range: (u32, u32),
-    display_vertical_range: (u32, u32),
+    /// Inclusive drawing area, nothing is drawn outside it
+    pub drawing_area_top_left: (u32, u32),
+    pub drawing_area_bottom_right: (u32, u32),
+    /// Added to all vertices positions
+    pub drawing_offset: (i32, i32),
+    /// In 8 pixel steps
+    pub texture_window_mask: (u32, u32),
+    pub texture_window_offset: (u32, u32),
+
+    pub vram_display_area_start: (u32, u32),
+    /// In GPU video clock cycles
+    pub display_horizontal_range: (u32, u32),
+    /// In scanlines
+    pub display_vertical_range: (u32, u32),
 
     // These are only used for handleing GP1(0x10) command, so instead of creating
     // the values again from the individual parts, we just cache it
@@ -189,6 +191,8 @@ enum BackendCommand {
     DrawPolyline {
         vertices: Vec<DrawingVertex>,
         semi_transparent: bool,
+        /// Dither if enabled in gpustat, only gouraud shaded lines are dithered
+        dithered: bool,
         state_snapshot: GpuStateSnapshot,
     },
     DrawPolygon {
@@ -197,15 +201,22 @@ enum BackendCommand {
         textured: bool,
         texture_blending: bool,
         semi_transparent: bool,
+        /// Dither if enabled in gpustat, only gouraud shaded and texture blended
+        /// polygons are dithered, rectangles are never dithered
+        dithered: bool,
         state_snapshot: GpuStateSnapshot,
     },
     WriteVramBlock {
         block_range: (Range<u32>, Range<u32>),
         block: Vec<u16>,
+        /// For the mask settings
+        gpu_stat: GpuStat,
     },
     VramVramBlit {
         src: (Range<u32>, Range<u32>),
         dst: (Range<u32>, Range<u32>),
+        /// For the mask settings
+        gpu_stat: GpuStat,
     },
     VramReadBlock {
         block_range: (Range<u32>, Range<u32>),
@@ -215,31 +226,48 @@ enum BackendCommand {
         size: (u32, u32),
         color: (u8, u8, u8),
     },
+    /// Read the whole 1024x512 VRAM and send it back through `result_sender`
+    DumpVram { result_sender: Sender<Vec<u16>> },
 }
 
-pub struct Gpu {
-    // used for blitting to frontend
-    queue: Arc<Queue>,
-    device: Arc<Device>,
+/// Everything the `Gpu` needs to continue from a save state,
+/// the rest (channels, backend thread, ...) are recreated and not saved.
+#[derive(Serialize, Deserialize)]
+struct GpuState {
+    gpu_stat: GpuStat,
+    state_snapshot: GpuStateSnapshot,
+    current_command_words: Vec<u32>,
+    gpu_read_fifo: Vec<u32>,
+
+    scanline: u32,
+    dot: u32,
+    drawing_odd: bool,
+    in_vblank: bool,
+    cpu_cycles_counter: u32,
+
+    vram: Vec<u16>,
+}
 
+pub struct Gpu {
     // handle the backend gpu thread
-    #[cfg(feature = "vulkan")]
     _gpu_backend_thread_handle: JoinHandle<()>,
 
     /// holds commands that needs extra parameter and complex, like sending
     /// to/from VRAM, and rendering
     current_command: Option<Box<dyn Gp0Command>>,
+    /// The words received so far for `current_command`, used to recreate it
+    /// when loading a save state, since the command itself can't be serialized
+    current_command_words: Vec<u32>,
     // GPUREAD channel
     gpu_read_sender: Sender<u32>,
     gpu_read_receiver: Receiver<u32>,
     // backend commands channel
     gpu_backend_sender: Sender<BackendCommand>,
     // channel for front image coming from backend
-    gpu_front_image_receiver: Receiver<Arc<Image>>,
+    gpu_front_image_receiver: Receiver<AnyFrontImage>,
 
     first_frame: bool,
-    current_front_image: Option<Arc<Image>>,
-    command_buffer_allocator: StandardCommandBufferAllocator,
+    current_front_image: Option<AnyFrontImage>,
 
     // shared GPUSTAT
     gpu_stat: Arc<AtomicCell<GpuStat>>,
@@ -251,43 +279,25 @@ pub struct Gpu {
     in_vblank: bool,
 
     cpu_cycles_counter: u32,
+
+    pending_unimplemented: Option<Report>,
 }
 
 impl Gpu {
-    pub fn new(device: Arc<Device>, queue: Arc<Queue>) -> Self {
+    /// `renderer_builder` is called on the rendering thread to create the renderer
+    pub fn new<R, F>(renderer_builder: F) -> Self
+    where
+        R: Renderer,
+        F: FnOnce() -> R + Send + 'static,
+    {
         let (gpu_read_sender, gpu_read_receiver) = crossbeam::channel::unbounded();
         let (gpu_backend_sender, gpu_backend_receiver) = crossbeam::channel::unbounded();
         let (gpu_front_image_sender, gpu_front_image_receiver) = crossbeam::channel::unbounded();
 
-        let gpu_stat = Arc::new(AtomicCell::new(
-            GpuStat::READY_FOR_CMD_RECV | GpuStat::READY_FOR_DMA_RECV,
-        ));
-
-        let state_snapshot = GpuStateSnapshot {
-            gpu_stat: gpu_stat.load(),
-            allow_texture_disable: false,
-            textured_rect_flip: (false, false),
-
-            drawing_area_top_left: (0, 0),
-            drawing_area_bottom_right: (0, 0),
-            drawing_offset: (0, 0),
-            texture_window_mask: (0, 0),
-            texture_window_offset: (0, 0),
-
-            cached_gp0_e2: 0,
-            cached_gp0_e3: 0,
-            cached_gp0_e4: 0,
-            cached_gp0_e5: 0,
-
-            vram_display_area_start: (0, 0),
-            display_horizontal_range: (0, 0),
-            display_vertical_range: (0, 0),
-        };
+        let gpu_stat = Arc::new(AtomicCell::new(Self::initial_gpu_stat()));
 
-        #[cfg(feature = "vulkan")]
-        let _gpu_backend_thread_handle = backend::GpuBackend::start(
-            device.clone(),
-            queue.clone(),
+        let _gpu_backend_thread_handle = GpuBackend::start(
+            renderer_builder,
             gpu_stat.clone(),
             gpu_read_sender.clone(),
             gpu_backend_receiver,
@@ -295,13 +305,10 @@ impl Gpu {
         );
 
         Self {
-            queue,
-            device: device.clone(),
-
-            #[cfg(feature = "vulkan")]
             _gpu_backend_thread_handle,
 
             current_command: None,
+            current_command_words: Vec::new(),
             gpu_read_sender,
             gpu_read_receiver,
             gpu_backend_sender,
@@ -309,24 +316,49 @@ impl Gpu {
 
             first_frame: true,
             current_front_image: None,
-            command_buffer_allocator: StandardCommandBufferAllocator::new(
-                device,
-                Default::default(),
-            ),
 
+            state_snapshot: GpuStateSnapshot {
+                gpu_stat: gpu_stat.load(),
+                ..Default::default()
+            },
             gpu_stat,
-            state_snapshot,
 
             scanline: 0,
             dot: 0,
             drawing_odd: false,
             in_vblank: false,
             cpu_cycles_counter: 0,
+
+            pending_unimplemented: None,
         }
     }
 
+    fn initial_gpu_stat() -> GpuStat {
+        GpuStat::READY_FOR_CMD_RECV | GpuStat::READY_FOR_DMA_RECV
+    }
+
+    /// Resets the state of the GPU, the renderer and its VRAM are kept as is.
     pub fn reset(&mut self) {
-        let _ = std::mem::replace(self, Self::new(self.device.clone(), self.queue.clone()));
+        self.current_command = None;
+        self.current_command_words.clear();
+        while self.gpu_read_receiver.try_recv().is_ok() {}
+
+        self.gpu_stat.store(Self::initial_gpu_stat());
+        self.state_snapshot = GpuStateSnapshot {
+            gpu_stat: self.gpu_stat.load(),
+            ..Default::default()
+        };
+
+        self.scanline = 0;
+        self.dot = 0;
+        self.drawing_odd = false;
+        self.in_vblank = false;
+        self.cpu_cycles_counter = 0;
+        self.pending_unimplemented = None;
+    }
+
+    pub(crate) fn take_unimplemented(&mut self) -> Option<Report> {
+        self.pending_unimplemented.take()
     }
 
     /// returns the number of `dot_clocks`, and if `hblank_clock` occurres
@@ -412,23 +444,9 @@ impl Gpu {
         self.in_vblank
     }
 
-    #[cfg(not(feature = "vulkan"))]
-    pub fn sync_gpu_and_blit_to_front(
-        &mut self,
-        _dest_image: Arc<Image>,
-        _full_vram: bool,
-        in_future: Box<dyn GpuFuture>,
-    ) -> Box<dyn GpuFuture> {
-        in_future
-    }
-
-    #[cfg(feature = "vulkan")]
-    pub fn sync_gpu_and_blit_to_front(
-        &mut self,
-        dest_image: Arc<Image>,
-        full_vram: bool,
-        in_future: Box<dyn GpuFuture>,
-    ) -> Box<dyn GpuFuture> {
+    /// Waits for the front image of the previous frame and requests the next one,
+    /// returns `None` on the first frame.
+    pub fn sync_front_image(&mut self, full_vram: bool) -> Option<AnyFrontImage> {
         // if we have a previous image, then we are not in the first frame,
         // so there should be an image in the channel.
         if !self.first_frame {
@@ -447,35 +465,111 @@ impl Gpu {
             })
             .unwrap();
 
-        if let Some(img) = self.current_front_image.as_ref() {
-            let mut builder: AutoCommandBufferBuilder<
-                crate::gpu::vulkan::PrimaryAutoCommandBuffer,
-            > = AutoCommandBufferBuilder::primary(
-                &self.command_buffer_allocator,
-                self.queue.queue_family_index(),
-                CommandBufferUsage::OneTimeSubmit,
-            )
+        self.current_front_image.clone()
+    }
+
+    /// Converts the VRAM into pixels, only the display area unless `full_vram` is set.
+    ///
+    /// Waits for the renderer to finish all pending drawing.
+    pub fn frame_buffer(&self, full_vram: bool, format: PixelFormat) -> FrameBuffer {
+        let mut state_snapshot = self.state_snapshot.clone();
+        state_snapshot.gpu_stat = self.gpu_stat.load();
+
+        FrameBuffer::from_vram(&self.dump_vram(), full_vram, &state_snapshot, format)
+    }
+}
+
+// save states
+impl Gpu {
+    fn dump_vram(&self) -> Vec<u16> {
+        let (result_sender, result_receiver) = crossbeam::channel::bounded(1);
+        self.gpu_backend_sender
+            .send(BackendCommand::DumpVram { result_sender })
+            .unwrap();
+        // the backend executes commands in order, so this will also wait
+        // for all pending drawing to finish
+        result_receiver.recv().unwrap()
+    }
+
+    fn restore_vram(&self, vram: Vec<u16>) {
+        self.gpu_backend_sender
+            .send(BackendCommand::WriteVramBlock {
+                block_range: (0..1024, 0..512),
+                block: vram,
+                // restored as is, without the mask settings
+                gpu_stat: GpuStat::empty(),
+            })
             .unwrap();
+    }
 
-            builder
-                .blit_image(BlitImageInfo {
-                    filter: Filter::Nearest,
-                    ..BlitImageInfo::images(img.clone(), dest_image)
-                })
-                .unwrap();
-            let cb = builder.build().unwrap();
-
-            // TODO: remove wait
-            in_future
-                .then_execute(self.queue.clone(), cb)
-                .unwrap()
-                .then_signal_fence_and_flush()
-                .unwrap()
-                .boxed()
-        } else {
-            // we must flush the future even if we are not using it.
-            in_future
+    pub fn save_state<W: Write>(&self, writer: W) -> bincode::Result<()> {
+        // must be done first, so that any pending `VramReadBlock` is in the GPUREAD fifo
+        let vram = self.dump_vram();
+
+        // we can't peek into the channel, so take everything and put it back
+        let gpu_read_fifo = self.gpu_read_receiver.try_iter().collect::<Vec<_>>();
+        for &data in &gpu_read_fifo {
+            self.gpu_read_sender.send(data).unwrap();
+        }
+
+        let state = GpuState {
+            gpu_stat: self.gpu_stat.load(),
+            state_snapshot: self.state_snapshot.clone(),
+            current_command_words: self.current_command_words.clone(),
+            gpu_read_fifo,
+
+            scanline: self.scanline,
+            dot: self.dot,
+            drawing_odd: self.drawing_odd,
+            in_vblank: self.in_vblank,
+            cpu_cycles_counter: self.cpu_cycles_counter,
+
+            vram,
+        };
+
+        bincode::serialize_into(writer, &state)
+    }
+
+    pub fn load_state<R: Read>(&mut self, reader: R) -> bincode::Result<()> {
+        let state: GpuState = bincode::deserialize_from(reader)?;
+
+        if state.vram.len() != 1024 * 512 {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "invalid vram size {}",
+                state.vram.len()
+            ))));
+        }
+
+        // drop anything left from before the load
+        while self.gpu_read_receiver.try_recv().is_ok() {}
+        for data in state.gpu_read_fifo {
+            self.gpu_read_sender.send(data).unwrap();
         }
+
+        // recreate the command by feeding it the same words again
+        self.current_command = state
+            .current_command_words
+            .split_first()
+            .map(|(&first, rest)| {
+                let mut cmd = instantiate_gp0_command(first);
+                for &param in rest {
+                    cmd.add_param(param);
+                }
+                cmd
+            });
+        self.current_command_words = state.current_command_words;
+
+        self.gpu_stat.store(state.gpu_stat);
+        self.state_snapshot = state.state_snapshot;
+        self.scanline = state.scanline;
+        self.dot = state.dot;
+        self.drawing_odd = state.drawing_odd;
+        self.in_vblank = state.in_vblank;
+        self.cpu_cycles_counter = state.cpu_cycles_counter;
+
+        self.restore_vram(state.vram);
+
+        Ok(())
     }
 }
 
@@ -520,19 +614,17 @@ impl Gpu {
             if cmd.still_need_params() {
                 log::trace!("gp0 extra param {:08X}", data);
                 cmd.add_param(data);
+                self.current_command_words.push(data);
                 if !cmd.still_need_params() {
                     let cmd = self.current_command.take().unwrap();
+                    self.current_command_words.clear();
 
                     self.gpu_stat
                         .fetch_update(|s| Some(s - GpuStat::READY_FOR_DMA_RECV))
                         .unwrap();
 
                     log::info!("executing command {:?}", cmd.cmd_type());
-                    if let Some(backend_cmd) =
-                        cmd.exec_command(self.gpu_stat.clone(), &mut self.state_snapshot)
-                    {
-                        self.gpu_backend_sender.send(backend_cmd).unwrap();
-                    }
+                    self.exec_gp0_command(cmd);
 
                     // ready for next command
                     self.gpu_stat
@@ -549,20 +641,33 @@ impl Gpu {
             log::info!("creating new command {:?}", cmd.cmd_type());
             if cmd.still_need_params() {
                 self.current_command = Some(cmd);
+                self.current_command_words.push(data);
                 self.gpu_stat
                     .fetch_update(|s| Some(s - GpuStat::READY_FOR_CMD_RECV))
                     .unwrap();
             } else {
                 log::info!("executing command {:?}", cmd.cmd_type());
-                if let Some(backend_cmd) =
-                    cmd.exec_command(self.gpu_stat.clone(), &mut self.state_snapshot)
-                {
-                    self.gpu_backend_sender.send(backend_cmd).unwrap();
-                }
+                self.exec_gp0_command(cmd);
             }
         }
     }
 
+    /// Executes a gp0 command that has all its parameters, and sends what needs
+    /// rendering to the backend
+    fn exec_gp0_command(&mut self, cmd: Box<dyn Gp0Command>) {
+        if let Some(detail) = cmd.unimplemented() {
+            unimplemented::report(
+                &mut self.pending_unimplemented,
+                HardwareComponent::Gpu,
+                detail,
+            );
+        }
+        if let Some(backend_cmd) = cmd.exec_command(self.gpu_stat.clone(), &mut self.state_snapshot)
+        {
+            self.gpu_backend_sender.send(backend_cmd).unwrap();
+        }
+    }
+
     /// Execute instructions we can from frontend, or else send to backend.
     /// This allows for GPU_STAT register to be synced.
     fn handle_gp1(&mut self, data: u32) {
@@ -589,14 +694,11 @@ impl Gpu {
                         let cmd = self.current_command.take().unwrap();
                         // CpuToVramBlit supports interrupts, and will only send
                         // the rows that are written to the vram.
-                        if let Some(backend_cmd) =
-                            cmd.exec_command(self.gpu_stat.clone(), &mut self.state_snapshot)
-                        {
-                            self.gpu_backend_sender.send(backend_cmd).unwrap();
-                        }
+                        self.exec_gp0_command(cmd);
                     }
                 }
                 self.current_command = None;
+                self.current_command_words.clear();
             }
             0x02 => {
                 // Reset IRQ
@@ -733,12 +835,18 @@ impl Gpu {
 
                 self.gpu_read_sender.send(result).unwrap();
             }
-            _ => todo!("gp1 command {:02X}", cmd),
+            _ => unimplemented::report(
+                &mut self.pending_unimplemented,
+                HardwareComponent::Gpu,
+                format!("gp1 command {:02X}", cmd),
+            ),
         }
     }
 }
 
 impl BusLine for Gpu {
+    const COMPONENT: HardwareComponent = HardwareComponent::Gpu;
+
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         let r = match addr {
             0 => self.gpu_read(),
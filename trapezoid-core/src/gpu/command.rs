@@ -54,6 +54,10 @@ pub(super) trait Gp0Command: Send {
     ) -> Option<BackendCommand>;
     fn still_need_params(&mut self) -> bool;
     fn cmd_type(&self) -> Gp0CmdType;
+    /// Describes the command if it's not emulated, it's executed as a no-op
+    fn unimplemented(&self) -> Option<String> {
+        None
+    }
 }
 
 #[derive(Debug)]
@@ -156,6 +160,7 @@ impl Gp0Command for PolygonCommand {
             textured: self.textured,
             texture_blending: self.texture_blending,
             semi_transparent: self.semi_transparent,
+            dithered: self.gouraud || (self.textured && self.texture_blending),
             state_snapshot: state_snapshot.clone(),
         })
     }
@@ -257,6 +262,7 @@ impl Gp0Command for LineCommand {
         Some(BackendCommand::DrawPolyline {
             vertices: self.vertices,
             semi_transparent: self.semi_transparent,
+            dithered: self.gouraud,
             state_snapshot: state_snapshot.clone(),
         })
     }
@@ -411,6 +417,7 @@ impl Gp0Command for RectangleCommand {
             textured: self.textured,
             texture_blending: self.texture_blending,
             semi_transparent: self.semi_transparent,
+            dithered: false,
             state_snapshot: state_snapshot.clone(),
         })
     }
@@ -452,7 +459,8 @@ impl Gp0Command for MiscCommand {
             0x01 => {
                 // Invalidate CLUT cache
             }
-            _ => todo!("gp0 misc command {:02X}", cmd),
+            // see `unimplemented`
+            _ => {}
         }
         None
     }
@@ -464,6 +472,14 @@ impl Gp0Command for MiscCommand {
     fn cmd_type(&self) -> Gp0CmdType {
         Gp0CmdType::Misc
     }
+
+    fn unimplemented(&self) -> Option<String> {
+        let cmd = self.0 >> 24;
+        match cmd {
+            0x00 | 0x01 | 0x03..=0x1E => None,
+            _ => Some(format!("gp0 misc command {:02X}", cmd)),
+        }
+    }
 }
 
 struct CpuToVramBlitCommand {
@@ -531,9 +547,11 @@ impl Gp0Command for CpuToVramBlitCommand {
 
     fn exec_command(
         mut self: Box<Self>,
-        _gpu_stat: Arc<AtomicCell<GpuStat>>,
+        gpu_stat: Arc<AtomicCell<GpuStat>>,
         _state_snapshot: &mut GpuStateSnapshot,
     ) -> Option<BackendCommand> {
+        let gpu_stat = gpu_stat.load();
+
         // command was executed normally
         if !self.still_need_params() {
             let x_range = (self.dest.0)..(self.dest.0 + self.size.0);
@@ -542,6 +560,7 @@ impl Gp0Command for CpuToVramBlitCommand {
             Some(BackendCommand::WriteVramBlock {
                 block_range: (x_range, y_range),
                 block: self.block,
+                gpu_stat,
             })
         } else {
             // command was aborted in the middle, let's just transfer the data we have
@@ -557,6 +576,7 @@ impl Gp0Command for CpuToVramBlitCommand {
                 Some(BackendCommand::WriteVramBlock {
                     block_range: (x_range, y_range),
                     block: self.block,
+                    gpu_stat,
                 })
             } else {
                 // FIXME: we are sending only the full rows now and discarding the rest
@@ -567,6 +587,7 @@ impl Gp0Command for CpuToVramBlitCommand {
                 Some(BackendCommand::WriteVramBlock {
                     block_range: (x_range, y_range),
                     block: self.block[..(n_rows * self.size.0 as usize)].to_vec(),
+                    gpu_stat,
                 })
             }
         }
@@ -630,7 +651,7 @@ impl Gp0Command for VramToVramBlitCommand {
 
     fn exec_command(
         mut self: Box<Self>,
-        _gpu_stat: Arc<AtomicCell<GpuStat>>,
+        gpu_stat: Arc<AtomicCell<GpuStat>>,
         _state_snapshot: &mut GpuStateSnapshot,
     ) -> Option<BackendCommand> {
         assert!(!self.still_need_params());
@@ -642,7 +663,11 @@ impl Gp0Command for VramToVramBlitCommand {
         let x_range = (self.dest.0)..(self.dest.0 + self.size.0);
         let y_range = (self.dest.1)..(self.dest.1 + self.size.1);
         let dst = (x_range, y_range);
-        Some(BackendCommand::VramVramBlit { src, dst })
+        Some(BackendCommand::VramVramBlit {
+            src,
+            dst,
+            gpu_stat: gpu_stat.load(),
+        })
     }
 
     fn still_need_params(&mut self) -> bool {
@@ -914,7 +939,8 @@ impl Gp0Command for EnvironmentCommand {
                     })
                     .unwrap();
             }
-            _ => todo!("gp0 environment command {:02X}", cmd),
+            // see `unimplemented`
+            _ => {}
         }
 
         None
@@ -927,6 +953,14 @@ impl Gp0Command for EnvironmentCommand {
     fn cmd_type(&self) -> Gp0CmdType {
         Gp0CmdType::Environment
     }
+
+    fn unimplemented(&self) -> Option<String> {
+        let cmd = self.0 >> 24;
+        match cmd {
+            0xE1..=0xE6 => None,
+            _ => Some(format!("gp0 environment command {:02X}", cmd)),
+        }
+    }
 }
 
 #[test]
@@ -949,7 +983,10 @@ fn cpu_to_vram_interrupt_less_than_1_row() {
 
     let backend_cmd = cmd.exec_command(gpu_stat.clone(), &mut state_snapshot);
 
-    if let Some(BackendCommand::WriteVramBlock { block_range, block }) = backend_cmd {
+    if let Some(BackendCommand::WriteVramBlock {
+        block_range, block, ..
+    }) = backend_cmd
+    {
         assert_eq!(block_range, (0..6, 0..1));
         assert_eq!(block, vec![0; 6]);
     } else {
@@ -989,7 +1026,10 @@ fn cpu_to_vram_interrupt_more_than_1_row() {
     let backend_cmd = cmd.exec_command(gpu_stat.clone(), &mut state_snapshot);
 
     // TODO: this is truncating to the full rows, it should also have content of the half rows
-    if let Some(BackendCommand::WriteVramBlock { block_range, block }) = backend_cmd {
+    if let Some(BackendCommand::WriteVramBlock {
+        block_range, block, ..
+    }) = backend_cmd
+    {
         assert_eq!(block_range, (0..10, 0..2));
         assert_eq!(block, vec![0; 20]);
     } else {
@@ -1032,7 +1072,10 @@ fn cpu_to_vram_interrupt_full_rows() {
     let backend_cmd = cmd.exec_command(gpu_stat.clone(), &mut state_snapshot);
 
     // TODO: this is truncating to the full rows, it should also have content of the half rows
-    if let Some(BackendCommand::WriteVramBlock { block_range, block }) = backend_cmd {
+    if let Some(BackendCommand::WriteVramBlock {
+        block_range, block, ..
+    }) = backend_cmd
+    {
         assert_eq!(block_range, (0..10, 0..3));
         assert_eq!(block, vec![0; 30]);
     } else {
@@ -1061,7 +1104,10 @@ fn cpu_to_vram_not_interrupted() {
     let backend_cmd = cmd.exec_command(gpu_stat.clone(), &mut state_snapshot);
 
     // TODO: this is truncating to the full rows, it should also have content of the half rows
-    if let Some(BackendCommand::WriteVramBlock { block_range, block }) = backend_cmd {
+    if let Some(BackendCommand::WriteVramBlock {
+        block_range, block, ..
+    }) = backend_cmd
+    {
         assert_eq!(block_range, (0..10, 0..10));
         assert_eq!(block, vec![0; 100]);
     } else {
@@ -0,0 +1,197 @@
+//! Converting the raw 16bit VRAM into displayable pixels.
+
+use super::GpuStateSnapshot;
+
+const VRAM_WIDTH: u32 = 1024;
+const VRAM_HEIGHT: u32 = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 3 bytes per pixel, red, green, blue
+    Rgb888,
+    /// 4 bytes per pixel, red, green, blue, and alpha which is always `0xFF`
+    Rgba8888,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgb888 => 3,
+            PixelFormat::Rgba8888 => 4,
+        }
+    }
+}
+
+/// An owned image of the VRAM or a part of it, see [`crate::Psx::frame_buffer`].
+#[derive(Debug, Clone)]
+pub struct FrameBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    /// The pixels, row by row, [`PixelFormat::bytes_per_pixel`] bytes each
+    pub data: Vec<u8>,
+}
+
+impl FrameBuffer {
+    /// Converts the part of `vram` that is displayed on screen, or all of it if `full_vram` is set.
+    ///
+    /// In 24bit color depth, the display area is interpreted as packed 24bit pixels,
+    /// but the full VRAM is always interpreted as 15bit pixels.
+    pub(crate) fn from_vram(
+        vram: &[u16],
+        full_vram: bool,
+        state_snapshot: &GpuStateSnapshot,
+        format: PixelFormat,
+    ) -> Self {
+        assert_eq!(vram.len(), (VRAM_WIDTH * VRAM_HEIGHT) as usize);
+
+        let (topleft, size) = if full_vram {
+            ((0, 0), (VRAM_WIDTH, VRAM_HEIGHT))
+        } else {
+            display_area(state_snapshot)
+        };
+
+        let vram_at = |x: u32, y: u32| {
+            vram[((y & (VRAM_HEIGHT - 1)) * VRAM_WIDTH + (x & (VRAM_WIDTH - 1))) as usize]
+        };
+
+        let mut data =
+            Vec::with_capacity(size.0 as usize * size.1 as usize * format.bytes_per_pixel());
+        let mut push = |[r, g, b]: [u8; 3]| match format {
+            PixelFormat::Rgb888 => data.extend_from_slice(&[r, g, b]),
+            PixelFormat::Rgba8888 => data.extend_from_slice(&[r, g, b, 0xFF]),
+        };
+
+        for y in topleft.1..topleft.1 + size.1 {
+            if !full_vram && state_snapshot.gpu_stat.is_24bit_color_depth() {
+                // in 24bit mode, the display start is still in 16bit units, and
+                // the pixels are packed 3 bytes each
+                let byte_at = |i: u32| (vram_at(i / 2, y) >> ((i & 1) * 8)) as u8;
+                let row_start = topleft.0 * 2;
+                for x in 0..size.0 {
+                    let i = row_start + x * 3;
+                    push([byte_at(i), byte_at(i + 1), byte_at(i + 2)]);
+                }
+            } else {
+                for x in topleft.0..topleft.0 + size.0 {
+                    push(rgb555_to_rgb888(vram_at(x, y)));
+                }
+            }
+        }
+
+        Self {
+            width: size.0,
+            height: size.1,
+            format,
+            data,
+        }
+    }
+}
+
+/// The top left corner and size of the displayed area of the VRAM
+fn display_area(state_snapshot: &GpuStateSnapshot) -> ((u32, u32), (u32, u32)) {
+    let gpu_stat = state_snapshot.gpu_stat;
+
+    // (((X2-X1)/cycles_per_pix)+2) AND NOT 3
+    let (x1, x2) = state_snapshot.display_horizontal_range;
+    let mut horizontal_size =
+        ((x2.saturating_sub(x1) / gpu_stat.horizontal_dots_divider()) + 2) & !3;
+
+    if horizontal_size == 0 {
+        horizontal_size = gpu_stat.horizontal_resolution();
+    }
+
+    let should_double = gpu_stat.vertical_resolution() == 480;
+
+    // Y2-Y1, double if we are interlacing
+    let (y1, y2) = state_snapshot.display_vertical_range;
+    let mut vertical_size = y2.saturating_sub(y1) << should_double as u32;
+
+    if vertical_size == 0 {
+        vertical_size = gpu_stat.vertical_resolution();
+    }
+
+    (
+        state_snapshot.vram_display_area_start,
+        (horizontal_size, vertical_size),
+    )
+}
+
+#[inline]
+fn rgb555_to_rgb888(color: u16) -> [u8; 3] {
+    let c = |shift: u16| {
+        let v = ((color >> shift) & 0x1F) as u8;
+        (v << 3) | (v >> 2)
+    };
+    [c(0), c(5), c(10)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::GpuStat;
+
+    /// 320 pixels wide mode
+    const HORIZONTAL_320: GpuStat = GpuStat::from_bits_retain(1 << 17);
+
+    fn snapshot(gpu_stat: GpuStat) -> GpuStateSnapshot {
+        GpuStateSnapshot {
+            gpu_stat,
+            vram_display_area_start: (16, 8),
+            // 320 pixels wide with the 8 cycles per pixel of 320 mode
+            display_horizontal_range: (0x260, 0x260 + 320 * 8),
+            display_vertical_range: (16, 16 + 240),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn display_area_15bit() {
+        let mut vram = vec![0; (VRAM_WIDTH * VRAM_HEIGHT) as usize];
+        vram[(8 * VRAM_WIDTH + 16) as usize] = 0x001F;
+        vram[(8 * VRAM_WIDTH + 17) as usize] = 0x7C00;
+
+        let fb =
+            FrameBuffer::from_vram(&vram, false, &snapshot(HORIZONTAL_320), PixelFormat::Rgb888);
+
+        assert_eq!((fb.width, fb.height), (320, 240));
+        assert_eq!(fb.data.len(), 320 * 240 * 3);
+        assert_eq!(&fb.data[..6], &[0xFF, 0, 0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn display_area_24bit() {
+        let mut vram = vec![0; (VRAM_WIDTH * VRAM_HEIGHT) as usize];
+        // two pixels: (0x11, 0x22, 0x33) and (0x44, 0x55, 0x66)
+        vram[(8 * VRAM_WIDTH + 16) as usize] = 0x2211;
+        vram[(8 * VRAM_WIDTH + 17) as usize] = 0x4433;
+        vram[(8 * VRAM_WIDTH + 18) as usize] = 0x6655;
+
+        let fb = FrameBuffer::from_vram(
+            &vram,
+            false,
+            &snapshot(HORIZONTAL_320 | GpuStat::DISPLAY_AREA_COLOR_DEPTH),
+            PixelFormat::Rgba8888,
+        );
+
+        assert_eq!((fb.width, fb.height), (320, 240));
+        assert_eq!(
+            &fb.data[..8],
+            &[0x11, 0x22, 0x33, 0xFF, 0x44, 0x55, 0x66, 0xFF]
+        );
+    }
+
+    #[test]
+    fn full_vram() {
+        let vram = vec![0x7FFF; (VRAM_WIDTH * VRAM_HEIGHT) as usize];
+        let fb = FrameBuffer::from_vram(
+            &vram,
+            true,
+            &snapshot(GpuStat::DISPLAY_AREA_COLOR_DEPTH),
+            PixelFormat::Rgb888,
+        );
+
+        assert_eq!((fb.width, fb.height), (1024, 512));
+        assert!(fb.data.iter().all(|&b| b == 0xFF));
+    }
+}
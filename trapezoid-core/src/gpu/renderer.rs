@@ -0,0 +1,238 @@
+//! The interface between the emulated GPU and the rendering backends.
+//!
+//! The GPU frontend parses the GP0 commands and forwards the drawing and VRAM transfer
+//! commands to a [`Renderer`], which runs on its own thread and executes them in order.
+
+use super::BackendCommand;
+
+pub use super::common::{DrawingTextureParams, DrawingVertex};
+pub use super::software::{Image as SoftwareImage, SoftwareRenderer};
+#[cfg(feature = "vulkan")]
+pub use super::vulkan::VulkanRenderer;
+pub use super::{GpuStat, GpuStateSnapshot};
+
+use crossbeam::{
+    atomic::AtomicCell,
+    channel::{Receiver, Sender},
+};
+use std::{
+    any::Any,
+    ops::Range,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+/// The front image as it is sent between threads, the concrete type is
+/// the [`Renderer::FrontImage`] of the renderer in use.
+pub(crate) type AnyFrontImage = Arc<dyn Any + Send + Sync>;
+
+/// A rendering backend for the GPU.
+///
+/// The renderer owns the 1024x512 16bit VRAM, all coordinates wrap around its edges.
+pub trait Renderer {
+    /// The image produced by [`Renderer::blit_to_front`], retrieved with
+    /// [`crate::Psx::sync_front_image`].
+    type FrontImage: Send + Sync + 'static;
+
+    /// Draw triangles, every 3 `vertices` form a triangle.
+    ///
+    /// `dithered` is whether this primitive can be dithered, it should still only be
+    /// dithered if enabled in gpustat.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_polygon(
+        &mut self,
+        vertices: &[DrawingVertex],
+        texture_params: DrawingTextureParams,
+        textured: bool,
+        texture_blending: bool,
+        semi_transparent: bool,
+        dithered: bool,
+        state_snapshot: GpuStateSnapshot,
+    );
+
+    /// Draw lines, every 2 `vertices` form a line, both ends included.
+    fn draw_polyline(
+        &mut self,
+        vertices: &[DrawingVertex],
+        semi_transparent: bool,
+        dithered: bool,
+        state_snapshot: GpuStateSnapshot,
+    );
+
+    /// Write `block` row by row into the `block_range` (x, y) area of the VRAM.
+    ///
+    /// The mask settings of `gpu_stat` apply like when drawing, pixels with the mask
+    /// bit set are kept with [`GpuStat::NO_DRAW_ON_MASK`], and the mask bit of the
+    /// written pixels is forced with [`GpuStat::DRAWING_MASK_BIT`].
+    fn write_vram_block(
+        &mut self,
+        block_range: (Range<u32>, Range<u32>),
+        block: &[u16],
+        gpu_stat: GpuStat,
+    );
+
+    /// Read the `block_range` (x, y) area of the VRAM row by row
+    fn read_vram_block(&mut self, block_range: (Range<u32>, Range<u32>)) -> Vec<u16>;
+
+    /// Copy `src_range` to `dst_range`, with the mask settings of `gpu_stat`,
+    /// see [`Renderer::write_vram_block`]
+    fn vram_vram_blit(
+        &mut self,
+        src_range: (Range<u32>, Range<u32>),
+        dst_range: (Range<u32>, Range<u32>),
+        gpu_stat: GpuStat,
+    ) {
+        // copying to itself only changes the mask bits, if they are forced
+        if src_range == dst_range && !gpu_stat.intersects(GpuStat::DRAWING_MASK_BIT) {
+            return;
+        }
+        let block = self.read_vram_block(src_range);
+        self.write_vram_block(dst_range, &block, gpu_stat);
+    }
+
+    /// Fill a rectangle with a 24bit `color`, not affected by the drawing area nor the mask
+    fn fill_color(&mut self, top_left: (u32, u32), size: (u32, u32), color: (u8, u8, u8));
+
+    /// Produce the image to be displayed, which is the display area of the VRAM,
+    /// or the whole VRAM if `full_vram` is set.
+    fn blit_to_front(
+        &mut self,
+        full_vram: bool,
+        state_snapshot: GpuStateSnapshot,
+    ) -> Arc<Self::FrontImage>;
+}
+
+/// Runs the renderer on its own thread, and feeds it the commands
+pub(super) struct GpuBackend<R: Renderer> {
+    renderer: R,
+    gpu_stat: Arc<AtomicCell<GpuStat>>,
+
+    gpu_read_sender: Sender<u32>,
+    gpu_backend_receiver: Receiver<BackendCommand>,
+    gpu_front_image_sender: Sender<AnyFrontImage>,
+}
+
+impl<R: Renderer> GpuBackend<R> {
+    /// The renderer is created on the new thread using `renderer_builder`,
+    /// so it doesn't have to be `Send`.
+    pub(super) fn start<F>(
+        renderer_builder: F,
+        gpu_stat: Arc<AtomicCell<GpuStat>>,
+        gpu_read_sender: Sender<u32>,
+        gpu_backend_receiver: Receiver<BackendCommand>,
+        gpu_front_image_sender: Sender<AnyFrontImage>,
+    ) -> JoinHandle<()>
+    where
+        F: FnOnce() -> R + Send + 'static,
+    {
+        thread::spawn(move || {
+            let b = GpuBackend {
+                renderer: renderer_builder(),
+                gpu_stat,
+                gpu_read_sender,
+                gpu_backend_receiver,
+                gpu_front_image_sender,
+            };
+            b.run();
+        })
+    }
+
+    fn run(mut self) {
+        // stops when the `Gpu` is dropped
+        while let Ok(command) = self.gpu_backend_receiver.recv() {
+            match command {
+                BackendCommand::BlitFront {
+                    full_vram,
+                    state_snapshot,
+                } => {
+                    let front_image = self.renderer.blit_to_front(full_vram, state_snapshot);
+                    // if the `Gpu` is dropped, we will exit on the next `recv`
+                    let _ = self.gpu_front_image_sender.send(front_image);
+                }
+                BackendCommand::DrawPolyline {
+                    vertices,
+                    semi_transparent,
+                    dithered,
+                    state_snapshot,
+                } => {
+                    self.renderer.draw_polyline(
+                        &vertices,
+                        semi_transparent,
+                        dithered,
+                        state_snapshot,
+                    );
+                }
+                BackendCommand::DrawPolygon {
+                    vertices,
+                    texture_params,
+                    textured,
+                    texture_blending,
+                    semi_transparent,
+                    dithered,
+                    state_snapshot,
+                } => {
+                    self.renderer.draw_polygon(
+                        &vertices,
+                        texture_params,
+                        textured,
+                        texture_blending,
+                        semi_transparent,
+                        dithered,
+                        state_snapshot,
+                    );
+                }
+                BackendCommand::WriteVramBlock {
+                    block_range,
+                    block,
+                    gpu_stat,
+                } => {
+                    self.renderer
+                        .write_vram_block(block_range, &block, gpu_stat);
+                }
+                BackendCommand::VramVramBlit { src, dst, gpu_stat } => {
+                    self.renderer.vram_vram_blit(src, dst, gpu_stat);
+                }
+                BackendCommand::VramReadBlock { block_range } => {
+                    let src = (block_range.0.start, block_range.1.start);
+                    let width = block_range.0.end - block_range.0.start;
+
+                    let block = self.renderer.read_vram_block(block_range);
+
+                    for (i, pair) in block.chunks(2).enumerate() {
+                        // used for debugging only
+                        let block_counter = i as u32 * 2;
+                        let vram_pos = (
+                            (block_counter % width) + src.0,
+                            (block_counter / width) + src.1,
+                        );
+                        let d1 = pair[0];
+                        let d2 = pair.get(1).copied().unwrap_or(0);
+
+                        let data = ((d2 as u32) << 16) | d1 as u32;
+                        log::info!("IN TRANSFERE, src={:?}, data={:08X}", vram_pos, data);
+
+                        // TODO: send full block
+                        let _ = self.gpu_read_sender.send(data);
+                    }
+                    // after sending all the data, we set the gpu_stat bit to indicate that
+                    // the data can be read now
+                    self.gpu_stat
+                        .fetch_update(|s| Some(s | GpuStat::READY_FOR_TO_SEND_VRAM))
+                        .unwrap();
+                    log::info!("DONE TRANSFERE");
+                }
+                BackendCommand::FillColor {
+                    top_left,
+                    size,
+                    color,
+                } => {
+                    self.renderer.fill_color(top_left, size, color);
+                }
+                BackendCommand::DumpVram { result_sender } => {
+                    let vram = self.renderer.read_vram_block((0..1024, 0..512));
+                    let _ = result_sender.send(vram);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,618 @@
+use crate::gpu::frame_buffer::{FrameBuffer, PixelFormat};
+use crate::gpu::renderer::Renderer;
+use crate::gpu::{DrawingTextureParams, DrawingVertex, GpuStat, GpuStateSnapshot};
+
+use std::ops::Range;
+use std::sync::Arc;
+
+const VRAM_WIDTH: u32 = 1024;
+const VRAM_HEIGHT: u32 = 512;
+
+const DITHER_TABLE: [[i32; 4]; 4] = [
+    [-4, 0, -3, 1],
+    [2, -2, 3, -1],
+    [-3, 1, -4, 0],
+    [3, -1, 2, -2],
+];
+
+/// The front buffer produced by the software renderer, in `RGBA8` format.
+pub struct Image {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Image {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The pixels, row by row, 4 bytes (RGBA) each
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Texture parameters that are constant for the whole draw
+struct TextureState {
+    clut_base: (u32, u32),
+    tex_page_base: (u32, u32),
+    tex_page_color_mode: u8,
+    window_mask: (u32, u32),
+    window_offset: (u32, u32),
+    blending: bool,
+}
+
+/// Everything needed to draw a single pixel, computed once per draw command
+struct DrawState {
+    /// inclusive bounds
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+    offset: (i32, i32),
+    /// `None` if the draw is opaque
+    semi_transparency_mode: Option<u8>,
+    dither: bool,
+    set_mask: bool,
+    check_mask: bool,
+    texture: Option<TextureState>,
+}
+
+impl DrawState {
+    fn new(
+        state_snapshot: &GpuStateSnapshot,
+        semi_transparent: bool,
+        semi_transparency_mode: u8,
+        dithered: bool,
+        texture: Option<TextureState>,
+    ) -> Self {
+        let gpu_stat = state_snapshot.gpu_stat;
+        let (left, top) = state_snapshot.drawing_area_top_left;
+        let (right, bottom) = state_snapshot.drawing_area_bottom_right;
+
+        Self {
+            left: left as i32,
+            top: top as i32,
+            right: right.min(VRAM_WIDTH - 1) as i32,
+            bottom: bottom.min(VRAM_HEIGHT - 1) as i32,
+            offset: state_snapshot.drawing_offset,
+            semi_transparency_mode: semi_transparent.then_some(semi_transparency_mode),
+            dither: dithered && gpu_stat.dither_enabled(),
+            set_mask: gpu_stat.intersects(GpuStat::DRAWING_MASK_BIT),
+            check_mask: gpu_stat.intersects(GpuStat::NO_DRAW_ON_MASK),
+            texture,
+        }
+    }
+
+    #[inline]
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.left && x <= self.right && y >= self.top && y <= self.bottom
+    }
+}
+
+/// A vertex after applying the drawing offset, with the attributes in integer form
+#[derive(Clone, Copy)]
+struct RasterVertex {
+    x: i32,
+    y: i32,
+    color: [i32; 3],
+    tex_coord: [i32; 2],
+}
+
+impl RasterVertex {
+    fn new(v: &DrawingVertex, offset: (i32, i32)) -> Self {
+        let position = v.position();
+        let color = v.color();
+        Self {
+            x: position[0] as i32 + offset.0,
+            y: position[1] as i32 + offset.1,
+            color: color.map(|c| (c * 255.0).round() as i32),
+            tex_coord: v.tex_coord(),
+        }
+    }
+}
+
+/// The edge function, positive if `p` is on the right side of `a->b` (y going down)
+#[inline]
+fn edge(a: &RasterVertex, b: &RasterVertex, px: i32, py: i32) -> i64 {
+    (b.x - a.x) as i64 * (py - a.y) as i64 - (b.y - a.y) as i64 * (px - a.x) as i64
+}
+
+/// The PSX doesn't draw the right and bottom edges of polygons, so only pixels
+/// exactly on the top or left edges are considered inside.
+#[inline]
+fn edge_bias(a: &RasterVertex, b: &RasterVertex) -> i64 {
+    let dy = b.y - a.y;
+    let is_left = dy < 0;
+    let is_top = dy == 0 && b.x > a.x;
+    if is_left || is_top {
+        0
+    } else {
+        -1
+    }
+}
+
+pub struct GpuContext {
+    vram: Box<[u16]>,
+}
+
+impl Default for GpuContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GpuContext {
+    pub fn new() -> Self {
+        Self {
+            vram: vec![0; (VRAM_WIDTH * VRAM_HEIGHT) as usize].into_boxed_slice(),
+        }
+    }
+
+    /// Coordinates wrap around the VRAM edges
+    #[inline]
+    fn vram_index(x: u32, y: u32) -> usize {
+        ((y & (VRAM_HEIGHT - 1)) * VRAM_WIDTH + (x & (VRAM_WIDTH - 1))) as usize
+    }
+}
+
+impl Renderer for GpuContext {
+    type FrontImage = Image;
+
+    fn write_vram_block(
+        &mut self,
+        block_range: (Range<u32>, Range<u32>),
+        block: &[u16],
+        gpu_stat: GpuStat,
+    ) {
+        let set_mask = (gpu_stat.intersects(GpuStat::DRAWING_MASK_BIT) as u16) << 15;
+        let check_mask = gpu_stat.intersects(GpuStat::NO_DRAW_ON_MASK);
+
+        let (x_range, y_range) = block_range;
+        let positions = y_range.flat_map(|y| x_range.clone().map(move |x| (x, y)));
+
+        for ((x, y), &data) in positions.zip(block) {
+            let pixel = &mut self.vram[Self::vram_index(x, y)];
+            if check_mask && *pixel & 0x8000 != 0 {
+                continue;
+            }
+            *pixel = data | set_mask;
+        }
+    }
+
+    fn read_vram_block(&mut self, block_range: (Range<u32>, Range<u32>)) -> Vec<u16> {
+        let (x_range, y_range) = block_range;
+
+        y_range
+            .flat_map(|y| x_range.clone().map(move |x| (x, y)))
+            .map(|(x, y)| self.vram[Self::vram_index(x, y)])
+            .collect()
+    }
+
+    fn fill_color(&mut self, top_left: (u32, u32), size: (u32, u32), color: (u8, u8, u8)) {
+        // fill is not affected by the mask settings nor the drawing area
+        let color =
+            (color.0 >> 3) as u16 | ((color.1 >> 3) as u16) << 5 | ((color.2 >> 3) as u16) << 10;
+
+        for y in top_left.1..top_left.1 + size.1 {
+            for x in top_left.0..top_left.0 + size.0 {
+                self.vram[Self::vram_index(x, y)] = color;
+            }
+        }
+    }
+
+    fn draw_polygon(
+        &mut self,
+        vertices: &[DrawingVertex],
+        texture_params: DrawingTextureParams,
+        textured: bool,
+        texture_blending: bool,
+        semi_transparent: bool,
+        dithered: bool,
+        state_snapshot: GpuStateSnapshot,
+    ) {
+        let textured = textured && !texture_params.texture_disable;
+
+        let (semi_transparency_mode, texture) = if textured {
+            (
+                texture_params.semi_transparency_mode,
+                Some(TextureState {
+                    clut_base: (texture_params.clut_base[0], texture_params.clut_base[1]),
+                    tex_page_base: (
+                        texture_params.tex_page_base[0],
+                        texture_params.tex_page_base[1],
+                    ),
+                    tex_page_color_mode: texture_params.tex_page_color_mode,
+                    window_mask: state_snapshot.texture_window_mask,
+                    window_offset: state_snapshot.texture_window_offset,
+                    blending: texture_blending,
+                }),
+            )
+        } else {
+            (state_snapshot.gpu_stat.semi_transparency_mode(), None)
+        };
+
+        let state = DrawState::new(
+            &state_snapshot,
+            semi_transparent,
+            semi_transparency_mode,
+            dithered,
+            texture,
+        );
+
+        for triangle in vertices.chunks_exact(3) {
+            let v = [
+                RasterVertex::new(&triangle[0], state.offset),
+                RasterVertex::new(&triangle[1], state.offset),
+                RasterVertex::new(&triangle[2], state.offset),
+            ];
+            self.draw_triangle(v, &state);
+        }
+    }
+
+    fn draw_polyline(
+        &mut self,
+        vertices: &[DrawingVertex],
+        semi_transparent: bool,
+        dithered: bool,
+        state_snapshot: GpuStateSnapshot,
+    ) {
+        let state = DrawState::new(
+            &state_snapshot,
+            semi_transparent,
+            state_snapshot.gpu_stat.semi_transparency_mode(),
+            dithered,
+            None,
+        );
+
+        for line in vertices.chunks_exact(2) {
+            let start = RasterVertex::new(&line[0], state.offset);
+            let end = RasterVertex::new(&line[1], state.offset);
+            self.draw_line(start, end, &state);
+        }
+    }
+
+    fn blit_to_front(&mut self, full_vram: bool, state_snapshot: GpuStateSnapshot) -> Arc<Image> {
+        let frame = FrameBuffer::from_vram(
+            &self.vram,
+            full_vram,
+            &state_snapshot,
+            PixelFormat::Rgba8888,
+        );
+
+        Arc::new(Image {
+            width: frame.width,
+            height: frame.height,
+            data: frame.data,
+        })
+    }
+}
+
+impl GpuContext {
+    fn draw_triangle(&mut self, mut v: [RasterVertex; 3], state: &DrawState) {
+        let min_x = v.iter().map(|v| v.x).min().unwrap();
+        let max_x = v.iter().map(|v| v.x).max().unwrap();
+        let min_y = v.iter().map(|v| v.y).min().unwrap();
+        let max_y = v.iter().map(|v| v.y).max().unwrap();
+
+        // the GPU skips polygons that are too large
+        if max_x - min_x >= VRAM_WIDTH as i32 || max_y - min_y >= VRAM_HEIGHT as i32 {
+            return;
+        }
+
+        let mut area = edge(&v[0], &v[1], v[2].x, v[2].y);
+        if area == 0 {
+            return;
+        }
+        // make the winding consistent, so that the inside is always positive
+        if area < 0 {
+            v.swap(1, 2);
+            area = -area;
+        }
+
+        let bias = [
+            edge_bias(&v[1], &v[2]),
+            edge_bias(&v[2], &v[0]),
+            edge_bias(&v[0], &v[1]),
+        ];
+
+        // interpolate an attribute with the edge weights, rounding down
+        let interpolate = |w: &[i64; 3], a: [i32; 3]| -> i32 {
+            let sum = w[0] * a[0] as i64 + w[1] * a[1] as i64 + w[2] * a[2] as i64;
+            sum.div_euclid(area) as i32
+        };
+
+        for y in min_y.max(state.top)..=max_y.min(state.bottom) {
+            for x in min_x.max(state.left)..=max_x.min(state.right) {
+                let w = [
+                    edge(&v[1], &v[2], x, y),
+                    edge(&v[2], &v[0], x, y),
+                    edge(&v[0], &v[1], x, y),
+                ];
+
+                if w.iter().zip(bias).any(|(&w, b)| w + b < 0) {
+                    continue;
+                }
+
+                let color = [0, 1, 2].map(|c| interpolate(&w, v.map(|v| v.color[c])));
+                let tex_coord = [0, 1].map(|c| interpolate(&w, v.map(|v| v.tex_coord[c])));
+
+                self.plot_pixel(x, y, color, tex_coord, state);
+            }
+        }
+    }
+
+    /// Lines include both end points, and are stepped with fixed point
+    /// on the major axis.
+    fn draw_line(&mut self, start: RasterVertex, end: RasterVertex, state: &DrawState) {
+        let dx = end.x - start.x;
+        let dy = end.y - start.y;
+
+        // the GPU skips lines that are too large
+        if dx.abs() >= VRAM_WIDTH as i32 || dy.abs() >= VRAM_HEIGHT as i32 {
+            return;
+        }
+
+        let steps = dx.abs().max(dy.abs());
+        if steps == 0 {
+            if state.contains(start.x, start.y) {
+                self.plot_pixel(start.x, start.y, start.color, [0; 2], state);
+            }
+            return;
+        }
+
+        // 16.16 fixed point, starting at the middle of the pixel
+        let step = |from: i32, to: i32| ((to - from) as i64 * 0x10000) / steps as i64;
+        let x_step = step(start.x, end.x);
+        let y_step = step(start.y, end.y);
+        let mut x = ((start.x as i64) << 16) + 0x8000;
+        let mut y = ((start.y as i64) << 16) + 0x8000;
+
+        for i in 0..=steps {
+            let px = (x >> 16) as i32;
+            let py = (y >> 16) as i32;
+
+            if state.contains(px, py) {
+                let color =
+                    [0, 1, 2].map(|c| start.color[c] + (end.color[c] - start.color[c]) * i / steps);
+                self.plot_pixel(px, py, color, [0; 2], state);
+            }
+
+            x += x_step;
+            y += y_step;
+        }
+    }
+
+    /// Fetch the 16bit texel, going through the CLUT if needed
+    fn fetch_texel(&self, texture: &TextureState, tex_coord: [i32; 2]) -> u16 {
+        // texture coordinates repeat every 256 pixels, which also handles flipping
+        let u = tex_coord[0].rem_euclid(256) as u32;
+        let v = tex_coord[1].rem_euclid(256) as u32;
+
+        // Texcoord = (Texcoord AND (NOT (Mask*8))) OR ((Offset AND Mask)*8)
+        let (mask_x, mask_y) = texture.window_mask;
+        let (offset_x, offset_y) = texture.window_offset;
+        let u = (u & !(mask_x * 8)) | ((offset_x & mask_x) * 8);
+        let v = (v & !(mask_y * 8)) | ((offset_y & mask_y) * 8);
+
+        let (page_x, page_y) = texture.tex_page_base;
+        let (clut_x, clut_y) = texture.clut_base;
+
+        match texture.tex_page_color_mode {
+            0 => {
+                let data = self.vram[Self::vram_index(page_x + u / 4, page_y + v)];
+                let index = (data >> ((u % 4) * 4)) & 0xF;
+                self.vram[Self::vram_index(clut_x + index as u32, clut_y)]
+            }
+            1 => {
+                let data = self.vram[Self::vram_index(page_x + u / 2, page_y + v)];
+                let index = (data >> ((u % 2) * 8)) & 0xFF;
+                self.vram[Self::vram_index(clut_x + index as u32, clut_y)]
+            }
+            _ => self.vram[Self::vram_index(page_x + u, page_y + v)],
+        }
+    }
+
+    /// `x` and `y` must be inside the drawing area
+    fn plot_pixel(
+        &mut self,
+        x: i32,
+        y: i32,
+        color: [i32; 3],
+        tex_coord: [i32; 2],
+        state: &DrawState,
+    ) {
+        let index = Self::vram_index(x as u32, y as u32);
+        let back = self.vram[index];
+
+        if state.check_mask && back & 0x8000 != 0 {
+            return;
+        }
+
+        let dither_offset = if state.dither {
+            DITHER_TABLE[(y & 3) as usize][(x & 3) as usize]
+        } else {
+            0
+        };
+        // from 8bit to 5bit
+        let to_5bit = |c: i32| ((c + dither_offset).clamp(0, 255) >> 3) as u16;
+
+        let (front, semi_transparent, mask) = if let Some(texture) = &state.texture {
+            let texel = self.fetch_texel(texture, tex_coord);
+            // fully transparent
+            if texel == 0 {
+                return;
+            }
+
+            let texel_color = [0, 5, 10].map(|shift| (texel >> shift) & 0x1F);
+            let front = if texture.blending {
+                // color of 128 (0x80) is the texel color as is
+                [0, 1, 2].map(|c| to_5bit((texel_color[c] as i32 * color[c]) >> 4))
+            } else {
+                texel_color
+            };
+
+            // only texels with the mask bit set are semi-transparent
+            (front, texel & 0x8000 != 0, texel & 0x8000)
+        } else {
+            (color.map(to_5bit), true, 0)
+        };
+
+        let front = match state.semi_transparency_mode {
+            Some(mode) if semi_transparent => {
+                let back = [0, 5, 10].map(|shift| (back >> shift) & 0x1F);
+                [0, 1, 2].map(|c| {
+                    let (b, f) = (back[c], front[c]);
+                    match mode {
+                        0 => (b + f) / 2,
+                        1 => (b + f).min(0x1F),
+                        2 => b.saturating_sub(f),
+                        _ => (b + f / 4).min(0x1F),
+                    }
+                })
+            }
+            _ => front,
+        };
+
+        let mask = mask | ((state.set_mask as u16) << 15);
+        self.vram[index] = front[0] | (front[1] << 5) | (front[2] << 10) | mask;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: u32, y: u32, color: u32) -> DrawingVertex {
+        let mut v = DrawingVertex::new_with_color(color);
+        v.position_from_u32((y << 16) | x);
+        v
+    }
+
+    fn snapshot() -> GpuStateSnapshot {
+        GpuStateSnapshot {
+            drawing_area_bottom_right: (1023, 511),
+            ..Default::default()
+        }
+    }
+
+    fn quad(left: u32, top: u32, right: u32, bottom: u32, color: u32) -> Vec<DrawingVertex> {
+        let tl = vertex(left, top, color);
+        let tr = vertex(right, top, color);
+        let bl = vertex(left, bottom, color);
+        let br = vertex(right, bottom, color);
+        vec![tl, tr, bl, tr, bl, br]
+    }
+
+    #[test]
+    fn polygon_excludes_right_and_bottom_edges() {
+        let mut ctx = GpuContext::new();
+        ctx.draw_polygon(
+            &quad(2, 2, 6, 6, 0xFFFFFF),
+            DrawingTextureParams::default(),
+            false,
+            false,
+            false,
+            false,
+            snapshot(),
+        );
+
+        let block = ctx.read_vram_block((0..8, 0..8));
+        for y in 0..8 {
+            for x in 0..8 {
+                let inside = (2..6).contains(&x) && (2..6).contains(&y);
+                let expected = if inside { 0x7FFF } else { 0 };
+                assert_eq!(block[y * 8 + x], expected, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn fill_color_wraps() {
+        let mut ctx = GpuContext::new();
+        ctx.fill_color((1016, 510), (16, 4), (0xFF, 0, 0));
+
+        assert_eq!(ctx.read_vram_block((1016..1024, 510..512)), vec![0x1F; 16]);
+        assert_eq!(ctx.read_vram_block((0..8, 0..2)), vec![0x1F; 16]);
+        assert_eq!(ctx.read_vram_block((8..9, 0..1)), vec![0]);
+    }
+
+    #[test]
+    fn textured_4bit_clut() {
+        let mut ctx = GpuContext::new();
+        // clut at (0, 256), index 1 is red and index 2 is green (semi-transparent)
+        ctx.write_vram_block((0..3, 256..257), &[0, 0x1F, 0x83E0], GpuStat::empty());
+        // texture page at (64, 0), first row: 1, 2, 0, 1
+        ctx.write_vram_block((64..65, 0..1), &[0x1021], GpuStat::empty());
+        // something behind
+        ctx.fill_color((0, 16), (16, 1), (0, 0, 0xFF));
+
+        let mut vertices = quad(0, 16, 4, 17, 0x808080);
+        for v in vertices.iter_mut() {
+            let [x, y] = v.position();
+            v.set_tex_coord([x as i32, y as i32 - 16]);
+        }
+
+        let mut texture_params = DrawingTextureParams::default();
+        texture_params.tex_page_from_gpustat(1);
+        texture_params.clut_from_u32((256 << 6) << 16);
+        texture_params.semi_transparency_mode = 1;
+
+        ctx.draw_polygon(
+            &vertices,
+            texture_params,
+            true,
+            false,
+            true,
+            false,
+            snapshot(),
+        );
+
+        let row = ctx.read_vram_block((0..4, 16..17));
+        // transparent texel keeps the background
+        assert_eq!(row, vec![0x1F, 0x83E0 | 0x7C00, 0x7C00, 0x1F]);
+    }
+
+    #[test]
+    fn vram_writes_and_blits_mask() {
+        let mut ctx = GpuContext::new();
+        ctx.write_vram_block((0..2, 0..1), &[0x8001, 0x0002], GpuStat::empty());
+
+        // the pixel with the mask bit is kept
+        ctx.write_vram_block((0..2, 0..1), &[0x0003, 0x0004], GpuStat::NO_DRAW_ON_MASK);
+        assert_eq!(ctx.read_vram_block((0..2, 0..1)), vec![0x8001, 0x0004]);
+
+        // the mask bit is forced, even when copying to the same place
+        ctx.vram_vram_blit((0..2, 0..1), (0..2, 0..1), GpuStat::DRAWING_MASK_BIT);
+        assert_eq!(ctx.read_vram_block((0..2, 0..1)), vec![0x8001, 0x8004]);
+
+        ctx.vram_vram_blit(
+            (0..2, 0..1),
+            (0..2, 1..2),
+            GpuStat::NO_DRAW_ON_MASK | GpuStat::DRAWING_MASK_BIT,
+        );
+        assert_eq!(ctx.read_vram_block((0..2, 1..2)), vec![0x8001, 0x8004]);
+        ctx.vram_vram_blit((0..1, 2..3), (0..1, 1..2), GpuStat::NO_DRAW_ON_MASK);
+        assert_eq!(ctx.read_vram_block((0..1, 1..2)), vec![0x8001]);
+    }
+
+    #[test]
+    fn blit_24bit() {
+        let mut ctx = GpuContext::new();
+        // two pixels, (1, 2, 3) and (4, 5, 6)
+        ctx.write_vram_block((0..3, 0..1), &[0x0201, 0x0403, 0x0605], GpuStat::empty());
+
+        let mut state_snapshot = snapshot();
+        state_snapshot.gpu_stat = GpuStat::DISPLAY_AREA_COLOR_DEPTH;
+        let image = ctx.blit_to_front(false, state_snapshot);
+
+        assert_eq!(image.width(), 256);
+        assert_eq!(image.height(), 240);
+        assert_eq!(&image.data()[..8], &[1, 2, 3, 0xFF, 4, 5, 6, 0xFF]);
+    }
+}
@@ -0,0 +1,6 @@
+//! A rendering backend that runs fully on the CPU, it renders into a 1024x512 `u16` VRAM
+//! following the PSX rasterization rules. Can be used when there is no GPU available, and
+//! as a reference to compare the output of the `vulkan` backend against.
+mod gpu_context;
+
+pub use gpu_context::{GpuContext as SoftwareRenderer, Image};
@@ -1,4 +1,3 @@
-use crossbeam::channel::Sender;
 pub use vulkano::{
     buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage},
     command_buffer::{
@@ -43,9 +42,10 @@ pub use vulkano::{
 };
 
 use super::front_blit::FrontBlit;
+use crate::gpu::renderer::Renderer;
 use crate::gpu::DrawingTextureParams;
 use crate::gpu::DrawingVertex;
-use crate::gpu::GpuStateSnapshot;
+use crate::gpu::{GpuStat, GpuStateSnapshot};
 
 use std::ops::Range;
 use std::sync::Arc;
@@ -177,9 +177,7 @@ struct BufferedDrawsState {
 }
 
 pub struct GpuContext {
-    pub(super) gpu_front_image_sender: Sender<Arc<Image>>,
-
-    pub(super) device: Arc<Device>,
+    device: Arc<Device>,
     queue: Arc<Queue>,
 
     memory_allocator: Arc<StandardMemoryAllocator>,
@@ -206,11 +204,7 @@ pub struct GpuContext {
 }
 
 impl GpuContext {
-    pub(super) fn new(
-        device: Arc<Device>,
-        queue: Arc<Queue>,
-        gpu_front_image_sender: Sender<Arc<Image>>,
-    ) -> Self {
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>) -> Self {
         let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
         let descriptor_set_allocator =
             StandardDescriptorSetAllocator::new(device.clone(), Default::default());
@@ -424,8 +418,6 @@ impl GpuContext {
         .unwrap();
 
         Self {
-            gpu_front_image_sender,
-
             device,
             queue,
 
@@ -455,8 +447,17 @@ impl GpuContext {
     }
 }
 
-impl GpuContext {
-    pub fn write_vram_block(&mut self, block_range: (Range<u32>, Range<u32>), block: &[u16]) {
+impl Renderer for GpuContext {
+    type FrontImage = Image;
+
+    // TODO: apply the mask settings of `gpu_stat`, the block is copied to the VRAM
+    //       image as is, it should go through a shader like drawing does
+    fn write_vram_block(
+        &mut self,
+        block_range: (Range<u32>, Range<u32>),
+        block: &[u16],
+        _gpu_stat: GpuStat,
+    ) {
         self.check_and_flush_buffered_draws(None);
 
         let left = block_range.0.start;
@@ -593,7 +594,7 @@ impl GpuContext {
         self.schedule_back_image_update();
     }
 
-    pub fn read_vram_block(&mut self, block_range: (Range<u32>, Range<u32>)) -> Vec<u16> {
+    fn read_vram_block(&mut self, block_range: (Range<u32>, Range<u32>)) -> Vec<u16> {
         self.check_and_flush_buffered_draws(None);
         self.flush_command_builder();
 
@@ -750,20 +751,21 @@ impl GpuContext {
         buffer_read.to_vec()
     }
 
-    pub fn vram_vram_blit(
+    fn vram_vram_blit(
         &mut self,
         src_range: (Range<u32>, Range<u32>),
         dst_range: (Range<u32>, Range<u32>),
+        gpu_stat: GpuStat,
     ) {
         if src_range == dst_range {
             return;
         }
         // TODO: use vulkan image copy itself
         let block = self.read_vram_block(src_range);
-        self.write_vram_block(dst_range, &block);
+        self.write_vram_block(dst_range, &block, gpu_stat);
     }
 
-    pub fn fill_color(&mut self, top_left: (u32, u32), size: (u32, u32), color: (u8, u8, u8)) {
+    fn fill_color(&mut self, top_left: (u32, u32), size: (u32, u32), color: (u8, u8, u8)) {
         let mut width = size.0;
         let mut height = size.1;
 
@@ -818,6 +820,129 @@ impl GpuContext {
         self.increment_command_builder_commands_and_flush();
     }
 
+    fn draw_polygon(
+        &mut self,
+        vertices: &[DrawingVertex],
+        texture_params: DrawingTextureParams,
+        textured: bool,
+        texture_blending: bool,
+        semi_transparent: bool,
+        dithered: bool,
+        state_snapshot: GpuStateSnapshot,
+    ) {
+        self.draw(
+            vertices,
+            DrawType::Polygon,
+            texture_params,
+            textured,
+            texture_blending,
+            semi_transparent,
+            dithered,
+            state_snapshot,
+        );
+    }
+
+    fn draw_polyline(
+        &mut self,
+        vertices: &[DrawingVertex],
+        semi_transparent: bool,
+        dithered: bool,
+        state_snapshot: GpuStateSnapshot,
+    ) {
+        // Textures are not supported for polylines
+        self.draw(
+            vertices,
+            DrawType::Polyline,
+            DrawingTextureParams::default(),
+            false,
+            false,
+            semi_transparent,
+            dithered,
+            state_snapshot,
+        );
+    }
+
+    fn blit_to_front(&mut self, full_vram: bool, state_snapshot: GpuStateSnapshot) -> Arc<Image> {
+        let gpu_stat = state_snapshot.gpu_stat;
+        let vram_display_area_start = state_snapshot.vram_display_area_start;
+
+        self.check_and_flush_buffered_draws(None);
+        self.flush_command_builder();
+
+        let (mut topleft, size) = if full_vram {
+            ([0; 2], [1024, 512])
+        } else {
+            // (((X2-X1)/cycles_per_pix)+2) AND NOT 3
+            let mut horizontal_size = (((state_snapshot.display_horizontal_range.1
+                - state_snapshot.display_horizontal_range.0)
+                / gpu_stat.horizontal_dots_divider())
+                + 2)
+                & !3;
+
+            if horizontal_size == 0 {
+                horizontal_size = gpu_stat.horizontal_resolution();
+            }
+
+            let should_double = gpu_stat.vertical_resolution() == 480;
+
+            // Y2-Y1, double if we are interlacing
+            let mut vertical_size = (state_snapshot.display_vertical_range.1
+                - state_snapshot.display_vertical_range.0)
+                << should_double as u32;
+
+            if vertical_size == 0 {
+                vertical_size = gpu_stat.vertical_resolution();
+            }
+
+            (
+                [vram_display_area_start.0, vram_display_area_start.1],
+                [horizontal_size, vertical_size],
+            )
+        };
+
+        // the rendering offset is more of a byte offset than pixel offset
+        // so in 24bit mode, we have to change that.
+        if gpu_stat.is_24bit_color_depth() {
+            topleft[0] = (topleft[0] * 2) / 3;
+        }
+
+        let front_image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                extent: [size[0], size[1], 1],
+                format: Format::B8G8R8A8_UNORM,
+                usage: ImageUsage::TRANSFER_DST
+                    | ImageUsage::TRANSFER_SRC
+                    | ImageUsage::COLOR_ATTACHMENT,
+                ..Default::default()
+            },
+            Default::default(),
+        )
+        .unwrap();
+
+        // TODO: try to remove the `wait` from here
+        self.front_blit
+            .blit(
+                front_image.clone(),
+                topleft,
+                size,
+                !full_vram && gpu_stat.is_24bit_color_depth(),
+                self.gpu_future.take().unwrap(),
+            )
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        // reset future since we are waiting
+        self.gpu_future = Some(sync::now(self.device.clone()).boxed());
+
+        front_image
+    }
+}
+
+impl GpuContext {
     /// Create ColorBlendState for a specific semi_transparency_mode, to be
     /// used to create a specific pipeline for it.
     fn create_color_blend_state(semi_transparency_mode: u8) -> ColorBlendState {
@@ -1050,6 +1175,7 @@ impl GpuContext {
         textured: bool,
         texture_blending: bool,
         semi_transparent: bool,
+        dithered: bool,
         state_snapshot: GpuStateSnapshot,
     ) {
         let gpu_stat = state_snapshot.gpu_stat;
@@ -1125,7 +1251,7 @@ impl GpuContext {
                 texture_window_offset,
                 semi_transparency_mode,
                 semi_transparent,
-                gpu_stat.dither_enabled(),
+                dithered && gpu_stat.dither_enabled(),
                 textured,
                 texture_blending,
             )
@@ -1138,122 +1264,4 @@ impl GpuContext {
             self.check_and_flush_buffered_draws(None);
         }
     }
-
-    pub(super) fn draw_polygon(
-        &mut self,
-        vertices: &[DrawingVertex],
-        texture_params: DrawingTextureParams,
-        textured: bool,
-        texture_blending: bool,
-        semi_transparent: bool,
-        state_snapshot: GpuStateSnapshot,
-    ) {
-        self.draw(
-            vertices,
-            DrawType::Polygon,
-            texture_params,
-            textured,
-            texture_blending,
-            semi_transparent,
-            state_snapshot,
-        );
-    }
-
-    pub(super) fn draw_polyline(
-        &mut self,
-        vertices: &[DrawingVertex],
-        semi_transparent: bool,
-        state_snapshot: GpuStateSnapshot,
-    ) {
-        // Textures are not supported for polylines
-        self.draw(
-            vertices,
-            DrawType::Polyline,
-            DrawingTextureParams::default(),
-            false,
-            false,
-            semi_transparent,
-            state_snapshot,
-        );
-    }
-
-    pub(super) fn blit_to_front(&mut self, full_vram: bool, state_snapshot: GpuStateSnapshot) {
-        let gpu_stat = state_snapshot.gpu_stat;
-        let vram_display_area_start = state_snapshot.vram_display_area_start;
-
-        self.check_and_flush_buffered_draws(None);
-        self.flush_command_builder();
-
-        let (mut topleft, size) = if full_vram {
-            ([0; 2], [1024, 512])
-        } else {
-            // (((X2-X1)/cycles_per_pix)+2) AND NOT 3
-            let mut horizontal_size = (((state_snapshot.display_horizontal_range.1
-                - state_snapshot.display_horizontal_range.0)
-                / gpu_stat.horizontal_dots_divider())
-                + 2)
-                & !3;
-
-            if horizontal_size == 0 {
-                horizontal_size = gpu_stat.horizontal_resolution();
-            }
-
-            let should_double = gpu_stat.vertical_resolution() == 480;
-
-            // Y2-Y1, double if we are interlacing
-            let mut vertical_size = (state_snapshot.display_vertical_range.1
-                - state_snapshot.display_vertical_range.0)
-                << should_double as u32;
-
-            if vertical_size == 0 {
-                vertical_size = gpu_stat.vertical_resolution();
-            }
-
-            (
-                [vram_display_area_start.0, vram_display_area_start.1],
-                [horizontal_size, vertical_size],
-            )
-        };
-
-        // the rendering offset is more of a byte offset than pixel offset
-        // so in 24bit mode, we have to change that.
-        if gpu_stat.is_24bit_color_depth() {
-            topleft[0] = (topleft[0] * 2) / 3;
-        }
-
-        let front_image = Image::new(
-            self.memory_allocator.clone(),
-            ImageCreateInfo {
-                image_type: ImageType::Dim2d,
-                extent: [size[0], size[1], 1],
-                format: Format::B8G8R8A8_UNORM,
-                usage: ImageUsage::TRANSFER_DST
-                    | ImageUsage::TRANSFER_SRC
-                    | ImageUsage::COLOR_ATTACHMENT,
-                ..Default::default()
-            },
-            Default::default(),
-        )
-        .unwrap();
-
-        // TODO: try to remove the `wait` from here
-        self.front_blit
-            .blit(
-                front_image.clone(),
-                topleft,
-                size,
-                !full_vram && gpu_stat.is_24bit_color_depth(),
-                self.gpu_future.take().unwrap(),
-            )
-            .then_signal_fence_and_flush()
-            .unwrap()
-            .wait(None)
-            .unwrap();
-
-        // send the front buffer
-        self.gpu_front_image_sender.send(front_image).unwrap();
-
-        // reset future since we are waiting
-        self.gpu_future = Some(sync::now(self.device.clone()).boxed());
-    }
 }
@@ -1,15 +1,4 @@
 mod front_blit;
-mod gpu_backend;
 mod gpu_context;
 
-pub use gpu_backend::GpuBackend;
-
-pub use vulkano::{
-    command_buffer::{
-        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, BlitImageInfo,
-        CommandBufferUsage, PrimaryAutoCommandBuffer,
-    },
-    device::{Device, Queue},
-    image::{sampler::Filter, Image},
-    sync::GpuFuture,
-};
+pub use gpu_context::GpuContext as VulkanRenderer;
@@ -6,12 +6,15 @@ mod gpu;
 mod mdec;
 mod memory;
 mod spu;
+mod state;
 mod timers;
+mod unimplemented;
 
 #[cfg(test)]
 mod tests;
 
 use std::{
+    io::Write,
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -19,35 +22,176 @@ use std::{
 use cpu::RegisterType;
 pub use memory::hw_registers::HW_REGISTERS;
 use memory::{Bios, BusLine, CpuBus, Result};
+pub use memory::{BusAccess, BusError, BusErrorKind};
 
+pub use cdrom::{
+    disc_region, open_disc_image, read_m3u_playlist, ChdImage, CueBinImage, DiscImage, IsoImage,
+    PbpImage, Region, Track, TrackKind,
+};
 pub use controller_mem_card::DigitalControllerKey;
+pub use gpu::{renderer, FrameBuffer, PixelFormat};
+use spu::AudioRecorder;
+pub use spu::{AudioResampler, AudioSource, SPU_SAMPLE_RATE};
 
-use crate::gpu::{Device, GpuFuture, Image, Queue};
+use renderer::Renderer;
 
 const MAX_CPU_CYCLES_TO_CLOCK: u32 = 2000;
 
+/// The region of the BIOS at `bios_file_path`, from its version string,
+/// `None` for the first BIOS versions, which don't have it
+pub fn bios_region<P: AsRef<Path>>(bios_file_path: P) -> Result<Option<Region>, PsxError> {
+    Ok(Bios::from_file(bios_file_path)?.region())
+}
+
 #[derive(Debug)]
 pub enum PsxError {
-    CouldNotLoadBios,
-    CouldNotLoadDisk(String),
-    DiskTypeNotSupported,
+    /// The BIOS file could not be read
+    CouldNotLoadBios {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The BIOS file was read, but it is not a 512KB BIOS image
+    InvalidBios {
+        path: PathBuf,
+        size: usize,
+    },
+    /// A disk file could not be read, this can be the disk file itself or
+    /// a file it references, like the bin file of a cue file
+    CouldNotLoadDisk {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The disk file was read, but its content is not valid
+    InvalidDisk {
+        path: PathBuf,
+        reason: String,
+    },
+    /// The extension of the disk file is not one we can load
+    DiskTypeNotSupported {
+        path: PathBuf,
+    },
+    InvalidSaveState(String),
+    SaveStateVersionMismatch {
+        expected: u32,
+        found: u32,
+    },
+    /// A value of the [`PsxConfig`] is out of range
+    InvalidConfig(String),
+    /// A WAV file of the audio recording could not be created or written to
+    CouldNotWriteAudioRecording {
+        path: PathBuf,
+        source: std::io::Error,
+    },
 }
 
-impl std::error::Error for PsxError {}
+impl std::error::Error for PsxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PsxError::CouldNotLoadBios { source, .. }
+            | PsxError::CouldNotLoadDisk { source, .. }
+            | PsxError::CouldNotWriteAudioRecording { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
 impl std::fmt::Display for PsxError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            PsxError::CouldNotLoadBios => write!(f, "Could not load BIOS"),
-            PsxError::CouldNotLoadDisk(s) => write!(f, "Could not load disk: {}", s),
-            PsxError::DiskTypeNotSupported => write!(f, "Disk type not supported"),
+            PsxError::CouldNotLoadBios { path, source } => {
+                write!(f, "Could not load BIOS {}: {}", path.display(), source)
+            }
+            PsxError::InvalidBios { path, size } => write!(
+                f,
+                "Invalid BIOS {}: expected 512KB, found {} bytes",
+                path.display(),
+                size
+            ),
+            PsxError::CouldNotLoadDisk { path, source } => {
+                write!(f, "Could not load disk {}: {}", path.display(), source)
+            }
+            PsxError::InvalidDisk { path, reason } => {
+                write!(f, "Invalid disk {}: {}", path.display(), reason)
+            }
+            PsxError::DiskTypeNotSupported { path } => {
+                write!(f, "Disk type not supported: {}", path.display())
+            }
+            PsxError::InvalidSaveState(s) => write!(f, "Invalid save state: {}", s),
+            PsxError::SaveStateVersionMismatch { expected, found } => write!(
+                f,
+                "Save state version {} is not supported, expected version {}",
+                found, expected
+            ),
+            PsxError::InvalidConfig(s) => write!(f, "Invalid config: {}", s),
+            PsxError::CouldNotWriteAudioRecording { path, source } => {
+                write!(
+                    f,
+                    "Could not write audio recording {}: {}",
+                    path.display(),
+                    source
+                )
+            }
         }
     }
 }
 
+/// The part of the console that an event is coming from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HardwareComponent {
+    Cpu,
+    Cop0,
+    /// The main bus itself, for addresses that are not mapped to any component
+    Bus,
+    MainRam,
+    Scratchpad,
+    Bios,
+    MemoryControl,
+    CacheControl,
+    Interrupts,
+    Dma,
+    Timers,
+    Gpu,
+    Mdec,
+    Spu,
+    Cdrom,
+    Controller,
+    Expansion,
+}
+
+impl std::fmt::Display for HardwareComponent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            HardwareComponent::Cpu => "CPU",
+            HardwareComponent::Cop0 => "COP0",
+            HardwareComponent::Bus => "Bus",
+            HardwareComponent::MainRam => "Main RAM",
+            HardwareComponent::Scratchpad => "Scratchpad",
+            HardwareComponent::Bios => "BIOS",
+            HardwareComponent::MemoryControl => "Memory control",
+            HardwareComponent::CacheControl => "Cache control",
+            HardwareComponent::Interrupts => "Interrupts",
+            HardwareComponent::Dma => "DMA",
+            HardwareComponent::Timers => "Timers",
+            HardwareComponent::Gpu => "GPU",
+            HardwareComponent::Mdec => "MDEC",
+            HardwareComponent::Spu => "SPU",
+            HardwareComponent::Cdrom => "CDROM",
+            HardwareComponent::Controller => "Controller",
+            HardwareComponent::Expansion => "Expansion",
+        };
+        f.write_str(name)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct PsxConfig {
     pub stdout_debug: bool,
     pub fast_boot: bool,
+    /// Skip most of the CD-ROM seek and spin-up delays
+    pub fast_disc_access: bool,
+    /// The sample rate of [`Psx::take_audio_buffer`], the SPU runs at
+    /// [`SPU_SAMPLE_RATE`] and is resampled to it, must not be 0
+    pub audio_sample_rate: u32,
+    pub audio_resampler: AudioResampler,
 }
 
 pub struct Psx {
@@ -67,34 +211,54 @@ pub struct Psx {
 }
 
 impl Psx {
-    // TODO: produce a valid `Error` struct
-    pub fn new<BiosPath: AsRef<Path>, DiskPath: AsRef<Path>>(
+    /// `renderer_builder` is called on the rendering thread to create the [`Renderer`],
+    /// so the renderer itself doesn't need to be `Send`.
+    pub fn new<BiosPath, DiskPath, R, F>(
         bios_file_path: BiosPath,
         disk_file: Option<DiskPath>,
         config: PsxConfig,
-        device: Arc<Device>,
-        queue: Arc<Queue>,
-    ) -> Result<Self, PsxError> {
+        renderer_builder: F,
+    ) -> Result<Self, PsxError>
+    where
+        BiosPath: AsRef<Path>,
+        DiskPath: AsRef<Path>,
+        R: Renderer,
+        F: FnOnce() -> R + Send + 'static,
+    {
+        if config.audio_sample_rate == 0 {
+            return Err(PsxError::InvalidConfig(
+                "audio sample rate can't be 0".to_string(),
+            ));
+        }
+
         let bios = Bios::from_file(bios_file_path)?;
+        Self::with_bios(bios, disk_file, config, renderer_builder)
+    }
 
+    fn with_bios<DiskPath, R, F>(
+        bios: Bios,
+        disk_file: Option<DiskPath>,
+        config: PsxConfig,
+        renderer_builder: F,
+    ) -> Result<Self, PsxError>
+    where
+        DiskPath: AsRef<Path>,
+        R: Renderer,
+        F: FnOnce() -> R + Send + 'static,
+    {
         // save the exe file if there is any
-        // The PSX itself is only responsible for loading normal cue files
+        // The PSX itself is only responsible for loading disc images
         let (exe_file, disk_file) = if let Some(disk_file) = disk_file {
             let path = disk_file.as_ref().to_owned();
             // if this is an exe file
-            match path
+            let extension = path
                 .extension()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_ascii_lowercase()
-                .as_str()
-            {
-                "exe" => (Some(path), None),
-                "cue" => (None, Some(path)),
-                _ => {
-                    return Err(PsxError::DiskTypeNotSupported);
-                }
+                .and_then(|e| e.to_str())
+                .map(|e| e.to_ascii_lowercase());
+            match extension.as_deref() {
+                Some("exe") => (Some(path), None),
+                // the cdrom will check if this is a disc image we support
+                _ => (None, Some(path)),
             }
         } else {
             // only fast_boot if there is anything to run
@@ -104,7 +268,7 @@ impl Psx {
         Ok(Self {
             cpu: cpu::Cpu::new(),
             disk_available: disk_file.is_some(),
-            bus: CpuBus::new(bios, disk_file, config, device, queue)?,
+            bus: CpuBus::new(bios, disk_file, config, renderer_builder)?,
             exe_file,
             config,
             excess_cpu_cycles: 0,
@@ -119,6 +283,24 @@ impl Psx {
 
     #[inline(always)]
     fn common_clock(&mut self) -> (u32, cpu::CpuState) {
+        let (added_clock, mut cpu_state) = self.clock_cpu_and_components();
+
+        // report anything that is not emulated, a breakpoint takes priority,
+        // and this will be reported on the next clock
+        if cpu_state == cpu::CpuState::Normal {
+            // only the first is reported, the rest are most likely caused by it
+            let cpu_report = self.cpu.take_unimplemented();
+            let bus_report = self.bus.take_unimplemented();
+            if let Some((component, detail)) = cpu_report.or(bus_report) {
+                cpu_state = cpu::CpuState::UnimplementedHardware { component, detail };
+            }
+        }
+
+        (added_clock, cpu_state)
+    }
+
+    #[inline(always)]
+    fn clock_cpu_and_components(&mut self) -> (u32, cpu::CpuState) {
         let mut cpu_state = cpu::CpuState::Normal;
         let mut added_clock = 0;
         if self.excess_cpu_cycles == 0 {
@@ -267,39 +449,245 @@ impl Psx {
         self.bus.cdrom_mut().change_cdrom_shell_open_state(open);
     }
 
-    pub fn blit_to_front(
-        &mut self,
-        dest_image: Arc<Image>,
-        full_vram: bool,
-        in_future: Box<dyn GpuFuture>,
-    ) -> Box<dyn GpuFuture> {
+    /// Replaces the disc with `disc`, which can be any implementation of [`DiscImage`],
+    /// [`open_disc_image`] opens the supported image files.
+    ///
+    /// This is meant to be used before starting, the running game doesn't see the
+    /// disc change, use [`Psx::insert_disc_image`] for that.
+    pub fn set_disc_image(&mut self, disc: Box<dyn DiscImage>) {
+        self.disk_available = true;
+        self.bus.cdrom_mut().set_disc_image(disc);
+    }
+
+    /// Opens the disc image at `path` and swaps the disc with it,
+    /// see [`Psx::insert_disc_image`]
+    pub fn insert_disc<P: AsRef<Path>>(&mut self, path: P) -> Result<(), PsxError> {
+        self.insert_disc_image(open_disc_image(path.as_ref())?);
+        Ok(())
+    }
+
+    /// Swaps the disc with `disc`, the shell is opened and closed after a short delay,
+    /// like changing discs on the console.
+    pub fn insert_disc_image(&mut self, disc: Box<dyn DiscImage>) {
+        self.disk_available = true;
+        self.bus.cdrom_mut().insert_disc(disc);
+    }
+
+    /// The region of the inserted disc from its license string, `None` if there is no disc,
+    /// or it's not a licensed PlayStation disc.
+    ///
+    /// Frontends can use it to pick the right BIOS, see [`bios_region`], and the NTSC or PAL
+    /// frame rate.
+    pub fn disc_region(&self) -> Option<Region> {
+        self.bus.cdrom().disc_region()
+    }
+
+    /// Opens the shell and removes the disc, it stays open until a disc is inserted
+    /// or it's closed with [`Psx::change_cdrom_shell_open_state`]
+    pub fn eject_disc(&mut self) {
+        self.bus.cdrom_mut().eject_disc();
+    }
+
+    /// Waits for the renderer to finish the previous frame and returns its front image,
+    /// then requests the front image of the current frame, so it would be ready by the next call.
+    ///
+    /// `T` must be the [`Renderer::FrontImage`] of the renderer in use, otherwise `None`
+    /// is returned and an error is logged. Also returns `None` on the first call, since
+    /// there is no previous frame.
+    pub fn sync_front_image<T>(&mut self, full_vram: bool) -> Option<Arc<T>>
+    where
+        T: Send + Sync + 'static,
+    {
         self.bus
             .gpu_mut()
-            .sync_gpu_and_blit_to_front(dest_image, full_vram, in_future)
+            .sync_front_image(full_vram)
+            .and_then(|image| match image.downcast() {
+                Ok(image) => Some(image),
+                Err(_) => {
+                    log::error!(
+                        "sync_front_image: `{}` is not the front image of the renderer",
+                        std::any::type_name::<T>()
+                    );
+                    None
+                }
+            })
+    }
+
+    /// The pixels currently shown on screen, which is the display area of the VRAM
+    /// in the current color depth.
+    ///
+    /// This waits for the renderer to finish all pending drawing, so it's
+    /// better not called more than once per frame.
+    pub fn frame_buffer(&self, format: PixelFormat) -> FrameBuffer {
+        self.bus.gpu().frame_buffer(false, format)
+    }
+
+    /// The whole 1024x512 VRAM as 15bit pixels, see [`Psx::frame_buffer`].
+    pub fn vram_frame_buffer(&self, format: PixelFormat) -> FrameBuffer {
+        self.bus.gpu().frame_buffer(true, format)
     }
 
+    /// The audio produced since the last call, interleaved stereo at
+    /// [`PsxConfig::audio_sample_rate`]
     pub fn take_audio_buffer(&mut self) -> Vec<f32> {
         self.bus.spu_mut().take_audio_buffer()
     }
 
+    /// Stretches the audio output by `rate_adjust`, limited to 5% either way,
+    /// above 1.0 produces more samples.
+    ///
+    /// This is used for dynamic rate control, when the emulation is paced to the display
+    /// (59.94Hz or 50Hz) instead of the audio device, the frontend can adjust this every
+    /// frame depending on how full its audio buffer is, to keep it from running dry or
+    /// overflowing.
+    pub fn set_audio_rate_adjust(&mut self, rate_adjust: f64) {
+        self.bus.spu_mut().set_audio_rate_adjust(rate_adjust);
+    }
+
+    /// Mutes `source` in the audio output, the emulation isn't affected, so the echoes
+    /// of a muted voice are still heard through the reverb, unless it's muted too
+    pub fn set_audio_source_muted(&mut self, source: AudioSource, muted: bool) {
+        self.bus.spu_mut().mixer_mut().set_muted(source, muted);
+    }
+
+    /// While any source is soloed, only the soloed sources are heard
+    pub fn set_audio_source_solo(&mut self, source: AudioSource, solo: bool) {
+        self.bus.spu_mut().mixer_mut().set_solo(source, solo);
+    }
+
+    pub fn is_audio_source_muted(&self, source: AudioSource) -> bool {
+        self.bus.spu().mixer().is_muted(source)
+    }
+
+    pub fn is_audio_source_solo(&self, source: AudioSource) -> bool {
+        self.bus.spu().mixer().is_solo(source)
+    }
+
+    /// The peak levels of the 24 voices since the last call, left and right in `0.0..=1.0`
+    /// after the voice volume and before the main volume. Muted voices are measured as well.
+    pub fn take_voice_peak_levels(&mut self) -> [(f32, f32); 24] {
+        self.bus.spu_mut().mixer_mut().take_voice_peaks()
+    }
+
+    /// Starts recording the audio into the WAV file at `path`, 16bit stereo at
+    /// [`SPU_SAMPLE_RATE`], before it's resampled to the output sample rate.
+    ///
+    /// With `stems`, each voice, after its volume, and the CD audio, as it comes from
+    /// the CD-ROM, are recorded as well next to it, `song.wav` records the stems into
+    /// `song-voice00.wav` to `song-voice23.wav`, and `song-cd.wav`. The stems are recorded
+    /// even if muted in the mixer, the mix is what's heard.
+    ///
+    /// A previous recording is stopped first, returning its error if any.
+    pub fn start_audio_recording<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        stems: bool,
+    ) -> Result<(), PsxError> {
+        let recorder = AudioRecorder::create(path.as_ref(), stems)?;
+        match self.bus.spu_mut().start_recording(recorder) {
+            Some(previous) => previous.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops the recording and finishes the files, returns the first error that happened
+    /// while writing to them, the recording stops on the first error
+    pub fn stop_audio_recording(&mut self) -> Result<(), PsxError> {
+        match self.bus.spu_mut().stop_recording() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording_audio(&self) -> bool {
+        self.bus.spu().is_recording()
+    }
+
+    /// Captures the whole machine state, which can be restored later with [`Psx::load_state`].
+    ///
+    /// The BIOS, disk and memory cards are not part of the state, so the same ones
+    /// should be used when loading it.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(state::SAVE_STATE_MAGIC);
+        data.extend_from_slice(&state::SAVE_STATE_VERSION.to_le_bytes());
+        self.save_state_body(&mut data)
+            .expect("writing a save state to memory should not fail");
+        data
+    }
+
+    /// Restores a state produced by [`Psx::save_state`].
+    ///
+    /// States from other versions are rejected, and if the state is corrupted,
+    /// the emulator is left as it was before the call.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), PsxError> {
+        let header_len = state::SAVE_STATE_MAGIC.len() + 4;
+        if data.len() < header_len || !data.starts_with(state::SAVE_STATE_MAGIC) {
+            return Err(PsxError::InvalidSaveState(
+                "missing save state header".to_string(),
+            ));
+        }
+        let version = u32::from_le_bytes(
+            data[state::SAVE_STATE_MAGIC.len()..header_len]
+                .try_into()
+                .unwrap(),
+        );
+        if version != state::SAVE_STATE_VERSION {
+            return Err(PsxError::SaveStateVersionMismatch {
+                expected: state::SAVE_STATE_VERSION,
+                found: version,
+            });
+        }
+
+        // components are loaded one by one, so keep the current state to go back to
+        // if we fail in the middle
+        let backup = self.save_state();
+        if let Err(e) = self.load_state_body(&data[header_len..]) {
+            self.load_state_body(&backup[header_len..])
+                .expect("restoring the state before the failed load should not fail");
+            return Err(PsxError::InvalidSaveState(e.to_string()));
+        }
+        // belongs to the state we left
+        self.cpu.take_unimplemented();
+        self.bus.take_unimplemented();
+
+        Ok(())
+    }
+
+    fn save_state_body<W: Write>(&self, mut writer: W) -> bincode::Result<()> {
+        self.cpu.save_state(&mut writer)?;
+        self.bus.save_state(&mut writer)?;
+        bincode::serialize_into(&mut writer, &self.excess_cpu_cycles)?;
+        bincode::serialize_into(&mut writer, &self.cpu_frame_cycles)?;
+
+        Ok(())
+    }
+
+    fn load_state_body(&mut self, mut reader: &[u8]) -> bincode::Result<()> {
+        self.cpu.load_state(&mut reader)?;
+        self.bus.load_state(&mut reader)?;
+        self.excess_cpu_cycles = bincode::deserialize_from(&mut reader)?;
+        self.cpu_frame_cycles = bincode::deserialize_from(&mut reader)?;
+
+        if !reader.is_empty() {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "{} extra bytes at the end",
+                reader.len()
+            ))));
+        }
+
+        Ok(())
+    }
+
     pub fn cpu(&mut self) -> &mut cpu::Cpu {
         &mut self.cpu
     }
 
     pub fn bus_read_u32(&mut self, addr: u32) -> Result<u32> {
-        // make sure its aligned
-        if addr % 4 != 0 {
-            return Err("Unaligned memory access".to_string());
-        }
         self.bus.read_u32(addr)
     }
 
     pub fn bus_read_u16(&mut self, addr: u32) -> Result<u16> {
-        // make sure its aligned
-        if addr % 2 != 0 {
-            return Err("Unaligned memory access".to_string());
-        }
-
         self.bus.read_u16(addr)
     }
 
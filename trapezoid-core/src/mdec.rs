@@ -1,8 +1,11 @@
 use std::collections::VecDeque;
 
 use crate::memory::{BusLine, Result};
+use crate::HardwareComponent;
 use bitflags::bitflags;
 use byteorder::{ByteOrder, LittleEndian};
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
 
 const ZIGZAG: [usize; 64] = [
     0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
@@ -33,7 +36,8 @@ const fn extend_sign<const N: usize>(x: u16) -> i32 {
 }
 
 bitflags! {
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Serialize, Deserialize)]
+    #[serde(transparent)]
     pub struct MdecStatus: u32 {
         const DATA_OUT_FIFO_EMPTY     = 0b1000_0000_0000_0000_0000_0000_0000_0000;
         const DATA_IN_FIFO_FULL       = 0b0100_0000_0000_0000_0000_0000_0000_0000;
@@ -61,15 +65,19 @@ impl MdecStatus {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 struct DecodeMacroBlockCommandState {
     // block state
+    #[serde(with = "BigArray")]
     rl_out: [i16; 64],
     q_scale: u16,
     k: usize,
     first: bool,
 
     // color state
+    #[serde(with = "BigArray")]
     cr_blk: [i16; 64],
+    #[serde(with = "BigArray")]
     cb_blk: [i16; 64],
     color_decoding_state: u32,
 }
@@ -98,13 +106,14 @@ impl DecodeMacroBlockCommandState {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 enum MdecCommand {
     DecodeMacroBlock(Box<DecodeMacroBlockCommandState>),
     SetQuantTable { color_and_luminance: bool },
     SetScaleTable,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum BlockType {
     Y1 = 0,
     Y2,
@@ -114,19 +123,22 @@ pub enum BlockType {
     Cb,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct FifoBlockState {
     pub block_type: BlockType,
     pub index: usize,
     pub is_24bit: bool,
 }
 
+#[derive(Serialize, Deserialize)]
 struct FifoBlock {
+    #[serde(with = "BigArray")]
     data: [u32; 48],
     size: usize,
     state: FifoBlockState,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Mdec {
     status: MdecStatus,
     remaining_params: u16,
@@ -135,8 +147,11 @@ pub struct Mdec {
 
     out_fifo: VecDeque<FifoBlock>,
 
+    #[serde(with = "BigArray")]
     iq_y: [u8; 64],
+    #[serde(with = "BigArray")]
     iq_uv: [u8; 64],
+    #[serde(with = "BigArray")]
     scaletable: [u16; 64],
 }
 
@@ -623,6 +638,8 @@ impl Mdec {
 }
 
 impl BusLine for Mdec {
+    const COMPONENT: HardwareComponent = HardwareComponent::Mdec;
+
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         let r = match addr & 0xF {
             0 => self.read_fifo(),
@@ -6,21 +6,21 @@ mod memory_control;
 mod ram;
 
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
-use std::sync::Arc;
 
-use crate::gpu::{Device, Queue};
+use crate::gpu::renderer::Renderer;
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
 
-use crate::cdrom::Cdrom;
+use crate::cdrom::{Cdrom, Region};
 use crate::controller_mem_card::ControllerAndMemoryCard;
 use crate::cpu::CpuBusProvider;
 use crate::gpu::Gpu;
 use crate::mdec::Mdec;
 use crate::spu::Spu;
 use crate::timers::Timers;
-use crate::{PsxConfig, PsxError};
+use crate::unimplemented::Report;
+use crate::{HardwareComponent, PsxConfig, PsxError};
 
 use dma::Dma;
 use expansion_regions::{ExpansionRegion1, ExpansionRegion2};
@@ -28,53 +28,133 @@ use interrupts::Interrupts;
 use memory_control::{CacheControl, MemoryControl1, MemoryControl2};
 use ram::{MainRam, Scratchpad};
 
-pub type Result<T, E = String> = std::result::Result<T, E>;
+pub type Result<T, E = BusError> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusAccess {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusErrorKind {
+    /// Nothing is mapped at the address
+    Unmapped,
+    /// The address is not aligned to the width of the access
+    Unaligned,
+    /// The component doesn't support accesses of this width
+    UnsupportedWidth,
+    /// The address is in a segment that can't be accessed, i.e. the bottom 1.5GB
+    /// of KUSEG, the scratchpad from KSEG1, or KSEG2 outside the cache control
+    InaccessibleSegment,
+    /// The component doesn't emulate this register or access, it's reported as
+    /// [`crate::cpu::CpuState::UnimplementedHardware`]
+    Unimplemented,
+}
+
+/// A failed access on the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusError {
+    /// The address as the CPU sees it
+    pub address: u32,
+    /// The width of the access in bits, 8, 16 or 32
+    pub width: u8,
+    pub access: BusAccess,
+    /// The component that rejected the access, or [`HardwareComponent::Bus`]
+    /// if it didn't reach any component
+    pub component: HardwareComponent,
+    pub kind: BusErrorKind,
+}
+
+impl BusError {
+    pub(crate) fn new(
+        component: HardwareComponent,
+        address: u32,
+        width: u8,
+        access: BusAccess,
+        kind: BusErrorKind,
+    ) -> Self {
+        Self {
+            address,
+            width,
+            access,
+            component,
+            kind,
+        }
+    }
+
+    /// Components only get the offset inside their region, the bus puts back the full address
+    fn at(self, address: u32) -> Self {
+        Self { address, ..self }
+    }
+}
+
+impl std::error::Error for BusError {}
+impl std::fmt::Display for BusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let access = match self.access {
+            BusAccess::Read => "read from",
+            BusAccess::Write => "write to",
+        };
+        let kind = match self.kind {
+            BusErrorKind::Unmapped => "unmapped address",
+            BusErrorKind::Unaligned => "unaligned address",
+            BusErrorKind::UnsupportedWidth => "unsupported access width",
+            BusErrorKind::InaccessibleSegment => "inaccessible memory segment",
+            BusErrorKind::Unimplemented => "unimplemented",
+        };
+        write!(
+            f,
+            "{}: u{} {} {:08X}: {}",
+            self.component, self.width, access, self.address, kind
+        )
+    }
+}
 
 pub trait BusLine {
+    /// Used to report which component rejected an access
+    const COMPONENT: HardwareComponent;
+
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
-        Err(format!(
-            "{}: u32 read from {:08X}",
-            std::any::type_name::<Self>(),
-            addr
-        ))
+        Err(Self::unsupported_width(addr, 32, BusAccess::Read))
     }
 
     fn write_u32(&mut self, addr: u32, _data: u32) -> Result<()> {
-        Err(format!(
-            "{}: u32 write to {:08X}",
-            std::any::type_name::<Self>(),
-            addr
-        ))
+        Err(Self::unsupported_width(addr, 32, BusAccess::Write))
     }
 
     fn read_u16(&mut self, addr: u32) -> Result<u16> {
-        Err(format!(
-            "{}: u16 read from {:08X}",
-            std::any::type_name::<Self>(),
-            addr
-        ))
+        Err(Self::unsupported_width(addr, 16, BusAccess::Read))
     }
     fn write_u16(&mut self, addr: u32, _data: u16) -> Result<()> {
-        Err(format!(
-            "{}: u16 write to {:08X}",
-            std::any::type_name::<Self>(),
-            addr
-        ))
+        Err(Self::unsupported_width(addr, 16, BusAccess::Write))
     }
 
     fn read_u8(&mut self, addr: u32) -> Result<u8> {
-        Err(format!(
-            "{}: u8 read from {:08X}",
-            std::any::type_name::<Self>(),
-            addr
-        ))
+        Err(Self::unsupported_width(addr, 8, BusAccess::Read))
     }
     fn write_u8(&mut self, addr: u32, _data: u8) -> Result<()> {
-        Err(format!(
-            "{}: u8 write to {:08X}",
-            std::any::type_name::<Self>(),
-            addr
-        ))
+        Err(Self::unsupported_width(addr, 8, BusAccess::Write))
+    }
+
+    fn unsupported_width(addr: u32, width: u8, access: BusAccess) -> BusError {
+        BusError::new(
+            Self::COMPONENT,
+            addr,
+            width,
+            access,
+            BusErrorKind::UnsupportedWidth,
+        )
+    }
+
+    fn unimplemented(addr: u32, width: u8, access: BusAccess) -> BusError {
+        BusError::new(
+            Self::COMPONENT,
+            addr,
+            width,
+            access,
+            BusErrorKind::Unimplemented,
+        )
     }
 }
 
@@ -135,14 +215,27 @@ impl Bios {
 }
 
 impl Bios {
-    // TODO: produce a valid `Error` struct
-    pub fn from_file<P: AsRef<Path>>(bios_file_path: P) -> Result<Self, PsxError> {
-        let mut data = Vec::new();
+    const SIZE: usize = 512 * 1024;
+    /// Where the version string is, like `System ROM Version 4.1 12/16/97 A`
+    const VERSION_OFFSET: usize = 0x7FF32;
 
-        let mut file = File::open(bios_file_path).map_err(|_| PsxError::CouldNotLoadBios)?;
+    pub fn from_file<P: AsRef<Path>>(bios_file_path: P) -> Result<Self, PsxError> {
+        let path = bios_file_path.as_ref();
+        let io_error = |source| PsxError::CouldNotLoadBios {
+            path: path.to_owned(),
+            source,
+        };
 
-        file.read_to_end(&mut data)
-            .map_err(|_| PsxError::CouldNotLoadBios)?;
+        let mut data = Vec::new();
+        let mut file = File::open(path).map_err(io_error)?;
+        file.read_to_end(&mut data).map_err(io_error)?;
+
+        if data.len() != Self::SIZE {
+            return Err(PsxError::InvalidBios {
+                path: path.to_owned(),
+                size: data.len(),
+            });
+        }
 
         let mut s = Self { data };
 
@@ -151,6 +244,32 @@ impl Bios {
         Ok(s)
     }
 
+    /// A BIOS of zeros, for tests that don't run it
+    #[cfg(test)]
+    pub(crate) fn zeroed() -> Self {
+        Self {
+            data: vec![0; Self::SIZE],
+        }
+    }
+
+    /// The region from the last letter of the version string,
+    /// `None` for the first BIOS versions, which don't have it
+    pub fn region(&self) -> Option<Region> {
+        let version = &self.data[Self::VERSION_OFFSET..];
+        let end = version.iter().position(|&b| b == 0)?;
+        let version = std::str::from_utf8(&version[..end]).ok()?;
+        if !version.starts_with("System ROM Version") {
+            return None;
+        }
+
+        match version.trim_end().chars().last()? {
+            'J' => Some(Region::Japan),
+            'A' => Some(Region::NorthAmerica),
+            'E' => Some(Region::Europe),
+            _ => None,
+        }
+    }
+
     pub fn read_u32(&self, addr: u32) -> Result<u32> {
         let index = (addr & 0xFFFFF) as usize;
 
@@ -210,16 +329,24 @@ pub struct CpuBus {
 
     scratchpad: Scratchpad,
     config: PsxConfig,
+
+    /// The first unimplemented feature used by the CPU through the bus,
+    /// or by the components, since the last [`CpuBus::take_unimplemented`]
+    pending_unimplemented: Option<Report>,
 }
 
 impl CpuBus {
-    pub fn new<DiskPath: AsRef<Path>>(
+    pub fn new<DiskPath, R, F>(
         bios: Bios,
         disk_file: Option<DiskPath>,
         config: PsxConfig,
-        device: Arc<Device>,
-        queue: Arc<Queue>,
-    ) -> Result<Self, PsxError> {
+        renderer_builder: F,
+    ) -> Result<Self, PsxError>
+    where
+        DiskPath: AsRef<Path>,
+        R: Renderer,
+        F: FnOnce() -> R + Send + 'static,
+    {
         let mut s = Self {
             bios,
             mem_ctrl_1: MemoryControl1::default(),
@@ -236,7 +363,7 @@ impl CpuBus {
 
             dma_bus: DmaBus {
                 cdrom: Cdrom::default(),
-                gpu: Gpu::new(device, queue),
+                gpu: Gpu::new(renderer_builder),
                 main_ram: MainRam::default(),
                 mdec: Mdec::default(),
                 spu: Spu::default(),
@@ -244,25 +371,22 @@ impl CpuBus {
 
             scratchpad: Scratchpad::default(),
             config,
+
+            pending_unimplemented: None,
         };
 
+        let drive_region = s.bios.region();
+        s.dma_bus.cdrom.set_drive_region(drive_region);
+        s.dma_bus
+            .cdrom
+            .set_fast_disc_access(config.fast_disc_access);
+        s.dma_bus
+            .spu
+            .set_audio_output(config.audio_sample_rate, config.audio_resampler);
+
         // TODO: handle errors in loading
         if let Some(disk_file) = disk_file {
-            let path = disk_file.as_ref().to_owned();
-            // if this is an exe file
-            match path
-                .extension()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_ascii_lowercase()
-                .as_str()
-            {
-                "cue" => s.dma_bus.cdrom.set_cue_file(disk_file)?,
-                _ => {
-                    return Err(PsxError::DiskTypeNotSupported);
-                }
-            }
+            s.dma_bus.cdrom.set_disc_file(disk_file)?;
         }
 
         Ok(s)
@@ -285,9 +409,10 @@ impl CpuBus {
         self.dma_bus.gpu.reset();
         self.dma_bus.main_ram = MainRam::default();
         self.dma_bus.mdec = Mdec::default();
-        self.dma_bus.spu = Spu::default();
+        self.dma_bus.spu.reset();
 
         self.scratchpad = Scratchpad::default();
+        self.pending_unimplemented = None;
     }
 
     pub fn gpu(&self) -> &Gpu {
@@ -310,11 +435,59 @@ impl CpuBus {
         &mut self.dma_bus.spu
     }
 
+    pub fn cdrom(&self) -> &Cdrom {
+        &self.dma_bus.cdrom
+    }
+
     pub fn cdrom_mut(&mut self) -> &mut Cdrom {
         &mut self.dma_bus.cdrom
     }
 }
 
+// save states
+//
+// The bios, expansion regions and config are not part of the state, they are
+// either constant or only used for debugging.
+impl CpuBus {
+    pub fn save_state<W: Write>(&self, mut writer: W) -> bincode::Result<()> {
+        bincode::serialize_into(&mut writer, &self.mem_ctrl_1)?;
+        bincode::serialize_into(&mut writer, &self.mem_ctrl_2)?;
+        bincode::serialize_into(&mut writer, &self.cache_control)?;
+        bincode::serialize_into(&mut writer, &self.interrupts)?;
+        self.controller_mem_card.save_state(&mut writer)?;
+        bincode::serialize_into(&mut writer, &self.timers)?;
+        bincode::serialize_into(&mut writer, &self.dma)?;
+        bincode::serialize_into(&mut writer, &self.scratchpad)?;
+
+        bincode::serialize_into(&mut writer, &self.dma_bus.main_ram)?;
+        self.dma_bus.cdrom.save_state(&mut writer)?;
+        self.dma_bus.gpu.save_state(&mut writer)?;
+        bincode::serialize_into(&mut writer, &self.dma_bus.mdec)?;
+        self.dma_bus.spu.save_state(&mut writer)?;
+
+        Ok(())
+    }
+
+    pub fn load_state<R: Read>(&mut self, mut reader: R) -> bincode::Result<()> {
+        self.mem_ctrl_1 = bincode::deserialize_from(&mut reader)?;
+        self.mem_ctrl_2 = bincode::deserialize_from(&mut reader)?;
+        self.cache_control = bincode::deserialize_from(&mut reader)?;
+        self.interrupts = bincode::deserialize_from(&mut reader)?;
+        self.controller_mem_card.load_state(&mut reader)?;
+        self.timers = bincode::deserialize_from(&mut reader)?;
+        self.dma = bincode::deserialize_from(&mut reader)?;
+        self.scratchpad = bincode::deserialize_from(&mut reader)?;
+
+        self.dma_bus.main_ram = bincode::deserialize_from(&mut reader)?;
+        self.dma_bus.cdrom.load_state(&mut reader)?;
+        self.dma_bus.gpu.load_state(&mut reader)?;
+        self.dma_bus.mdec = bincode::deserialize_from(&mut reader)?;
+        self.dma_bus.spu.load_state(&mut reader)?;
+
+        Ok(())
+    }
+}
+
 impl CpuBus {
     // TODO: handle errors
     //
@@ -357,7 +530,11 @@ impl CpuBus {
     /// Since DMA is running using the CPU resources, we should run it and
     /// treat the cycles consumed by it as if they were running from the CPU
     pub fn clock_dma(&mut self) -> u32 {
-        self.dma.clock_dma(&mut self.dma_bus, &mut self.interrupts)
+        let cycles = self.dma.clock_dma(&mut self.dma_bus, &mut self.interrupts);
+        if let Some(report) = self.dma.take_unimplemented() {
+            self.pending_unimplemented.get_or_insert(report);
+        }
+        cycles
     }
 
     pub fn clock_components(&mut self, cpu_cycles: u32) {
@@ -382,145 +559,236 @@ impl CpuBus {
         self.timers.clock_from_gpu_dot(dot_clocks);
         // interrupts for the timers
         self.timers.handle_interrupts(&mut self.interrupts);
+
+        let reports = [
+            self.dma_bus.gpu.take_unimplemented(),
+            self.controller_mem_card.take_unimplemented(),
+            self.dma_bus.cdrom.take_unimplemented(),
+        ];
+        for report in reports.into_iter().flatten() {
+            self.pending_unimplemented.get_or_insert(report);
+        }
+    }
+
+    /// The first unimplemented feature used since the last call, only the first is
+    /// kept, as later ones are most likely caused by it
+    pub fn take_unimplemented(&mut self) -> Option<Report> {
+        self.pending_unimplemented.take()
+    }
+
+    /// Components only get the offset inside their region, this puts back the full
+    /// address, and keeps the first unimplemented access to report it
+    fn access_error(&mut self, error: BusError, addr: u32) -> BusError {
+        let error = error.at(addr);
+        if error.kind == BusErrorKind::Unimplemented {
+            self.pending_unimplemented
+                .get_or_insert((error.component, error.to_string()));
+        }
+        error
     }
 
     // implement the PSX memory map
     // Note that `addr >= 0xFFFE0000` point to the cache control registers and isn't changed
-    fn map_address(&self, addr: u32) -> Result<u32> {
+    fn map_address(&self, addr: u32, width: u8, access: BusAccess) -> Result<u32> {
         let region = addr >> 29;
         const MASK_512M: u32 = 0x1FFFFFFF;
 
+        let inaccessible = || {
+            Err(BusError::new(
+                HardwareComponent::Bus,
+                addr,
+                width,
+                access,
+                BusErrorKind::InaccessibleSegment,
+            ))
+        };
+
         match region {
             // KUSEG mirror of KSEG0/KSEG1
             0 => Ok(addr & MASK_512M),
-            1..=3 => Err(String::from("Accessing bottom 1.5G of KUSEG")),
+            // Accessing bottom 1.5G of KUSEG
+            1..=3 => inaccessible(),
             // KSEG0
             4 => Ok(addr & MASK_512M),
             // KSEG1
             5 => {
+                // Cannot access scratchpad from KSEG1
                 if (0xBF800000..0xBF801000).contains(&addr) {
-                    Err(String::from("Cannot access scratchpad from KSEG1"))
+                    inaccessible()
                 } else {
                     Ok(addr & MASK_512M)
                 }
             }
             // KSEG2
             7 if addr >= 0xFFFE0000 => Ok(addr), // no change
-            6 | 7 => Err(String::from(
-                "KSEG2 has only the cache control registers at 0xFFFE0000",
-            )),
+            // KSEG2 has only the cache control registers at 0xFFFE0000
+            6 | 7 => inaccessible(),
             _ => unreachable!(),
         }
     }
+
+    /// Checks the alignment and maps the address, see [`CpuBus::map_address`]
+    fn prepare_access(&self, addr: u32, width: u8, access: BusAccess) -> Result<u32> {
+        if !addr.is_multiple_of(width as u32 / 8) {
+            return Err(BusError::new(
+                HardwareComponent::Bus,
+                addr,
+                width,
+                access,
+                BusErrorKind::Unaligned,
+            ));
+        }
+        self.map_address(addr, width, access)
+    }
 }
 
 impl BusLine for CpuBus {
+    const COMPONENT: HardwareComponent = HardwareComponent::Bus;
+
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
-        assert!(addr % 4 == 0, "unalligned u32 read");
-        let addr = self.map_address(addr)?;
+        let mapped = self.prepare_access(addr, 32, BusAccess::Read)?;
 
-        match addr {
+        match mapped {
             // TODO: implement I-cache isolation properly
-            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.read_u32(addr & 0x1FFFFF),
-            0x1FC00000..=0x1FC80000 => self.bios.read_u32(addr),
-            0x1F800000..=0x1F8003FF => self.scratchpad.read_u32(addr & 0x3FF),
-            0x1F801000..=0x1F801020 => self.mem_ctrl_1.read_u32(addr),
-            0x1F801044..=0x1F80104F => self.controller_mem_card.read_u32(addr & 0xF),
-            0x1F801060 => self.mem_ctrl_2.read_u32(addr),
-            0x1F801070..=0x1F801077 => self.interrupts.read_u32(addr & 0xF),
-            0x1F801080..=0x1F8010FC => self.dma.read_u32(addr & 0xFF),
-            0x1F801100..=0x1F80112F => self.timers.read_u32(addr & 0xFF),
-            0x1F801810..=0x1F801814 => self.dma_bus.gpu.read_u32(addr & 0xF),
-            0x1F801820..=0x1F801824 => self.dma_bus.mdec.read_u32(addr & 0xF),
-            0x1F801C00..=0x1F801FFC => self.dma_bus.spu.read_u32(addr & 0x3FF),
-            0x1F802000..=0x1F80208F => self.expansion_region_2.read_u32(addr & 0xFF),
-            0xFFFE0130 => self.cache_control.read_u32(addr),
-            _ => Err(format!("MainBus: u32 read from {:08X}", addr)),
+            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.read_u32(mapped & 0x1FFFFF),
+            0x1FC00000..=0x1FC80000 => self.bios.read_u32(mapped),
+            0x1F800000..=0x1F8003FF => self.scratchpad.read_u32(mapped & 0x3FF),
+            0x1F801000..=0x1F801020 => self.mem_ctrl_1.read_u32(mapped),
+            0x1F801044..=0x1F80104F => self.controller_mem_card.read_u32(mapped & 0xF),
+            0x1F801060 => self.mem_ctrl_2.read_u32(mapped),
+            0x1F801070..=0x1F801077 => self.interrupts.read_u32(mapped & 0xF),
+            0x1F801080..=0x1F8010FC => self.dma.read_u32(mapped & 0xFF),
+            0x1F801100..=0x1F80112F => self.timers.read_u32(mapped & 0xFF),
+            0x1F801810..=0x1F801814 => self.dma_bus.gpu.read_u32(mapped & 0xF),
+            0x1F801820..=0x1F801824 => self.dma_bus.mdec.read_u32(mapped & 0xF),
+            0x1F801C00..=0x1F801FFC => self.dma_bus.spu.read_u32(mapped & 0x3FF),
+            0x1F802000..=0x1F80208F => self.expansion_region_2.read_u32(mapped & 0xFF),
+            0xFFFE0130 => self.cache_control.read_u32(mapped),
+            _ => Err(BusError::new(
+                HardwareComponent::Bus,
+                mapped,
+                32,
+                BusAccess::Read,
+                BusErrorKind::Unmapped,
+            )),
         }
+        .map_err(|e| self.access_error(e, addr))
     }
 
     fn write_u32(&mut self, addr: u32, data: u32) -> Result<()> {
-        assert!(addr % 4 == 0, "unalligned u32 write");
-        let addr = self.map_address(addr)?;
-
-        match addr {
-            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.write_u32(addr & 0x1FFFFF, data),
-            0x1F800000..=0x1F8003FF => self.scratchpad.write_u32(addr & 0x3FF, data),
-            0x1F801000..=0x1F801020 => self.mem_ctrl_1.write_u32(addr, data),
-            0x1F801060 => self.mem_ctrl_2.write_u32(addr, data),
-            0x1F801070..=0x1F801077 => self.interrupts.write_u32(addr & 0xF, data),
-            0x1F801080..=0x1F8010FC => self.dma.write_u32(addr & 0xFF, data),
-            0x1F801100..=0x1F80112F => self.timers.write_u32(addr & 0xFF, data),
-            0x1F801810..=0x1F801814 => self.dma_bus.gpu.write_u32(addr & 0xF, data),
-            0x1F801820..=0x1F801824 => self.dma_bus.mdec.write_u32(addr & 0xF, data),
-            0x1F801C00..=0x1F801FFC => self.dma_bus.spu.write_u32(addr & 0x3FF, data),
-            0x1F802000..=0x1F80208F => self.expansion_region_2.write_u32(addr & 0xFF, data),
-            0xFFFE0130 => self.cache_control.write_u32(addr, data),
-            _ => Err(format!("MainBus: u32 write to {:08X}", addr)),
+        let mapped = self.prepare_access(addr, 32, BusAccess::Write)?;
+
+        match mapped {
+            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.write_u32(mapped & 0x1FFFFF, data),
+            0x1F800000..=0x1F8003FF => self.scratchpad.write_u32(mapped & 0x3FF, data),
+            0x1F801000..=0x1F801020 => self.mem_ctrl_1.write_u32(mapped, data),
+            0x1F801060 => self.mem_ctrl_2.write_u32(mapped, data),
+            0x1F801070..=0x1F801077 => self.interrupts.write_u32(mapped & 0xF, data),
+            0x1F801080..=0x1F8010FC => self.dma.write_u32(mapped & 0xFF, data),
+            0x1F801100..=0x1F80112F => self.timers.write_u32(mapped & 0xFF, data),
+            0x1F801810..=0x1F801814 => self.dma_bus.gpu.write_u32(mapped & 0xF, data),
+            0x1F801820..=0x1F801824 => self.dma_bus.mdec.write_u32(mapped & 0xF, data),
+            0x1F801C00..=0x1F801FFC => self.dma_bus.spu.write_u32(mapped & 0x3FF, data),
+            0x1F802000..=0x1F80208F => self.expansion_region_2.write_u32(mapped & 0xFF, data),
+            0xFFFE0130 => self.cache_control.write_u32(mapped, data),
+            _ => Err(BusError::new(
+                HardwareComponent::Bus,
+                mapped,
+                32,
+                BusAccess::Write,
+                BusErrorKind::Unmapped,
+            )),
         }
+        .map_err(|e| self.access_error(e, addr))
     }
 
     fn read_u16(&mut self, addr: u32) -> Result<u16> {
-        assert!(addr % 2 == 0, "unalligned u16 read");
-        let addr = self.map_address(addr)?;
-
-        match addr {
-            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.read_u16(addr & 0x1FFFFF),
-            0x1F800000..=0x1F8003FF => self.scratchpad.read_u16(addr & 0x3FF),
-            0x1F801044..=0x1F80104F => self.controller_mem_card.read_u16(addr & 0xF),
-            0x1F801070..=0x1F801077 => self.interrupts.read_u16(addr & 0xF),
-            0x1F801100..=0x1F80112F => self.timers.read_u16(addr & 0xFF),
-            0x1F801C00..=0x1F801FFC => self.dma_bus.spu.read_u16(addr & 0x3FF),
-            0x1FC00000..=0x1FC80000 => self.bios.read_u16(addr),
-            0x1F802000..=0x1F80208F => self.expansion_region_2.read_u16(addr & 0xFF),
-            _ => Err(format!("u16 read from {:08X}", addr)),
+        let mapped = self.prepare_access(addr, 16, BusAccess::Read)?;
+
+        match mapped {
+            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.read_u16(mapped & 0x1FFFFF),
+            0x1F800000..=0x1F8003FF => self.scratchpad.read_u16(mapped & 0x3FF),
+            0x1F801044..=0x1F80104F => self.controller_mem_card.read_u16(mapped & 0xF),
+            0x1F801070..=0x1F801077 => self.interrupts.read_u16(mapped & 0xF),
+            0x1F801100..=0x1F80112F => self.timers.read_u16(mapped & 0xFF),
+            0x1F801C00..=0x1F801FFC => self.dma_bus.spu.read_u16(mapped & 0x3FF),
+            0x1FC00000..=0x1FC80000 => self.bios.read_u16(mapped),
+            0x1F802000..=0x1F80208F => self.expansion_region_2.read_u16(mapped & 0xFF),
+            _ => Err(BusError::new(
+                HardwareComponent::Bus,
+                mapped,
+                16,
+                BusAccess::Read,
+                BusErrorKind::Unmapped,
+            )),
         }
+        .map_err(|e| self.access_error(e, addr))
     }
 
     fn write_u16(&mut self, addr: u32, data: u16) -> Result<()> {
-        assert!(addr % 2 == 0, "unalligned u16 write");
-        let addr = self.map_address(addr)?;
-
-        match addr {
-            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.write_u16(addr & 0x1FFFFF, data),
-            0x1F800000..=0x1F8003FF => self.scratchpad.write_u16(addr & 0x3FF, data),
-            0x1F801048..=0x1F80104F => self.controller_mem_card.write_u16(addr & 0xF, data),
-            0x1F801070..=0x1F801077 => self.interrupts.write_u16(addr & 0xF, data),
-            0x1F801100..=0x1F80112F => self.timers.write_u16(addr & 0xFF, data),
-            0x1F801C00..=0x1F801FFC => self.dma_bus.spu.write_u16(addr & 0x3FF, data),
-            0x1F802000..=0x1F80208F => self.expansion_region_2.write_u16(addr & 0xFF, data),
-            _ => Err(format!("u16 write to {:08X}", addr)),
+        let mapped = self.prepare_access(addr, 16, BusAccess::Write)?;
+
+        match mapped {
+            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.write_u16(mapped & 0x1FFFFF, data),
+            0x1F800000..=0x1F8003FF => self.scratchpad.write_u16(mapped & 0x3FF, data),
+            0x1F801048..=0x1F80104F => self.controller_mem_card.write_u16(mapped & 0xF, data),
+            0x1F801070..=0x1F801077 => self.interrupts.write_u16(mapped & 0xF, data),
+            0x1F801100..=0x1F80112F => self.timers.write_u16(mapped & 0xFF, data),
+            0x1F801C00..=0x1F801FFC => self.dma_bus.spu.write_u16(mapped & 0x3FF, data),
+            0x1F802000..=0x1F80208F => self.expansion_region_2.write_u16(mapped & 0xFF, data),
+            _ => Err(BusError::new(
+                HardwareComponent::Bus,
+                mapped,
+                16,
+                BusAccess::Write,
+                BusErrorKind::Unmapped,
+            )),
         }
+        .map_err(|e| self.access_error(e, addr))
     }
     fn read_u8(&mut self, addr: u32) -> Result<u8> {
-        let addr = self.map_address(addr)?;
-
-        match addr {
-            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.read_u8(addr & 0x1FFFFF),
-            0x1F800000..=0x1F8003FF => self.scratchpad.read_u8(addr & 0x3FF),
-            0x1F801040 => self.controller_mem_card.read_u8(addr & 0xF),
-            0x1F000000..=0x1F080000 => self.expansion_region_1.read_u8(addr & 0xFFFFF),
-            0x1F801080..=0x1F8010FF => self.dma.read_u8(addr & 0xFF),
-            0x1F801800..=0x1F801803 => self.dma_bus.cdrom.read_u8(addr & 3),
-            0x1F802000..=0x1F80208F => self.expansion_region_2.read_u8(addr & 0xFF),
-            0x1FC00000..=0x1FC80000 => self.bios.read_u8(addr),
-            _ => Err(format!("u8 read from {:08X}", addr)),
+        let mapped = self.prepare_access(addr, 8, BusAccess::Read)?;
+
+        match mapped {
+            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.read_u8(mapped & 0x1FFFFF),
+            0x1F800000..=0x1F8003FF => self.scratchpad.read_u8(mapped & 0x3FF),
+            0x1F801040 => self.controller_mem_card.read_u8(mapped & 0xF),
+            0x1F000000..=0x1F080000 => self.expansion_region_1.read_u8(mapped & 0xFFFFF),
+            0x1F801080..=0x1F8010FF => self.dma.read_u8(mapped & 0xFF),
+            0x1F801800..=0x1F801803 => self.dma_bus.cdrom.read_u8(mapped & 3),
+            0x1F802000..=0x1F80208F => self.expansion_region_2.read_u8(mapped & 0xFF),
+            0x1FC00000..=0x1FC80000 => self.bios.read_u8(mapped),
+            _ => Err(BusError::new(
+                HardwareComponent::Bus,
+                mapped,
+                8,
+                BusAccess::Read,
+                BusErrorKind::Unmapped,
+            )),
         }
+        .map_err(|e| self.access_error(e, addr))
     }
 
     fn write_u8(&mut self, addr: u32, data: u8) -> Result<()> {
-        let addr = self.map_address(addr)?;
-
-        match addr {
-            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.write_u8(addr & 0x1FFFFF, data),
-            0x1F800000..=0x1F8003FF => self.scratchpad.write_u8(addr & 0x3FF, data),
-            0x1F801040 => self.controller_mem_card.write_u8(addr & 0xF, data),
-            0x1F000000..=0x1F080000 => self.expansion_region_1.write_u8(addr & 0xFFFFF, data),
-            0x1F801080..=0x1F8010FF => self.dma.write_u8(addr & 0xFF, data),
-            0x1F801800..=0x1F801803 => self.dma_bus.cdrom.write_u8(addr & 3, data),
-            0x1F802000..=0x1F80208F => self.expansion_region_2.write_u8(addr & 0xFF, data),
-            _ => Err(format!("u8 write to {:08X}", addr)),
+        let mapped = self.prepare_access(addr, 8, BusAccess::Write)?;
+
+        match mapped {
+            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.write_u8(mapped & 0x1FFFFF, data),
+            0x1F800000..=0x1F8003FF => self.scratchpad.write_u8(mapped & 0x3FF, data),
+            0x1F801040 => self.controller_mem_card.write_u8(mapped & 0xF, data),
+            0x1F000000..=0x1F080000 => self.expansion_region_1.write_u8(mapped & 0xFFFFF, data),
+            0x1F801080..=0x1F8010FF => self.dma.write_u8(mapped & 0xFF, data),
+            0x1F801800..=0x1F801803 => self.dma_bus.cdrom.write_u8(mapped & 3, data),
+            0x1F802000..=0x1F80208F => self.expansion_region_2.write_u8(mapped & 0xFF, data),
+            _ => Err(BusError::new(
+                HardwareComponent::Bus,
+                mapped,
+                8,
+                BusAccess::Write,
+                BusErrorKind::Unmapped,
+            )),
         }
+        .map_err(|e| self.access_error(e, addr))
     }
 }
 
@@ -1,11 +1,16 @@
+use serde::{Deserialize, Serialize};
+
 use crate::mdec;
 use crate::memory::Result;
+use crate::unimplemented::{self, Report};
+use crate::HardwareComponent;
 
 use super::interrupts::InterruptRequester;
 use super::BusLine;
 
 bitflags::bitflags! {
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct ChannelControl: u32 {
         const DIRECTION_FROM_RAM       = 0b00000000000000000000000000000001;
         const ADDRESS_STEP_DIRECTION   = 0b00000000000000000000000000000010;
@@ -54,7 +59,8 @@ impl ChannelControl {
 }
 
 bitflags::bitflags! {
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct DmaInterruptRegister: u32 {
         const UNKNOWN                = 0b00000000000000000000000000111111;
         const FORCE_IRQ              = 0b00000000000000001000000000000000;
@@ -93,7 +99,7 @@ impl DmaInterruptRegister {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 struct DmaChannel {
     base_address: u32,
     block_control: u32,
@@ -152,11 +158,15 @@ impl DmaChannel {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Dma {
     control: u32,
     interrupt: DmaInterruptRegister,
 
     channels: [DmaChannel; 7],
+
+    #[serde(skip)]
+    pending_unimplemented: Option<Report>,
 }
 
 impl Default for Dma {
@@ -165,6 +175,7 @@ impl Default for Dma {
             control: 0x07654321,
             interrupt: Default::default(),
             channels: Default::default(),
+            pending_unimplemented: None,
         }
     }
 }
@@ -603,6 +614,10 @@ impl Dma {
 }
 
 impl Dma {
+    pub(super) fn take_unimplemented(&mut self) -> Option<Report> {
+        self.pending_unimplemented.take()
+    }
+
     pub(super) fn needs_to_run(&self) -> bool {
         self.channels.iter().enumerate().any(|(i, channel)| {
             let channel_enabled = (self.control >> (i * 4)) & 0b1000 != 0;
@@ -676,7 +691,15 @@ impl Dma {
                 2 => Self::perform_gpu_channel2_dma(channel, dma_bus),
                 3 => Self::perform_cdrom_channel3_dma(channel, dma_bus),
                 4 => Self::perform_spu_channel4_dma(channel, dma_bus),
-                5 => todo!("DMA channel PIO 5"),
+                5 => {
+                    unimplemented::report(
+                        &mut self.pending_unimplemented,
+                        HardwareComponent::Dma,
+                        "DMA channel PIO 5".to_string(),
+                    );
+                    // nothing is connected to the expansion port, finish right away
+                    (1, true)
+                }
                 6 => Self::perform_otc_channel6_dma(channel, dma_bus),
                 _ => unreachable!(),
             };
@@ -714,6 +737,8 @@ impl Dma {
 }
 
 impl BusLine for Dma {
+    const COMPONENT: HardwareComponent = HardwareComponent::Dma;
+
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         let r = match addr {
             0x80..=0xEF => {
@@ -1,6 +1,6 @@
-use crate::{memory::Result, PsxConfig};
+use crate::{memory::Result, HardwareComponent, PsxConfig};
 
-use super::BusLine;
+use super::{BusAccess, BusLine};
 
 // for now there is no external device to be hooked in PIO extension, so maybe
 // use it as ram?
@@ -22,6 +22,8 @@ impl Default for ExpansionRegion1 {
 }
 
 impl BusLine for ExpansionRegion1 {
+    const COMPONENT: HardwareComponent = HardwareComponent::Expansion;
+
     fn read_u8(&mut self, addr: u32) -> Result<u8> {
         Ok(self.data[addr as usize])
     }
@@ -56,31 +58,18 @@ impl DuartTTY {
         }
     }
 
-    fn read(&self, addr: u32) -> u8 {
+    /// `None` if the register is not emulated
+    fn read(&self, addr: u32) -> Option<u8> {
         match addr & 0xF {
-            0x0 => todo!(),
             // DUART Status Register A
             // bit.2: Tx Empty (ready to send)
-            0x1 => 0b100,
-            0x2 => todo!(),
-            0x3 => todo!(),
-            0x4 => todo!(),
-            0x5 => todo!(),
-            0x6 => todo!(),
-            0x7 => todo!(),
-            0x8 => todo!(),
-            0x9 => todo!(),
-            0xA => todo!(),
-            0xB => todo!(),
-            0xC => todo!(),
-            0xD => todo!(),
-            0xE => todo!(),
-            0xF => todo!(),
-            _ => unreachable!(),
+            0x1 => Some(0b100),
+            _ => None,
         }
     }
 
-    fn write(&mut self, addr: u32, data: u8) {
+    /// `None` if the register is not emulated
+    fn write(&mut self, addr: u32, data: u8) -> Option<()> {
         match addr & 0xF {
             // DUART Mode Register A
             0x0 => {}
@@ -109,21 +98,15 @@ impl DuartTTY {
             // DUART Interrupt Mask Register
             // 0 is written here, so no need to handle any interrupts
             0x5 => {}
-            0x6 => todo!(),
-            0x7 => todo!(),
-            0x8 => todo!(),
-            0x9 => todo!(),
             // DUART Command Register B
             0xA => {}
-            0xB => todo!(),
-            0xC => todo!(),
             // DUART Output Port Configuration Register
             0xD => {}
             // DUART Set Output Port Bits Command
             0xE => {}
-            0xF => todo!(),
-            _ => unreachable!(),
+            _ => return None,
         }
+        Some(())
     }
 }
 
@@ -144,9 +127,14 @@ impl ExpansionRegion2 {
 }
 
 impl BusLine for ExpansionRegion2 {
+    const COMPONENT: HardwareComponent = HardwareComponent::Expansion;
+
     fn read_u8(&mut self, addr: u32) -> Result<u8> {
         let out = match addr {
-            0x20..=0x2F => self.tty_duart.read(addr & 0xF),
+            0x20..=0x2F => self
+                .tty_duart
+                .read(addr & 0xF)
+                .ok_or_else(|| Self::unimplemented(addr, 8, BusAccess::Read))?,
             _ => self.data[addr as usize],
         };
 
@@ -161,14 +149,17 @@ impl BusLine for ExpansionRegion2 {
             data
         );
 
+        self.data[addr as usize] = data;
+
         match addr {
-            0x20..=0x2F => self.tty_duart.write(addr & 0xF, data),
+            0x20..=0x2F => self
+                .tty_duart
+                .write(addr & 0xF, data)
+                .ok_or_else(|| Self::unimplemented(addr, 8, BusAccess::Write))?,
             // POST register used for debugging the BIOS and kernel init
             0x41 => println!("TraceStep {:02X}", data),
-            _ => self.data[addr as usize] = data,
+            _ => {}
         }
-
-        self.data[addr as usize] = data;
         Ok(())
     }
 
@@ -1,9 +1,13 @@
+use serde::{Deserialize, Serialize};
+
 use crate::memory::Result;
+use crate::HardwareComponent;
 
 use super::BusLine;
 
 bitflags::bitflags! {
-    #[derive(Default, Debug, Clone, Copy)]
+    #[derive(Default, Debug, Clone, Copy, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct InterruptFlags: u16 {
         const VBLANK                 = 1 << 0;
         const GPU                    = 1 << 1;
@@ -30,7 +34,7 @@ pub trait InterruptRequester {
     fn request_spu(&mut self);
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct Interrupts {
     stat: InterruptFlags,
     mask: InterruptFlags,
@@ -43,6 +47,8 @@ impl Interrupts {
 }
 
 impl BusLine for Interrupts {
+    const COMPONENT: HardwareComponent = HardwareComponent::Interrupts;
+
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         let r = match addr {
             0 => self.stat.bits() as u32,
@@ -1,8 +1,11 @@
+use serde::{Deserialize, Serialize};
+
 use crate::memory::Result;
+use crate::HardwareComponent;
 
 use super::BusLine;
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct MemoryControl1 {
     data: [u32; 9],
     // TODO: if these are used, then use them as variables instead of array
@@ -19,6 +22,8 @@ pub struct MemoryControl1 {
 }
 
 impl BusLine for MemoryControl1 {
+    const COMPONENT: HardwareComponent = HardwareComponent::MemoryControl;
+
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         let addr = addr & 0xFF;
         let index = (addr / 4) as usize;
@@ -38,10 +43,12 @@ impl BusLine for MemoryControl1 {
 }
 
 // RAM_SIZE
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct MemoryControl2(u32);
 
 impl BusLine for MemoryControl2 {
+    const COMPONENT: HardwareComponent = HardwareComponent::MemoryControl;
+
     fn read_u32(&mut self, _addr: u32) -> Result<u32> {
         Ok(self.0)
     }
@@ -58,10 +65,12 @@ impl BusLine for MemoryControl2 {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct CacheControl(u32);
 
 impl BusLine for CacheControl {
+    const COMPONENT: HardwareComponent = HardwareComponent::CacheControl;
+
     fn read_u32(&mut self, _addr: u32) -> Result<u32> {
         Ok(self.0)
     }
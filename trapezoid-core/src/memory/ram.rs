@@ -1,9 +1,12 @@
 use byteorder::{ByteOrder, LittleEndian};
+use serde::{Deserialize, Serialize};
 
 use crate::memory::Result;
+use crate::HardwareComponent;
 
 use super::BusLine;
 
+#[derive(Serialize, Deserialize)]
 pub struct MainRam {
     data: Vec<u8>,
 }
@@ -27,6 +30,8 @@ impl MainRam {
 }
 
 impl BusLine for MainRam {
+    const COMPONENT: HardwareComponent = HardwareComponent::MainRam;
+
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         let index = (addr as usize) & 0x1FFFFF;
 
@@ -62,6 +67,7 @@ impl BusLine for MainRam {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Scratchpad {
     data: Vec<u8>,
 }
@@ -75,6 +81,8 @@ impl Default for Scratchpad {
 }
 
 impl BusLine for Scratchpad {
+    const COMPONENT: HardwareComponent = HardwareComponent::Scratchpad;
+
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         let index = addr as usize;
 
@@ -1,10 +1,27 @@
+mod mixer;
+mod recorder;
+mod resampler;
+mod reverb;
+
 use std::{
     cell::Cell,
     collections::VecDeque,
+    io::{Read, Write},
     ops::{Index, IndexMut, Range},
 };
 
-use crate::memory::{interrupts::InterruptRequester, BusLine, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::memory::{interrupts::InterruptRequester, BusAccess, BusLine, Result};
+use crate::state::boxed_array;
+use crate::HardwareComponent;
+use mixer::AudioMixer;
+use resampler::Resampler;
+use reverb::Reverb;
+
+pub use mixer::AudioSource;
+pub use recorder::AudioRecorder;
+pub use resampler::{AudioResampler, SPU_SAMPLE_RATE};
 
 const CPU_CLOCKS_PER_SPU: u32 = 0x300;
 
@@ -16,7 +33,8 @@ enum RamTransferMode {
 }
 
 bitflags::bitflags! {
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct SpuControl: u16 {
         const CD_AUDIO_ENABLE         = 0b0000000000000001;
         const EXTERNAL_AUDIO_ENABLE   = 0b0000000000000010;
@@ -26,13 +44,21 @@ bitflags::bitflags! {
         const IRQ9_ENABLE             = 0b0000000001000000;
         const REVERB_MASTER_ENABLE    = 0b0000000010000000;
         const NOISE_FREQ_STEP         = 0b0000001100000000;
-        const NOICE_FREQ_SHIFT        = 0b0011110000000000;
+        const NOISE_FREQ_SHIFT        = 0b0011110000000000;
         const UNMUTE_SPU              = 0b0100000000000000;
         const SPU_ENABLE              = 0b1000000000000000;
     }
 }
 
 impl SpuControl {
+    fn noise_step(&self) -> i32 {
+        ((self.bits() >> 8) & 0b11) as i32 + 4
+    }
+
+    fn noise_shift(&self) -> u32 {
+        ((self.bits() >> 10) & 0b1111) as u32
+    }
+
     fn ram_transfer_mode(&self) -> RamTransferMode {
         match self.bits() & Self::SOUND_RAM_TRANSFER_MODE.bits() {
             0b000000 => RamTransferMode::Stop,
@@ -45,7 +71,8 @@ impl SpuControl {
 }
 
 bitflags::bitflags! {
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct SpuStat: u16 {
         const CURRENT_SPU_MODE                 = 0b0000000000111111;
         const IRQ_FLAG                         = 0b0000000001000000;
@@ -63,7 +90,7 @@ bitflags::bitflags! {
 const ADPCM_TABLE_POS: &[i32; 5] = &[0, 60, 115, 98, 122];
 const ADPCM_TABLE_NEG: &[i32; 5] = &[0, 0, -52, -55, -60];
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
 struct AdpcmDecoder {
     old: i32,
     older: i32,
@@ -118,7 +145,36 @@ impl AdpcmDecoder {
     }
 }
 
-#[derive(Default)]
+/// Random samples used instead of ADPCM by voices in noise mode
+#[derive(Default, Serialize, Deserialize)]
+struct NoiseGenerator {
+    timer: i32,
+    level: u16,
+}
+
+impl NoiseGenerator {
+    /// Shifts a new random bit into the level when the timer,
+    /// configured by the noise step and shift in `control`, runs out
+    fn clock(&mut self, control: &SpuControl) {
+        self.timer -= control.noise_step();
+        let parity_bit =
+            (self.level >> 15) ^ (self.level >> 12) ^ (self.level >> 11) ^ (self.level >> 10) ^ 1;
+        if self.timer < 0 {
+            self.level = (self.level << 1) | (parity_bit & 1);
+        }
+        for _ in 0..2 {
+            if self.timer < 0 {
+                self.timer += 0x20000 >> control.noise_shift();
+            }
+        }
+    }
+
+    fn sample(&self) -> i16 {
+        self.level as i16
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
 struct VoicesFlag {
     bits: u32,
 }
@@ -144,7 +200,8 @@ impl VoicesFlag {
 }
 
 bitflags::bitflags! {
-    #[derive(Default, Clone, Copy)]
+    #[derive(Default, Clone, Copy, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct ADSRConfig: u32 {
         const SUSTAIN_LEVEL                    = 0b00000000000000000000000000001111;
         // decay step is fixed (-8)
@@ -211,7 +268,107 @@ impl std::fmt::Debug for ADSRConfig {
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq)]
+/// The envelope operation, shared by the ADSR and the volume sweep
+///
+///  AdsrCycles = 1 SHL Max(0,ShiftValue-11)
+///  AdsrStep = StepValue SHL Max(0,11-ShiftValue)
+///  IF exponential AND increase AND AdsrLevel>6000h THEN AdsrCycles=AdsrCycles*4
+///  IF exponential AND decrease THEN AdsrStep=AdsrStep*AdsrLevel/8000h
+///
+/// returns the cycles to wait, and the step to add to `level`
+fn envelope_step(
+    mode_exponential: bool,
+    direction_decrease: bool,
+    shift: u8,
+    step: i16,
+    level: u16,
+) -> (u32, i16) {
+    let mut cycles = 1 << shift.saturating_sub(11);
+    let mut step = step << (11u8).saturating_sub(shift);
+
+    // fake exponential
+    if mode_exponential {
+        if direction_decrease {
+            step = (step as i32 * level as i32 / 0x8000).clamp(-0x8000, 0x7FFF) as i16;
+            if step == 0 {
+                step = -1;
+            }
+        } else if level > 0x6000 {
+            if shift < 10 {
+                step /= 4;
+            } else if shift >= 11 {
+                cycles *= 4;
+            } else {
+                step /= 4;
+                cycles *= 4;
+            }
+        }
+    }
+
+    (cycles, step)
+}
+
+/// A volume register, which is either a fixed volume, or a sweep envelope
+/// changing the current volume over time
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+struct Volume {
+    register: u16,
+    /// The volume applied, readable from the current volume registers
+    current: i16,
+    i_sweep_cycle_counter: u32,
+}
+
+impl Volume {
+    fn write(&mut self, data: u16) {
+        self.register = data;
+        self.i_sweep_cycle_counter = 0;
+        // volume mode
+        if data & 0x8000 == 0 {
+            self.current = (data * 2) as i16;
+        }
+    }
+
+    /// Steps the sweep envelope, at 44.1kHz
+    fn clock_sweep(&mut self) {
+        const STEPS_POS: &[i16; 4] = &[7, 6, 5, 4];
+        const STEPS_NEG: &[i16; 4] = &[-8, -7, -6, -5];
+
+        // sweep mode
+        if self.register & 0x8000 == 0 {
+            return;
+        }
+        if self.i_sweep_cycle_counter > 0 {
+            self.i_sweep_cycle_counter -= 1;
+            return;
+        }
+
+        let mode_exponential = self.register & 0x4000 != 0;
+        let direction_decrease = self.register & 0x2000 != 0;
+        // the envelope is inverted for the negative phase
+        let phase_negative = self.register & 0x1000 != 0;
+        let shift = ((self.register >> 2) & 0x1F) as u8;
+        let step_i = (self.register & 0b11) as usize;
+        let step = if direction_decrease {
+            STEPS_NEG[step_i]
+        } else {
+            STEPS_POS[step_i]
+        };
+
+        let level = self.current.unsigned_abs().min(0x7FFF);
+        let (cycles, step) =
+            envelope_step(mode_exponential, direction_decrease, shift, step, level);
+        self.i_sweep_cycle_counter = cycles.max(1) - 1;
+
+        let level = (level as i16).saturating_add(step).clamp(0, 0x7FFF);
+        self.current = if phase_negative { -level } else { level };
+    }
+
+    fn apply(&self, sample: i32) -> i32 {
+        (sample * self.current as i32 / 0x8000).clamp(-0x8000, 0x7FFF)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
 enum ADSRState {
     Attack,
     Decay,
@@ -221,13 +378,10 @@ enum ADSRState {
     Stopped,
 }
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
 struct Voice {
-    volume_left: u16,
-    volume_right: u16,
-
-    current_vol_left: i16,
-    current_vol_right: i16,
+    volume_left: Volume,
+    volume_right: Volume,
 
     /// pitch
     adpcm_sample_rate: u16,
@@ -385,28 +539,13 @@ impl Voice {
             return;
         }
 
-        let mut adsr_cycles = 1 << shift.saturating_sub(11);
-        let mut adsr_step = step << (11u8).saturating_sub(shift);
-
-        // fake exponential
-        if mode_exponential {
-            if direction_decrease {
-                adsr_step = (adsr_step as i32 * self.adsr_current_vol as i32 / 0x8000)
-                    .clamp(-0x8000, 0x7FFF) as i16;
-                if adsr_step == 0 {
-                    adsr_step = -1;
-                }
-            } else if self.adsr_current_vol > 0x6000 {
-                if shift < 10 {
-                    adsr_step /= 4;
-                } else if shift >= 11 {
-                    adsr_cycles *= 4;
-                } else {
-                    adsr_step /= 4;
-                    adsr_cycles *= 4;
-                }
-            }
-        }
+        let (adsr_cycles, adsr_step) = envelope_step(
+            mode_exponential,
+            direction_decrease,
+            shift,
+            step,
+            self.adsr_current_vol,
+        );
 
         self.i_adsr_cycle_counter = adsr_cycles.max(1);
 
@@ -479,12 +618,20 @@ impl Voice {
         endx_set
     }
 
+    /// `modulator` is the output of the previous voice when pitch modulation is enabled,
+    /// and `noise` replaces the ADPCM samples when noise mode is enabled
+    ///
     /// returns
     /// - `true` if `ENDX` should be set
     /// - `mono_output` can be used for capture
     /// - `left_output`
     /// - `right_output`
-    fn clock_voice(&mut self, ram: &SpuRam) -> (bool, i16, i32, i32) {
+    fn clock_voice(
+        &mut self,
+        ram: &SpuRam,
+        modulator: Option<i16>,
+        noise: Option<i16>,
+    ) -> (bool, i16, i32, i32) {
         self.clock_adsr();
 
         let mut endx_set = false;
@@ -499,7 +646,14 @@ impl Voice {
 
         let current_index = self.i_cached_sample_index;
 
-        let mut step = self.adpcm_sample_rate;
+        let mut step = self.adpcm_sample_rate as u32;
+
+        if let Some(modulator) = modulator {
+            // the factor is 0.0 to ~2.0, pitches above 0x7FFF are sign extended
+            // before multiplying, and then cut to 16 bits, like the hardware does
+            let factor = modulator as i32 + 0x8000;
+            step = ((self.adpcm_sample_rate as i16 as i32 * factor) >> 15) as u32 & 0xFFFF;
+        }
 
         // clamp
         if step > 0x3FFF {
@@ -507,29 +661,25 @@ impl Voice {
         }
 
         // handle sample rate
-        self.i_adpcm_pitch_counter += step as u32;
+        self.i_adpcm_pitch_counter += step;
         // Counter.Bit12 and up indicates the current sample (within a ADPCM block).
         let next_sample = self.i_adpcm_pitch_counter >> 12;
-        // TODO: add pitch modulation
         // Counter.Bit3..11 are used as 8bit gaussian interpolation index
 
         self.i_cached_sample_index = next_sample as usize;
 
-        let current_sample = self.i_cached_28_samples_block[current_index];
+        // the ADPCM is still decoded in noise mode, for its flags
+        let current_sample = noise.unwrap_or(self.i_cached_28_samples_block[current_index]);
 
         // This `mono output` can be used in the capture buffer, the remaining
         // volume control and sweep are not included in the capture buffer data.
         let mono_output =
             (current_sample as i32 * self.adsr_current_vol as i32 / 0x8000).clamp(-0x8000, 0x7FFF);
 
-        // TODO: implement sweep
-        #[allow(unused)]
-        let sweep_mode = self.volume_left & 0x8000 == 0x8000;
-
-        let left_output =
-            (mono_output * self.current_vol_left as i32 / 0x8000).clamp(-0x8000, 0x7FFF);
-        let right_output =
-            (mono_output * self.current_vol_right as i32 / 0x8000).clamp(-0x8000, 0x7FFF);
+        self.volume_left.clock_sweep();
+        self.volume_right.clock_sweep();
+        let left_output = self.volume_left.apply(mono_output);
+        let right_output = self.volume_right.apply(mono_output);
 
         (endx_set, mono_output as i16, left_output, right_output)
     }
@@ -538,7 +688,9 @@ impl Voice {
 // 1KB of RAM (16bit)
 const CAPTURE_MEMORY_REGION_SIZE: usize = 0x200;
 
+#[derive(Serialize, Deserialize)]
 struct SpuRam {
+    #[serde(with = "boxed_array")]
     data: Box<[u16; 0x40000]>,
     /// The address from the ram, when read/written to it should trigger interrupt
     irq_address: usize,
@@ -645,10 +797,10 @@ impl Default for SpuRam {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct Spu {
-    main_vol_left: u16,
-    main_vol_right: u16,
+    main_vol_left: Volume,
+    main_vol_right: Volume,
 
     reverb_out_vol_left: u16,
     reverb_out_vol_right: u16,
@@ -660,9 +812,6 @@ pub struct Spu {
     external_vol_left: u16,
     external_vol_right: u16,
 
-    current_main_vol_left: i16,
-    current_main_vol_right: i16,
-
     ram_transfer_control: u16,
     ram_transfer_address: u16,
     i_ram_transfer_address: usize,
@@ -688,6 +837,8 @@ pub struct Spu {
     voices: [Voice; 24],
 
     reverb_config: [u16; 0x20],
+    reverb: Reverb,
+    noise: NoiseGenerator,
 
     spu_ram: SpuRam,
 
@@ -699,13 +850,33 @@ pub struct Spu {
     /// I guess the CPU clock was designed around the SPU?
     cpu_clock_timer: u32,
 
-    /// Output audio stereo in 44100Hz 16PCM
+    /// Output audio stereo, resampled to the output sample rate
+    // not part of the state, it belongs to the frontend once produced
+    #[serde(skip)]
     out_audio_buffer: Vec<f32>,
+    // configured by the frontend, not part of the state
+    #[serde(skip)]
+    resampler: Resampler,
+    #[serde(skip)]
+    mixer: AudioMixer,
+    #[serde(skip)]
+    recorder: Option<AudioRecorder>,
 
     in_dma_transfer: bool,
 }
 
 impl Spu {
+    /// Resets the SPU, the audio output, the mixer and the recording of the frontend are kept
+    pub fn reset(&mut self) {
+        let resampler = std::mem::take(&mut self.resampler);
+        let mixer = std::mem::take(&mut self.mixer);
+        let recorder = self.recorder.take();
+        *self = Self::default();
+        self.resampler = resampler;
+        self.mixer = mixer;
+        self.recorder = recorder;
+    }
+
     pub fn clock(&mut self, interrupt_requester: &mut impl InterruptRequester, cycles: u32) {
         self.cpu_clock_timer += cycles;
 
@@ -781,25 +952,52 @@ impl Spu {
             let cd_left = self.cdrom_audio_buffer_left.pop_front().unwrap_or(0);
             let cd_right = self.cdrom_audio_buffer_right.pop_front().unwrap_or(0);
             self.spu_ram.push_cd_capture_samples(cd_left, cd_right);
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record_cd_audio(cd_left, cd_right);
+            }
 
-            mixed_audio_left +=
-                ((cd_left as i32 * self.cd_vol_left as i32) / 0x8000).clamp(-0x8000, 0x7FFF);
-            mixed_audio_right +=
-                ((cd_right as i32 * self.cd_vol_right as i32) / 0x8000).clamp(-0x8000, 0x7FFF);
+            let mut reverb_in_left = 0;
+            let mut reverb_in_right = 0;
+
+            // the capture gets the cd audio even if it's not mixed
+            if self.control.intersects(SpuControl::CD_AUDIO_ENABLE) {
+                let cd_left =
+                    ((cd_left as i32 * self.cd_vol_left as i32) / 0x8000).clamp(-0x8000, 0x7FFF);
+                let cd_right =
+                    ((cd_right as i32 * self.cd_vol_right as i32) / 0x8000).clamp(-0x8000, 0x7FFF);
+                if self.mixer.is_audible(AudioSource::CdAudio) {
+                    mixed_audio_left += cd_left;
+                    mixed_audio_right += cd_right;
+                }
+
+                if self.control.intersects(SpuControl::CD_AUDIO_REVERB) {
+                    reverb_in_left += cd_left;
+                    reverb_in_right += cd_right;
+                }
+            }
+
+            self.noise.clock(&self.control);
+            self.main_vol_left.clock_sweep();
+            self.main_vol_right.clock_sweep();
+
+            // the output of the previous voice, for pitch modulation
+            let mut previous_output = 0;
 
             // TODO: implement correct order of handling voices (refer to above)
             for i in 0..24 {
-                let pitch_mod = self.pitch_mod_channel_flag.get(i);
+                // the first voice can't be modulated
+                let pitch_mod = i > 0 && self.pitch_mod_channel_flag.get(i);
                 let noise_mode = self.noise_channel_mode_flag.get(i);
-                let _reverb_mode = self.reverb_channel_mode_flag.get(i);
-
-                assert!(!pitch_mod);
-                assert!(!noise_mode);
-                //assert!(!_reverb_mode);
+                let reverb_mode = self.reverb_channel_mode_flag.get(i);
 
                 // handle voices
-                let (reached_endx, mono_output, left_output, right_output) =
-                    self.voices[i].clock_voice(&self.spu_ram);
+                let (reached_endx, mono_output, left_output, right_output) = self.voices[i]
+                    .clock_voice(
+                        &self.spu_ram,
+                        pitch_mod.then_some(previous_output),
+                        noise_mode.then(|| self.noise.sample()),
+                    );
+                previous_output = mono_output;
 
                 // push the voice output to the capture buffer
                 match i {
@@ -808,19 +1006,45 @@ impl Spu {
                     _ => {}
                 }
 
-                let final_left_output = (left_output * self.current_main_vol_left as i32 / 0x8000)
-                    .clamp(-0x8000, 0x7FFF);
-                mixed_audio_left += final_left_output;
-                let final_right_output = (right_output * self.current_main_vol_right as i32
-                    / 0x8000)
-                    .clamp(-0x8000, 0x7FFF);
-                mixed_audio_right += final_right_output;
+                if reverb_mode {
+                    reverb_in_left += left_output;
+                    reverb_in_right += right_output;
+                }
+
+                self.mixer.record_voice_peak(i, left_output, right_output);
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.record_voice(i, left_output, right_output);
+                }
+                if self.mixer.is_audible(AudioSource::Voice(i)) {
+                    mixed_audio_left += self.main_vol_left.apply(left_output);
+                    mixed_audio_right += self.main_vol_right.apply(right_output);
+                }
 
                 if reached_endx {
                     self.endx_flag.set(i, true);
                 }
             }
 
+            // the reverb is mixed like a voice, its output volume is the voice volume
+            let (reverb_left, reverb_right) = self.reverb.clock(
+                &mut self.spu_ram,
+                &self.reverb_config,
+                self.reverb_work_base,
+                (
+                    reverb_in_left.clamp(-0x8000, 0x7FFF),
+                    reverb_in_right.clamp(-0x8000, 0x7FFF),
+                ),
+                self.control.intersects(SpuControl::REVERB_MASTER_ENABLE),
+            );
+            let reverb_left = (reverb_left * self.reverb_out_vol_left as i16 as i32 / 0x8000)
+                .clamp(-0x8000, 0x7FFF);
+            let reverb_right = (reverb_right * self.reverb_out_vol_right as i16 as i32 / 0x8000)
+                .clamp(-0x8000, 0x7FFF);
+            if self.mixer.is_audible(AudioSource::Reverb) {
+                mixed_audio_left += self.main_vol_left.apply(reverb_left);
+                mixed_audio_right += self.main_vol_right.apply(reverb_right);
+            }
+
             let (left, right) = if self.control.intersects(SpuControl::UNMUTE_SPU) {
                 (
                     mixed_audio_left.clamp(-0x8000, 0x7FFF) as i16,
@@ -830,12 +1054,16 @@ impl Spu {
                 (0, 0)
             };
 
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record_mix(left, right);
+            }
+
             // convert i16 to f32
             let left = left as f32 / 0x8000 as f32;
             let right = right as f32 / 0x8000 as f32;
 
-            self.out_audio_buffer.push(left);
-            self.out_audio_buffer.push(right);
+            self.resampler
+                .push((left, right), &mut self.out_audio_buffer);
 
             if self
                 .control
@@ -855,6 +1083,39 @@ impl Spu {
         self.cdrom_audio_buffer_right.extend(right);
     }
 
+    pub fn mixer(&self) -> &AudioMixer {
+        &self.mixer
+    }
+
+    pub fn mixer_mut(&mut self) -> &mut AudioMixer {
+        &mut self.mixer
+    }
+
+    /// Starts recording the audio, stopping the previous recording if any
+    pub fn start_recording(&mut self, recorder: AudioRecorder) -> Option<AudioRecorder> {
+        self.recorder.replace(recorder)
+    }
+
+    pub fn stop_recording(&mut self) -> Option<AudioRecorder> {
+        self.recorder.take()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Changes the sample rate of the audio output, and how it's resampled from 44100Hz
+    pub fn set_audio_output(&mut self, sample_rate: u32, kind: AudioResampler) {
+        if self.resampler.output_sample_rate() != sample_rate || self.resampler.kind() != kind {
+            self.resampler = Resampler::new(sample_rate, kind);
+        }
+    }
+
+    /// Stretches the audio output slightly, see [`Resampler::set_rate_adjust`]
+    pub fn set_audio_rate_adjust(&mut self, rate_adjust: f64) {
+        self.resampler.set_rate_adjust(rate_adjust);
+    }
+
     pub fn take_audio_buffer(&mut self) -> Vec<f32> {
         let mut out = Vec::with_capacity(self.out_audio_buffer.len());
         out.extend_from_slice(&self.out_audio_buffer);
@@ -862,11 +1123,27 @@ impl Spu {
         out
     }
 
+    pub fn save_state<W: Write>(&self, writer: W) -> bincode::Result<()> {
+        bincode::serialize_into(writer, self)
+    }
+
+    pub fn load_state<R: Read>(&mut self, reader: R) -> bincode::Result<()> {
+        let mut state: Self = bincode::deserialize_from(reader)?;
+
+        // these belong to the frontend
+        state.resampler = std::mem::take(&mut self.resampler);
+        state.mixer = std::mem::take(&mut self.mixer);
+        state.recorder = self.recorder.take();
+        *self = state;
+
+        Ok(())
+    }
+
     pub fn print_state(&self) {
         println!("SPU State:");
         println!(
             "  Main Volume: Left: {:04X}, Right: {:04X}",
-            self.main_vol_left, self.main_vol_right
+            self.main_vol_left.register, self.main_vol_right.register
         );
         println!(
             "  Reverb Volume: Left: {:04X}, Right: {:04X}",
@@ -907,8 +1184,8 @@ impl Spu {
                 self.noise_channel_mode_flag.get(i),
                 self.reverb_channel_mode_flag.get(i),
                 self.endx_flag.get(i),
-                self.voices[i].volume_left,
-                self.voices[i].volume_right,
+                self.voices[i].volume_left.register,
+                self.voices[i].volume_right.register,
                 self.voices[i].adpcm_sample_rate,
                 self.voices[i].adpcm_start_address,
                 self.voices[i].adpcm_repeat_address,
@@ -998,28 +1275,20 @@ impl Spu {
 }
 
 impl BusLine for Spu {
+    const COMPONENT: HardwareComponent = HardwareComponent::Spu;
+
+    // 32bit accesses are split into two 16bit accesses on the real hardware,
+    // but nothing seems to use them
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         match addr {
-            0x000..=0x17F => Err(format!("SPU u32 read voice register {:03X}", addr)),
-            0x180..=0x187 => Err(format!("SPU u32 read spu control {:03X}", addr)),
-            0x188..=0x19F => Err(format!("SPU u32 read voice flags {:03X}", addr)),
-            0x1A0..=0x1BF => Err(format!("SPU u32 read spu  control {:03X}", addr)),
-            0x1C0..=0x1FF => Err(format!("SPU u32 read reverb configuration {:03X}", addr)),
-            0x200..=0x25F => Err(format!("SPU u32 read voice internal reg {:03X}", addr)),
-            0x260..=0x2FF => unreachable!("u32 read unknown {:03X}", addr),
+            0x000..=0x2FF => Err(Self::unsupported_width(addr, 32, BusAccess::Read)),
             _ => unreachable!(),
         }
     }
 
     fn write_u32(&mut self, addr: u32, _data: u32) -> Result<()> {
         match addr {
-            0x000..=0x17F => Err(format!("SPU u32 write voice register {:03X}", addr)),
-            0x180..=0x187 => Err(format!("SPU u32 write spu control {:03X}", addr)),
-            0x188..=0x19F => Err(format!("SPU u32 write voice flags {:03X}", addr)),
-            0x1A0..=0x1BF => Err(format!("SPU u32 write spu  control {:03X}", addr)),
-            0x1C0..=0x1FF => Err(format!("SPU u32 write reverb configuration {:03X}", addr)),
-            0x200..=0x25F => Err(format!("SPU u32 write voice internal reg {:03X}", addr)),
-            0x260..=0x2FF => unreachable!("u32 write unknown {:03X}", addr),
+            0x000..=0x2FF => Err(Self::unsupported_width(addr, 32, BusAccess::Write)),
             _ => unreachable!(),
         }
     }
@@ -1030,8 +1299,8 @@ impl BusLine for Spu {
                 let reg = addr & 0xF;
                 let voice_idx = (addr >> 4) as usize;
                 match reg {
-                    0x0 => self.voices[voice_idx].volume_left,
-                    0x2 => self.voices[voice_idx].volume_right,
+                    0x0 => self.voices[voice_idx].volume_left.register,
+                    0x2 => self.voices[voice_idx].volume_right.register,
                     0x4 => self.voices[voice_idx].adpcm_sample_rate,
                     0x6 => self.voices[voice_idx].adpcm_start_address,
                     0x8 => self.voices[voice_idx].adsr_config.bits() as u16,
@@ -1041,8 +1310,8 @@ impl BusLine for Spu {
                     _ => unreachable!(),
                 }
             }
-            0x180 => self.main_vol_left,
-            0x182 => self.main_vol_right,
+            0x180 => self.main_vol_left.register,
+            0x182 => self.main_vol_right.register,
             0x184 => self.reverb_out_vol_left,
             0x186 => self.reverb_out_vol_right,
             // key on and key off should be treated as write only, reading
@@ -1069,15 +1338,15 @@ impl BusLine for Spu {
             0x1B2 => self.cd_vol_right,
             0x1B4 => self.external_vol_left,
             0x1B6 => self.external_vol_right,
-            0x1B8 => self.current_main_vol_left as u16,
-            0x1BA => self.current_main_vol_right as u16,
+            0x1B8 => self.main_vol_left.current as u16,
+            0x1BA => self.main_vol_right.current as u16,
             0x1C0..=0x1FE => self.reverb_config[(addr - 0x1C0) as usize / 2],
             0x200..=0x25E => {
-                let voice_idx = ((addr >> 2) & 23) as usize;
+                let voice_idx = ((addr - 0x200) >> 2) as usize;
                 if addr & 0x2 == 0 {
-                    self.voices[voice_idx].current_vol_left as u16
+                    self.voices[voice_idx].volume_left.current as u16
                 } else {
-                    self.voices[voice_idx].current_vol_right as u16
+                    self.voices[voice_idx].volume_right.current as u16
                 }
             }
             0x1A0 | 0x1BC..=0x1BF | 0x260..=0x2FF => {
@@ -1096,20 +1365,8 @@ impl BusLine for Spu {
                 let voice_idx = (addr >> 4) as usize;
                 log::info!("voice {}, reg {:01X} = {:04X}", voice_idx, reg, data);
                 match reg {
-                    0x0 => {
-                        self.voices[voice_idx].volume_left = data;
-                        // volume mode
-                        if data & 0x8000 == 0 {
-                            self.voices[voice_idx].current_vol_left = (data * 2) as i16;
-                        }
-                    }
-                    0x2 => {
-                        self.voices[voice_idx].volume_right = data;
-                        // volume mode
-                        if data & 0x8000 == 0 {
-                            self.voices[voice_idx].current_vol_right = (data * 2) as i16;
-                        }
-                    }
+                    0x0 => self.voices[voice_idx].volume_left.write(data),
+                    0x2 => self.voices[voice_idx].volume_right.write(data),
                     0x4 => self.voices[voice_idx].adpcm_sample_rate = data,
                     0x6 => self.voices[voice_idx].adpcm_start_address = data,
                     0x8 => {
@@ -1129,19 +1386,11 @@ impl BusLine for Spu {
             }
             0x180 => {
                 log::info!("main vol left = {:04X}", data);
-                self.main_vol_left = data;
-                // volume mode
-                if data & 0x8000 == 0 {
-                    self.current_main_vol_left = (data * 2) as i16;
-                }
+                self.main_vol_left.write(data);
             }
             0x182 => {
                 log::info!("main vol right = {:04X}", data);
-                self.main_vol_right = data;
-                // volume mode
-                if data & 0x8000 == 0 {
-                    self.current_main_vol_right = (data * 2) as i16;
-                }
+                self.main_vol_right.write(data);
             }
             0x184 => {
                 log::info!("reverb vol left = {:04X}", data);
@@ -1316,11 +1565,11 @@ impl BusLine for Spu {
             }
             0x1B4 => self.external_vol_left = data,
             0x1B6 => self.external_vol_right = data,
-            0x1B8 => self.current_main_vol_left = data as i16,
-            0x1BA => self.current_main_vol_right = data as i16,
+            0x1B8 => self.main_vol_left.current = data as i16,
+            0x1BA => self.main_vol_right.current = data as i16,
             0x1C0..=0x1FE => self.reverb_config[(addr - 0x1C0) as usize / 2] = data,
             // TODO: not sure if this is writable, since its internal current vol
-            0x200..=0x25F => todo!("u16 write voice internal reg {:03X}", addr),
+            0x200..=0x25F => return Err(Self::unimplemented(addr, 16, BusAccess::Write)),
             0x1A0 | 0x1BC..=0x1BF | 0x260..=0x2FF => {
                 log::warn!(
                     "Writing value {:04X} to unknown register {:03X}, ignoring...",
@@ -1333,11 +1582,13 @@ impl BusLine for Spu {
         Ok(())
     }
 
-    fn read_u8(&mut self, _addr: u32) -> Result<u8> {
-        todo!()
+    fn read_u8(&mut self, addr: u32) -> Result<u8> {
+        // 8bit reads are done on the 16bit databus
+        let data = self.read_u16(addr & !1)?;
+        Ok((data >> ((addr & 1) * 8)) as u8)
     }
 
-    fn write_u8(&mut self, addr: u32, _data: u8) -> Result<()> {
+    fn write_u8(&mut self, addr: u32, data: u8) -> Result<()> {
         // The SPU is connected to a 16bit databus.
         // 8bit/16bit/32bit reads and 16bit/32bit writes are implemented.
         // However, 8bit writes are NOT implemented: 8bit writes to
@@ -1348,6 +1599,58 @@ impl BusLine for Spu {
         // TODO: implement this behavior of 8bit writes, we need to get access
         //       to the whole 32bit word from the CPU
 
-        todo!("spu corrupt 8bit write addr: {:03X}", addr);
+        if addr & 1 == 0 {
+            self.write_u16(addr, data as u16)?;
+            return Err(Self::unimplemented(addr, 8, BusAccess::Write));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_sweep() {
+        // linear increase, fastest rate
+        let mut volume = Volume::default();
+        volume.write(0x8000 | 0b11);
+        for _ in 0..0x2000 {
+            volume.clock_sweep();
+        }
+        assert_eq!(volume.current, 0x7FFF);
+
+        // linear decrease in the negative phase, from the fixed volume, step -5
+        let mut volume = Volume::default();
+        volume.write(0x2000);
+        assert_eq!(volume.current, 0x4000);
+        volume.write(0x8000 | 0x2000 | 0x1000 | 0b11);
+        volume.clock_sweep();
+        assert!(volume.current < 0);
+        assert_eq!(volume.current, -(0x4000 - (5 << 11)));
+        volume.clock_sweep();
+        assert_eq!(volume.current, 0);
+
+        // exponential increase, slows down above 0x6000
+        let mut volume = Volume::default();
+        volume.write(0x8000 | 0x4000);
+        let mut levels = [0; 4];
+        for level in &mut levels {
+            volume.clock_sweep();
+            *level = volume.current;
+        }
+        assert_eq!(levels, [0x3800, 0x7000, 0x7E00, 0x7FFF]);
+
+        // exponential decrease, the step is relative to the level
+        let mut volume = Volume::default();
+        volume.write(0x2000);
+        volume.write(0x8000 | 0x4000 | 0x2000);
+        let mut levels = [0; 3];
+        for level in &mut levels {
+            volume.clock_sweep();
+            *level = volume.current;
+        }
+        assert_eq!(levels, [0x2000, 0x1000, 0x800]);
     }
 }
@@ -0,0 +1,116 @@
+//! Muting and soloing of the sources mixed into the SPU output, and the peak levels
+//! of the voices.
+//!
+//! This only changes what's heard, the emulation doesn't see it: muted voices still go
+//! into the reverb and the capture buffers, so their echoes are still heard unless the
+//! reverb is muted too.
+
+/// A source of audio in the SPU output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioSource {
+    /// One of the 24 voices, `0..24`
+    Voice(usize),
+    CdAudio,
+    Reverb,
+}
+
+impl AudioSource {
+    fn bit(self) -> u32 {
+        match self {
+            AudioSource::Voice(voice) => {
+                assert!(voice < 24, "voice {} out of range", voice);
+                1 << voice
+            }
+            AudioSource::CdAudio => 1 << 24,
+            AudioSource::Reverb => 1 << 25,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct AudioMixer {
+    /// `AudioSource::bit` of the muted sources
+    muted: u32,
+    /// `AudioSource::bit` of the soloed sources, when any is soloed,
+    /// only those are heard
+    soloed: u32,
+    /// The highest absolute output of each voice since the last `take_voice_peaks`,
+    /// before the main volume
+    voice_peaks: [(u16, u16); 24],
+}
+
+impl AudioMixer {
+    pub fn set_muted(&mut self, source: AudioSource, muted: bool) {
+        if muted {
+            self.muted |= source.bit();
+        } else {
+            self.muted &= !source.bit();
+        }
+    }
+
+    pub fn set_solo(&mut self, source: AudioSource, solo: bool) {
+        if solo {
+            self.soloed |= source.bit();
+        } else {
+            self.soloed &= !source.bit();
+        }
+    }
+
+    pub fn is_muted(&self, source: AudioSource) -> bool {
+        self.muted & source.bit() != 0
+    }
+
+    pub fn is_solo(&self, source: AudioSource) -> bool {
+        self.soloed & source.bit() != 0
+    }
+
+    /// Should `source` be in the output
+    pub fn is_audible(&self, source: AudioSource) -> bool {
+        let bit = source.bit();
+        self.muted & bit == 0 && (self.soloed == 0 || self.soloed & bit != 0)
+    }
+
+    pub(super) fn record_voice_peak(&mut self, voice: usize, left: i32, right: i32) {
+        let peak = &mut self.voice_peaks[voice];
+        peak.0 = peak.0.max(left.unsigned_abs().min(0x8000) as u16);
+        peak.1 = peak.1.max(right.unsigned_abs().min(0x8000) as u16);
+    }
+
+    /// The peak levels of the voices since the last call, left and right in `0.0..=1.0`,
+    /// muted voices are measured as well
+    pub fn take_voice_peaks(&mut self) -> [(f32, f32); 24] {
+        std::mem::take(&mut self.voice_peaks)
+            .map(|(left, right)| (left as f32 / 0x8000 as f32, right as f32 / 0x8000 as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mute_and_solo() {
+        let mut mixer = AudioMixer::default();
+        mixer.set_muted(AudioSource::Voice(3), true);
+        assert!(!mixer.is_audible(AudioSource::Voice(3)));
+        assert!(mixer.is_audible(AudioSource::Voice(4)));
+        assert!(mixer.is_audible(AudioSource::CdAudio));
+
+        // soloing silences everything else, muted sources stay muted
+        mixer.set_solo(AudioSource::Voice(3), true);
+        mixer.set_solo(AudioSource::Reverb, true);
+        assert!(!mixer.is_audible(AudioSource::Voice(3)));
+        assert!(!mixer.is_audible(AudioSource::Voice(4)));
+        assert!(!mixer.is_audible(AudioSource::CdAudio));
+        assert!(mixer.is_audible(AudioSource::Reverb));
+
+        mixer.set_muted(AudioSource::Voice(3), false);
+        assert!(mixer.is_audible(AudioSource::Voice(3)));
+
+        mixer.record_voice_peak(3, -0x4000, 0x100);
+        mixer.record_voice_peak(3, 0x2000, -0x8000);
+        let peaks = mixer.take_voice_peaks();
+        assert_eq!(peaks[3], (0.5, 1.0));
+        assert_eq!(mixer.take_voice_peaks()[3], (0.0, 0.0));
+    }
+}
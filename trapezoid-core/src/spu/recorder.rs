@@ -0,0 +1,250 @@
+//! Recording of the SPU audio into WAV files, 16bit stereo at 44100Hz, before it's
+//! resampled for the frontend.
+//!
+//! Next to the mixed output, each voice and the CD audio can be recorded into their
+//! own files (stems), named after the output file, `song.wav` records the stems into
+//! `song-voice00.wav` to `song-voice23.wav`, and `song-cd.wav`.
+//!
+//! The sizes in the headers are updated every second of audio, so the files stay
+//! playable even if the process is killed before the recording is stopped.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use super::SPU_SAMPLE_RATE;
+use crate::PsxError;
+
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+const BLOCK_ALIGN: u16 = CHANNELS * BITS_PER_SAMPLE / 8;
+/// Size of the header before the samples
+const HEADER_SIZE: u32 = 44;
+/// The sizes in the header are 32bit, the RIFF size counts the header after it
+const MAX_SAMPLES: u32 = (u32::MAX - (HEADER_SIZE - 8)) / BLOCK_ALIGN as u32;
+/// How often the sizes in the header are updated, one second
+const HEADER_UPDATE_SAMPLES: u32 = SPU_SAMPLE_RATE;
+
+/// A 16bit stereo PCM WAV file, the sizes in the header are filled in `update_header`
+struct WavWriter<W: Write + Seek> {
+    writer: W,
+    /// Number of stereo samples written
+    samples: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(b"RIFF")?;
+        // file size after this field, written in `update_header`
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_u32::<LittleEndian>(16)?;
+        // PCM
+        writer.write_u16::<LittleEndian>(1)?;
+        writer.write_u16::<LittleEndian>(CHANNELS)?;
+        writer.write_u32::<LittleEndian>(SPU_SAMPLE_RATE)?;
+        writer.write_u32::<LittleEndian>(SPU_SAMPLE_RATE * BLOCK_ALIGN as u32)?;
+        writer.write_u16::<LittleEndian>(BLOCK_ALIGN)?;
+        writer.write_u16::<LittleEndian>(BITS_PER_SAMPLE)?;
+
+        writer.write_all(b"data")?;
+        // data size, written in `update_header`
+        writer.write_u32::<LittleEndian>(0)?;
+
+        Ok(Self { writer, samples: 0 })
+    }
+
+    fn write(&mut self, left: i16, right: i16) -> io::Result<()> {
+        if self.samples == MAX_SAMPLES {
+            return Err(io::Error::other("reached the 4GB limit of WAV files"));
+        }
+        self.writer.write_i16::<LittleEndian>(left)?;
+        self.writer.write_i16::<LittleEndian>(right)?;
+        self.samples += 1;
+        if self.samples.is_multiple_of(HEADER_UPDATE_SAMPLES) {
+            self.update_header()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the sizes of the samples written so far, and flushes
+    fn update_header(&mut self) -> io::Result<()> {
+        let data_size = self.samples * BLOCK_ALIGN as u32;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_u32::<LittleEndian>(HEADER_SIZE - 8 + data_size)?;
+        self.writer.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.writer.write_u32::<LittleEndian>(data_size)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+struct WavFile {
+    path: PathBuf,
+    wav: WavWriter<BufWriter<File>>,
+}
+
+impl WavFile {
+    fn create(path: PathBuf) -> Result<Self, PsxError> {
+        let wav = File::create(&path).and_then(|file| WavWriter::new(BufWriter::new(file)));
+        match wav {
+            Ok(wav) => Ok(Self { path, wav }),
+            Err(source) => Err(PsxError::CouldNotWriteAudioRecording { path, source }),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), PsxError> {
+        self.wav
+            .update_header()
+            .map_err(|source| PsxError::CouldNotWriteAudioRecording {
+                path: self.path.clone(),
+                source,
+            })
+    }
+}
+
+/// The path of a stem file, `name` is added to the file name of the output `path`
+fn stem_path(path: &Path, name: &str) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_owned();
+    file_name.push(format!("-{}.wav", name));
+    path.with_file_name(file_name)
+}
+
+pub struct AudioRecorder {
+    mix: WavFile,
+    /// The 24 voices after their volume, and the CD audio before the CD volume
+    stems: Option<(Vec<WavFile>, WavFile)>,
+    /// The first write error, nothing is written after it,
+    /// and it's returned from `finish`
+    error: Option<PsxError>,
+    finished: bool,
+}
+
+impl AudioRecorder {
+    pub fn create(path: &Path, stems: bool) -> Result<Self, PsxError> {
+        let mix = WavFile::create(path.to_owned())?;
+        let stems = if stems {
+            let voices = (0..24)
+                .map(|i| WavFile::create(stem_path(path, &format!("voice{:02}", i))))
+                .collect::<Result<Vec<_>, _>>()?;
+            let cd_audio = WavFile::create(stem_path(path, "cd"))?;
+            Some((voices, cd_audio))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            mix,
+            stems,
+            error: None,
+            finished: false,
+        })
+    }
+
+    fn write(error: &mut Option<PsxError>, file: &mut WavFile, left: i32, right: i32) {
+        if error.is_some() {
+            return;
+        }
+        let left = left.clamp(-0x8000, 0x7FFF) as i16;
+        let right = right.clamp(-0x8000, 0x7FFF) as i16;
+        if let Err(source) = file.wav.write(left, right) {
+            log::error!("spu: audio recording stopped: {}", source);
+            *error = Some(PsxError::CouldNotWriteAudioRecording {
+                path: file.path.clone(),
+                source,
+            });
+        }
+    }
+
+    pub fn record_mix(&mut self, left: i16, right: i16) {
+        Self::write(&mut self.error, &mut self.mix, left as i32, right as i32);
+    }
+
+    pub fn record_voice(&mut self, voice: usize, left: i32, right: i32) {
+        if let Some((voices, _)) = &mut self.stems {
+            Self::write(&mut self.error, &mut voices[voice], left, right);
+        }
+    }
+
+    pub fn record_cd_audio(&mut self, left: i16, right: i16) {
+        if let Some((_, cd_audio)) = &mut self.stems {
+            Self::write(&mut self.error, cd_audio, left as i32, right as i32);
+        }
+    }
+
+    fn finish_files(&mut self) -> Result<(), PsxError> {
+        let mut result = self.error.take().map_or(Ok(()), Err);
+
+        let stems = self
+            .stems
+            .iter_mut()
+            .flat_map(|(voices, cd_audio)| voices.iter_mut().chain(std::iter::once(cd_audio)));
+        for file in std::iter::once(&mut self.mix).chain(stems) {
+            let finished = file.finish();
+            if result.is_ok() {
+                result = finished;
+            }
+        }
+        self.finished = true;
+        result
+    }
+
+    /// Finishes the headers of all the files, returns the first error
+    /// that happened while recording if any
+    pub fn finish(mut self) -> Result<(), PsxError> {
+        self.finish_files()
+    }
+}
+
+impl Drop for AudioRecorder {
+    /// Finishes the files if the recording wasn't stopped, like when the emulator is dropped
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Err(e) = self.finish_files() {
+                log::error!("spu: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn wav_header() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new())).unwrap();
+        wav.write(0x1234, -2).unwrap();
+        wav.write(0, 1).unwrap();
+        wav.update_header().unwrap();
+        let data = wav.writer.into_inner();
+
+        assert_eq!(data.len(), HEADER_SIZE as usize + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(data[4..8], (HEADER_SIZE - 8 + 8).to_le_bytes());
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(data[24..28], 44100u32.to_le_bytes());
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(data[40..44], 8u32.to_le_bytes());
+        assert_eq!(data[44..], [0x34, 0x12, 0xFE, 0xFF, 0, 0, 1, 0]);
+
+        // the sizes would overflow after this
+        let mut wav = WavWriter::new(Cursor::new(Vec::new())).unwrap();
+        wav.samples = MAX_SAMPLES;
+        assert!(wav.write(0, 0).is_err());
+
+        assert_eq!(
+            stem_path(Path::new("/rips/song.wav"), "voice03"),
+            PathBuf::from("/rips/song-voice03.wav")
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Resampling of the 44.1kHz SPU output to the sample rate requested by the frontend.
+//!
+//! The ratio can also be stretched slightly with a rate adjustment, so the frontend can
+//! keep the buffer of the host audio device from running dry or overflowing, when the
+//! emulation is paced to the display and not to the audio clock.
+
+use std::{collections::VecDeque, f64::consts::PI};
+
+/// The sample rate the SPU runs at
+pub const SPU_SAMPLE_RATE: u32 = 44100;
+
+/// Zero crossings of the sinc kernel on each side of the output sample
+const SINC_ZERO_CROSSINGS: usize = 16;
+/// The kernel is precomputed for this many positions between two input samples
+const SINC_PHASES: usize = 256;
+/// Largest change of the ratio from the rate adjustment, enough to cover the difference
+/// between the PSX frame rate (~59.29Hz) and the display (59.94Hz), with room to spare
+const MAX_RATE_ADJUST: f64 = 0.05;
+
+/// The interpolation used to resample the audio output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AudioResampler {
+    /// Linear interpolation between two samples, cheap, but muffles
+    /// the highs and aliases a bit
+    #[default]
+    Linear,
+    /// Windowed sinc interpolation, cleaner, but more expensive
+    Sinc,
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The windowed sinc coefficients for all the phases, `SINC_PHASES + 1` rows
+/// of `2 * SINC_ZERO_CROSSINGS` taps, so the last phase can be interpolated with
+/// the next input sample.
+fn sinc_table(cutoff: f64) -> Vec<f32> {
+    let taps = SINC_ZERO_CROSSINGS * 2;
+    let mut table = Vec::with_capacity((SINC_PHASES + 1) * taps);
+    for phase in 0..=SINC_PHASES {
+        let fraction = phase as f64 / SINC_PHASES as f64;
+        let row = (0..taps).map(|tap| {
+            let distance = tap as f64 - (SINC_ZERO_CROSSINGS - 1) as f64 - fraction;
+            // Lanczos window
+            let window = sinc(distance / SINC_ZERO_CROSSINGS as f64);
+            cutoff * sinc(cutoff * distance) * window
+        });
+        let row = row.collect::<Vec<_>>();
+        // normalize, so that a constant input doesn't ripple
+        let sum = row.iter().sum::<f64>();
+        table.extend(row.into_iter().map(|c| (c / sum) as f32));
+    }
+    table
+}
+
+pub struct Resampler {
+    kind: AudioResampler,
+    output_sample_rate: u32,
+    rate_adjust: f64,
+    /// Input samples advanced for each output sample
+    step: f64,
+    /// Position of the next output sample after the center of `history`
+    position: f64,
+    /// The last input samples, output samples are between the two in the middle
+    history: VecDeque<(f32, f32)>,
+    sinc_table: Vec<f32>,
+}
+
+impl Default for Resampler {
+    fn default() -> Self {
+        Self::new(SPU_SAMPLE_RATE, AudioResampler::default())
+    }
+}
+
+impl Resampler {
+    pub fn new(output_sample_rate: u32, kind: AudioResampler) -> Self {
+        assert!(output_sample_rate > 0);
+
+        let history_len = match kind {
+            AudioResampler::Linear => 2,
+            AudioResampler::Sinc => SINC_ZERO_CROSSINGS * 2,
+        };
+        let sinc_table = match kind {
+            AudioResampler::Linear => Vec::new(),
+            // when going down, filter out what doesn't fit in the output
+            AudioResampler::Sinc => {
+                sinc_table((output_sample_rate as f64 / SPU_SAMPLE_RATE as f64).min(1.0))
+            }
+        };
+
+        let mut s = Self {
+            kind,
+            output_sample_rate,
+            rate_adjust: 1.0,
+            step: 1.0,
+            position: 0.0,
+            history: VecDeque::from(vec![(0.0, 0.0); history_len]),
+            sinc_table,
+        };
+        s.update_step();
+        s
+    }
+
+    pub fn kind(&self) -> AudioResampler {
+        self.kind
+    }
+
+    pub fn output_sample_rate(&self) -> u32 {
+        self.output_sample_rate
+    }
+
+    /// Above 1.0 produces more samples than the output sample rate, and below 1.0 less,
+    /// it's clamped to be within 5% of 1.0
+    pub fn set_rate_adjust(&mut self, rate_adjust: f64) {
+        self.rate_adjust = rate_adjust.clamp(1.0 - MAX_RATE_ADJUST, 1.0 + MAX_RATE_ADJUST);
+        self.update_step();
+    }
+
+    fn update_step(&mut self) {
+        self.step = SPU_SAMPLE_RATE as f64 / (self.output_sample_rate as f64 * self.rate_adjust);
+    }
+
+    /// Takes one input stereo sample, and pushes the output samples interleaved into `out`
+    pub fn push(&mut self, sample: (f32, f32), out: &mut Vec<f32>) {
+        self.history.pop_front();
+        self.history.push_back(sample);
+
+        while self.position < 1.0 {
+            let (left, right) = match self.kind {
+                AudioResampler::Linear => self.linear(),
+                AudioResampler::Sinc => self.sinc(),
+            };
+            out.push(left);
+            out.push(right);
+            self.position += self.step;
+        }
+        self.position -= 1.0;
+    }
+
+    fn linear(&self) -> (f32, f32) {
+        let (l0, r0) = self.history[0];
+        let (l1, r1) = self.history[1];
+        let t = self.position as f32;
+        (l0 + (l1 - l0) * t, r0 + (r1 - r0) * t)
+    }
+
+    fn sinc(&self) -> (f32, f32) {
+        let taps = SINC_ZERO_CROSSINGS * 2;
+        let phase = self.position * SINC_PHASES as f64;
+        let index = phase as usize;
+        let t = (phase - index as f64) as f32;
+        let row_a = &self.sinc_table[index * taps..][..taps];
+        let row_b = &self.sinc_table[(index + 1) * taps..][..taps];
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for ((&(l, r), &a), &b) in self.history.iter().zip(row_a).zip(row_b) {
+            let coefficient = a + (b - a) * t;
+            left += l * coefficient;
+            right += r * coefficient;
+        }
+        (left, right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resample(resampler: &mut Resampler, samples: usize) -> Vec<f32> {
+        let mut out = Vec::new();
+        for i in 0..samples {
+            // 1kHz sine
+            let sample = (i as f64 * 2.0 * PI * 1000.0 / SPU_SAMPLE_RATE as f64).sin() as f32;
+            resampler.push((sample, -sample), &mut out);
+        }
+        out
+    }
+
+    #[test]
+    fn sample_rates_and_rate_adjust() {
+        for kind in [AudioResampler::Linear, AudioResampler::Sinc] {
+            let mut resampler = Resampler::new(48000, kind);
+            let out = resample(&mut resampler, 44100);
+            assert!(out.len().abs_diff(48000 * 2) <= 2);
+            // the sine is kept, without going out of range
+            let peak = out.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+            assert!(peak > 0.95 && peak < 1.01, "{:?}: {}", kind, peak);
+
+            resampler.set_rate_adjust(1.1);
+            let out = resample(&mut resampler, 44100);
+            // limited to 5%
+            assert!(out.len().abs_diff(50400 * 2) <= 2);
+        }
+
+        // same rate is a copy, one sample late
+        let mut resampler = Resampler::default();
+        let mut out = Vec::new();
+        for sample in [0.25, 0.5, -0.5] {
+            resampler.push((sample, sample), &mut out);
+        }
+        assert_eq!(out, [0.0, 0.0, 0.25, 0.25, 0.5, 0.5]);
+    }
+}
@@ -0,0 +1,245 @@
+//! The reverb unit, which mixes delayed echoes of its input back into the output.
+//!
+//! It runs at half the sample rate (22.05kHz) on a ring buffer in the SPU RAM, starting
+//! at the reverb work base up to the end of the RAM. The delays and volumes come from
+//! the 32 reverb registers, written by games as presets.
+
+use serde::{Deserialize, Serialize};
+
+use super::SpuRam;
+
+// indices of the reverb registers (0x1F801DC0 and up) in `reverb_config`,
+// `d`/`m` are addresses in 8 bytes units, `v` are volumes
+const D_APF1: usize = 0x00;
+const D_APF2: usize = 0x01;
+const V_IIR: usize = 0x02;
+const V_COMB1: usize = 0x03;
+const V_COMB2: usize = 0x04;
+const V_COMB3: usize = 0x05;
+const V_COMB4: usize = 0x06;
+const V_WALL: usize = 0x07;
+const V_APF1: usize = 0x08;
+const V_APF2: usize = 0x09;
+const M_LSAME: usize = 0x0A;
+const M_RSAME: usize = 0x0B;
+const M_LCOMB1: usize = 0x0C;
+const M_RCOMB1: usize = 0x0D;
+const M_LCOMB2: usize = 0x0E;
+const M_RCOMB2: usize = 0x0F;
+const D_LSAME: usize = 0x10;
+const D_RSAME: usize = 0x11;
+const M_LDIFF: usize = 0x12;
+const M_RDIFF: usize = 0x13;
+const M_LCOMB3: usize = 0x14;
+const M_RCOMB3: usize = 0x15;
+const M_LCOMB4: usize = 0x16;
+const M_RCOMB4: usize = 0x17;
+const D_LDIFF: usize = 0x18;
+const D_RDIFF: usize = 0x19;
+const M_LAPF1: usize = 0x1A;
+const M_RAPF1: usize = 0x1B;
+const M_LAPF2: usize = 0x1C;
+const M_RAPF2: usize = 0x1D;
+const V_LIN: usize = 0x1E;
+const V_RIN: usize = 0x1F;
+
+/// Size of the SPU RAM in halfwords
+const RAM_SIZE: usize = 0x40000;
+
+/// Applies a signed 16bit volume
+fn volume(sample: i32, volume: i32) -> i32 {
+    (sample * volume) >> 15
+}
+
+fn saturate(sample: i32) -> i32 {
+    sample.clamp(-0x8000, 0x7FFF)
+}
+
+/// The registers and the work area of one processing step
+struct Step<'a> {
+    ram: &'a mut SpuRam,
+    config: &'a [u16; 0x20],
+    /// The start of the work area, in halfwords
+    base: usize,
+    /// The current address in the work area, in halfwords
+    current: usize,
+    write_enabled: bool,
+}
+
+impl Step<'_> {
+    fn volume(&self, register: usize) -> i32 {
+        self.config[register] as i16 as i32
+    }
+
+    /// The address of a register, in halfwords
+    fn offset(&self, register: usize) -> isize {
+        self.config[register] as isize * 4
+    }
+
+    /// Addresses wrap around inside the work area
+    fn address(&self, offset: isize) -> usize {
+        let size = (RAM_SIZE - self.base) as isize;
+        let relative = (self.current - self.base) as isize + offset;
+        self.base + relative.rem_euclid(size) as usize
+    }
+
+    fn read(&self, offset: isize) -> i32 {
+        self.ram[self.address(offset)] as i16 as i32
+    }
+
+    fn write(&mut self, offset: isize, sample: i32) {
+        if self.write_enabled {
+            let address = self.address(offset);
+            self.ram[address] = saturate(sample) as i16 as u16;
+        }
+    }
+
+    /// Reflection from the `delay` register into the `destination` register,
+    /// filtered with the previous sample of the destination
+    fn reflection(&mut self, input: i32, destination: usize, delay: usize) {
+        let destination = self.offset(destination);
+        let previous = self.read(destination - 1);
+        let reflected = volume(self.read(self.offset(delay)), self.volume(V_WALL));
+        let sample = volume(saturate(input + reflected - previous), self.volume(V_IIR)) + previous;
+        self.write(destination, sample);
+    }
+
+    fn comb(&self, combs: [usize; 4]) -> i32 {
+        [V_COMB1, V_COMB2, V_COMB3, V_COMB4]
+            .into_iter()
+            .zip(combs)
+            .map(|(v, m)| volume(self.read(self.offset(m)), self.volume(v)))
+            .sum()
+    }
+
+    fn all_pass(&mut self, input: i32, destination: usize, delay: usize, v: usize) -> i32 {
+        let destination = self.offset(destination);
+        let delayed = self.read(destination - self.offset(delay));
+        let out = saturate(input - volume(delayed, self.volume(v)));
+        self.write(destination, out);
+        saturate(volume(out, self.volume(v)) + delayed)
+    }
+
+    fn process(&mut self, left: i32, right: i32) -> (i32, i32) {
+        let left = volume(left, self.volume(V_LIN));
+        let right = volume(right, self.volume(V_RIN));
+
+        // same side reflection
+        self.reflection(left, M_LSAME, D_LSAME);
+        self.reflection(right, M_RSAME, D_RSAME);
+        // different side reflection
+        self.reflection(left, M_LDIFF, D_RDIFF);
+        self.reflection(right, M_RDIFF, D_LDIFF);
+
+        // early echo
+        let left = saturate(self.comb([M_LCOMB1, M_LCOMB2, M_LCOMB3, M_LCOMB4]));
+        let right = saturate(self.comb([M_RCOMB1, M_RCOMB2, M_RCOMB3, M_RCOMB4]));
+
+        // late reverb
+        let left = self.all_pass(left, M_LAPF1, D_APF1, V_APF1);
+        let right = self.all_pass(right, M_RAPF1, D_APF1, V_APF1);
+        let left = self.all_pass(left, M_LAPF2, D_APF2, V_APF2);
+        let right = self.all_pass(right, M_RAPF2, D_APF2, V_APF2);
+
+        (left, right)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Reverb {
+    /// The current address in the work area, in halfwords
+    current_address: usize,
+    /// Only every other sample is processed, the input is averaged over both
+    odd_sample: bool,
+    previous_input: (i32, i32),
+    /// The last two outputs, interpolated for the samples in between
+    previous_output: (i32, i32),
+    output: (i32, i32),
+}
+
+impl Reverb {
+    /// Takes the input of one sample, and returns the output of the reverb, before its
+    /// output volume. The work area is only written to if `write_enabled`.
+    pub fn clock(
+        &mut self,
+        ram: &mut SpuRam,
+        config: &[u16; 0x20],
+        work_base: u16,
+        input: (i32, i32),
+        write_enabled: bool,
+    ) -> (i32, i32) {
+        self.odd_sample = !self.odd_sample;
+        if self.odd_sample {
+            self.previous_input = input;
+            return (
+                (self.previous_output.0 + self.output.0) / 2,
+                (self.previous_output.1 + self.output.1) / 2,
+            );
+        }
+
+        let base = work_base as usize * 4;
+        let mut step = Step {
+            ram,
+            config,
+            base,
+            current: self.current_address.max(base),
+            write_enabled,
+        };
+        let output = step.process(
+            (self.previous_input.0 + input.0) / 2,
+            (self.previous_input.1 + input.1) / 2,
+        );
+
+        self.current_address = ((step.current + 1) & (RAM_SIZE - 1)).max(base);
+        self.previous_output = self.output;
+        self.output = output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_and_irq() {
+        let mut ram = SpuRam::default();
+        // a same side reflection without feedback, read back by the first comb,
+        // then delayed by the all-pass filters without their feedback
+        let mut config = [0; 0x20];
+        config[V_LIN] = 0x7FFF;
+        config[V_IIR] = 0x7FFF;
+        config[V_COMB1] = 0x7FFF;
+        config[M_LSAME] = 2;
+        config[M_LCOMB1] = 0;
+        // the other buffers out of the way
+        for (i, m) in [
+            M_RSAME, M_LDIFF, M_RDIFF, M_LAPF1, M_RAPF1, M_LAPF2, M_RAPF2,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            config[m] = 0x10 * (i as u16 + 1);
+        }
+        config[D_APF1] = 1;
+        config[D_APF2] = 1;
+        let work_base = 0xFF00;
+        ram.irq_address = work_base as usize * 4 + 8;
+
+        let mut reverb = Reverb::default();
+        let mut outputs = Vec::new();
+        for _ in 0..80 {
+            outputs.push(
+                reverb
+                    .clock(&mut ram, &config, work_base, (0x4000, 0), true)
+                    .0,
+            );
+        }
+
+        // the input is written 8 halfwords ahead, then each all-pass filter delays it by 4,
+        // the output comes back 16 processed samples (32 samples) later
+        assert!(outputs[..33].iter().all(|&sample| sample == 0));
+        assert!(outputs[36..].iter().all(|&sample| sample > 0x3F00));
+        assert!(ram.irq_flag.get());
+    }
+}
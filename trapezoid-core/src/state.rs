@@ -0,0 +1,77 @@
+//! Common pieces for saving and loading the emulator state.
+//!
+//! A save state is the `SAVE_STATE_MAGIC` and `SAVE_STATE_VERSION` header followed by
+//! each component serialized with `bincode` one after the other, see [`crate::Psx::save_state`].
+
+/// The first bytes of any save state, used to reject random files early.
+pub(crate) const SAVE_STATE_MAGIC: &[u8; 8] = b"TRPZSTAT";
+
+/// Must be incremented whenever the layout of any of the saved components changes,
+/// since `bincode` doesn't store field names, loading an older state into the new
+/// layout will produce garbage instead of an error.
+pub(crate) const SAVE_STATE_VERSION: u32 = 9;
+
+/// `serde` doesn't support big arrays, and `serde-big-array` builds the array on the
+/// stack before boxing it, which is not great for things like the SPU RAM.
+///
+/// This (de)serializes `Box<[T; N]>` through a slice/`Vec` instead.
+pub(crate) mod boxed_array {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    #[allow(clippy::borrowed_box)]
+    pub fn serialize<S, T, const N: usize>(
+        data: &Box<[T; N]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        data[..].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D, T, const N: usize>(deserializer: D) -> Result<Box<[T; N]>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        let data = Vec::<T>::deserialize(deserializer)?;
+        let len = data.len();
+
+        data.into_boxed_slice()
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &format!("an array of {N}").as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct Boxed<const N: usize> {
+        #[serde(with = "super::boxed_array")]
+        data: Box<[u16; N]>,
+    }
+
+    #[test]
+    fn boxed_array_roundtrip() {
+        let mut data = Box::new([0; 0x1000]);
+        data.iter_mut().enumerate().for_each(|(i, d)| *d = i as u16);
+
+        let encoded = bincode::serialize(&Boxed { data }).unwrap();
+        let decoded: Boxed<0x1000> = bincode::deserialize(&encoded).unwrap();
+
+        assert!(decoded.data.iter().enumerate().all(|(i, &d)| d == i as u16));
+    }
+
+    #[test]
+    fn boxed_array_wrong_size() {
+        let encoded = bincode::serialize(&Boxed {
+            data: Box::new([0; 0x10]),
+        })
+        .unwrap();
+
+        assert!(bincode::deserialize::<Boxed<0x20>>(&encoded).is_err());
+    }
+}
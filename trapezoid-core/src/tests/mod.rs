@@ -1,3 +1,5 @@
+mod save_state;
+
 #[test]
 fn test() {
     assert_eq!(1 + 1, 2)
@@ -0,0 +1,142 @@
+//! Save states of the components and of the whole emulator, using a BIOS of zeros
+//! that is never run.
+
+use crate::cdrom::Cdrom;
+use crate::memory::{Bios, BusLine, CpuBus};
+use crate::renderer::SoftwareRenderer;
+use crate::spu::Spu;
+use crate::{state, AudioResampler, Psx, PsxConfig, PsxError, SPU_SAMPLE_RATE};
+
+const CONFIG: PsxConfig = PsxConfig {
+    stdout_debug: false,
+    fast_boot: false,
+    fast_disc_access: false,
+    audio_sample_rate: SPU_SAMPLE_RATE,
+    audio_resampler: AudioResampler::Linear,
+};
+
+fn new_bus() -> CpuBus {
+    CpuBus::new(Bios::zeroed(), None::<&str>, CONFIG, SoftwareRenderer::new).unwrap()
+}
+
+fn new_psx() -> Psx {
+    Psx::with_bios(Bios::zeroed(), None::<&str>, CONFIG, SoftwareRenderer::new).unwrap()
+}
+
+/// Saving, loading what was saved into `loaded`, then saving again gives the same bytes
+fn assert_round_trip<T>(
+    saved: &T,
+    loaded: &mut T,
+    save: impl Fn(&T, &mut Vec<u8>) -> bincode::Result<()>,
+    load: impl Fn(&mut T, &[u8]) -> bincode::Result<()>,
+) {
+    let mut data = Vec::new();
+    save(saved, &mut data).unwrap();
+
+    let mut fresh = Vec::new();
+    save(loaded, &mut fresh).unwrap();
+    assert_ne!(
+        data, fresh,
+        "the state to save should not be the initial one"
+    );
+
+    load(loaded, &data).unwrap();
+    let mut data_again = Vec::new();
+    save(loaded, &mut data_again).unwrap();
+    assert_eq!(data, data_again);
+}
+
+#[test]
+fn spu_round_trip() {
+    let mut spu = Spu::default();
+    // SPUCNT enable, voice 0 volume and pitch, data transfer address and fifo
+    spu.write_u16(0x1AA, 0xC000).unwrap();
+    spu.write_u16(0x000, 0x3FFF).unwrap();
+    spu.write_u16(0x004, 0x1000).unwrap();
+    spu.write_u16(0x1A6, 0x0200).unwrap();
+    spu.write_u16(0x1A8, 0x1234).unwrap();
+
+    assert_round_trip(
+        &spu,
+        &mut Spu::default(),
+        |spu, data| spu.save_state(data),
+        |spu, data| spu.load_state(data),
+    );
+}
+
+#[test]
+fn cdrom_round_trip() {
+    let mut cdrom = Cdrom::default();
+    // two parameters, then the interrupt enable
+    cdrom.write_u8(0, 0).unwrap();
+    cdrom.write_u8(2, 0x12).unwrap();
+    cdrom.write_u8(2, 0x34).unwrap();
+    cdrom.write_u8(0, 1).unwrap();
+    cdrom.write_u8(2, 0x1F).unwrap();
+
+    assert_round_trip(
+        &cdrom,
+        &mut Cdrom::default(),
+        |cdrom, data| cdrom.save_state(data),
+        |cdrom, data| cdrom.load_state(data),
+    );
+}
+
+#[test]
+fn bus_round_trip() {
+    let mut bus = new_bus();
+    // main RAM, scratchpad, interrupt mask, timer 0 target, SPU main volume
+    bus.write_u32(0x8000_1000, 0x1234_5678).unwrap();
+    bus.write_u32(0x1F80_0010, 0x9ABC_DEF0).unwrap();
+    bus.write_u32(0x1F80_1074, 0x0000_0001).unwrap();
+    bus.write_u16(0x1F80_1108, 0x0100).unwrap();
+    bus.write_u16(0x1F80_1D80, 0x3FFF).unwrap();
+
+    assert_round_trip(
+        &bus,
+        &mut new_bus(),
+        |bus, data| bus.save_state(data),
+        |bus, data| bus.load_state(data),
+    );
+}
+
+#[test]
+fn version_mismatch() {
+    let mut psx = new_psx();
+    let mut data = psx.save_state();
+
+    let version_offset = state::SAVE_STATE_MAGIC.len();
+    let older = state::SAVE_STATE_VERSION - 1;
+    data[version_offset..version_offset + 4].copy_from_slice(&older.to_le_bytes());
+
+    match psx.load_state(&data) {
+        Err(PsxError::SaveStateVersionMismatch { expected, found }) => {
+            assert_eq!(expected, state::SAVE_STATE_VERSION);
+            assert_eq!(found, older);
+        }
+        r => panic!("expected a version mismatch, got {:?}", r),
+    }
+
+    assert!(matches!(
+        psx.load_state(b"not a state"),
+        Err(PsxError::InvalidSaveState(_))
+    ));
+}
+
+#[test]
+fn truncated_state_keeps_current_state() {
+    let mut psx = new_psx();
+    let old = psx.save_state();
+
+    psx.bus.write_u32(0x8000_1000, 0x1234_5678).unwrap();
+    let current = psx.save_state();
+    assert_ne!(old, current);
+
+    // the CPU and part of the bus are loaded before failing
+    let truncated = &old[..old.len() / 2];
+    assert!(matches!(
+        psx.load_state(truncated),
+        Err(PsxError::InvalidSaveState(_))
+    ));
+    assert_eq!(psx.save_state(), current);
+}
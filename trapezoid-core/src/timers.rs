@@ -1,8 +1,11 @@
 use crate::memory::{interrupts::InterruptRequester, BusLine, Result};
+use crate::HardwareComponent;
 use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
 
 bitflags! {
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Serialize, Deserialize)]
+    #[serde(transparent)]
     struct CounterMode: u16 {
         const SYNC_ENABLE        = 0b0000000000000001;
         const SYNC_MODE          = 0b0000000000000110;
@@ -78,7 +81,7 @@ impl CounterMode {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 struct TimerBase {
     mode: CounterMode,
     counter: u16,
@@ -210,7 +213,7 @@ impl TimerBase {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 struct Timer0 {
     base: TimerBase,
 }
@@ -252,7 +255,7 @@ impl Timer0 {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 struct Timer1 {
     base: TimerBase,
 }
@@ -294,7 +297,7 @@ impl Timer1 {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 struct Timer2 {
     base: TimerBase,
     divider_counter: u32,
@@ -342,7 +345,7 @@ impl Timer2 {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct Timers {
     timer0: Timer0,
     timer1: Timer1,
@@ -409,6 +412,8 @@ impl Timers {
 }
 
 impl BusLine for Timers {
+    const COMPONENT: HardwareComponent = HardwareComponent::Timers;
+
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         let timer_index = (addr >> 4) & 0x3;
         let reg_index = (addr & 0xF) / 4;
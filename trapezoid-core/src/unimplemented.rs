@@ -0,0 +1,39 @@
+//! Reporting of hardware features that are not emulated.
+//!
+//! Instead of crashing, the components log the event with [`report`] into their
+//! `pending_unimplemented`, and continue with a best-effort stub. Bus accesses return
+//! [`crate::BusErrorKind::Unimplemented`] instead.
+//!
+//! [`crate::memory::CpuBus`] drains the components when clocking them, and keeps the
+//! first report, which [`crate::Psx`] returns as
+//! [`crate::cpu::CpuState::UnimplementedHardware`].
+
+use crate::HardwareComponent;
+
+pub(crate) type Report = (HardwareComponent, String);
+
+/// Logs the unimplemented feature, only the first report is kept in `pending`,
+/// as later ones are most likely caused by it.
+pub(crate) fn report(pending: &mut Option<Report>, component: HardwareComponent, detail: String) {
+    log::error!("[{}] unimplemented: {}", component, detail);
+    pending.get_or_insert((component, detail));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_first_report() {
+        let mut pending = None;
+        report(&mut pending, HardwareComponent::Spu, "first".to_string());
+        report(&mut pending, HardwareComponent::Gpu, "second".to_string());
+        assert_eq!(
+            pending.take(),
+            Some((HardwareComponent::Spu, "first".to_string()))
+        );
+
+        report(&mut pending, HardwareComponent::Gpu, "third".to_string());
+        assert_eq!(pending, Some((HardwareComponent::Gpu, "third".to_string())));
+    }
+}